@@ -0,0 +1,396 @@
+//! Terminal client for the time tracker.
+//!
+//! `orbix-cli login you@example.com` stores the API token in the user's
+//! config directory; every other command uses it.
+
+use std::{io::Write, path::PathBuf};
+
+use anyhow::{anyhow, bail, Context, Result};
+use chrono::{DateTime, Datelike, Duration, NaiveDate, SecondsFormat, TimeZone, Utc};
+use chrono_tz::Tz;
+use clap::{Parser, Subcommand};
+use reqwest::{Method, RequestBuilder, StatusCode};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+use orbix_backend::models::{
+    AuthResponse, CreateTimeEntryRequest, DayOfWeek, LoginRequest, Project, StopTimeEntryRequest,
+    ReportTotals, SummaryReport, TimeEntry, UserSettings,
+};
+
+const DEFAULT_SERVER: &str = "http://localhost:5000";
+
+#[derive(Parser)]
+#[command(name = "orbix-cli", about = "Track time against the Orbix API from the terminal")]
+struct Cli {
+    /// API base URL, overriding the one saved at login
+    #[arg(long, env = "ORBIX_API_URL", global = true)]
+    server: Option<String>,
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Log in and store the token locally
+    Login { email: String },
+    /// Forget the stored token
+    Logout,
+    /// Start a timer on a project (name or ID)
+    Start {
+        project: String,
+        #[arg(required = true, num_args = 1..)]
+        description: Vec<String>,
+        /// Stop the running timer first instead of refusing
+        #[arg(long)]
+        switch: bool,
+    },
+    /// Stop the running timer
+    Stop,
+    /// Show the running timer
+    Status,
+    /// List today's entries
+    Log,
+    /// Show hours per day for a period
+    Report {
+        /// The current week, per your week-start setting
+        #[arg(long)]
+        week: bool,
+    },
+}
+
+/// Stored in `<config dir>/orbix/cli.json`.
+#[derive(Serialize, Deserialize, Default)]
+struct Credentials {
+    server: String,
+    token: Option<String>,
+    email: Option<String>,
+}
+
+impl Credentials {
+    fn path() -> Result<PathBuf> {
+        Ok(dirs::config_dir()
+            .ok_or(anyhow!("No config directory on this system"))?
+            .join("orbix")
+            .join("cli.json"))
+    }
+
+    fn load() -> Result<Self> {
+        let path = Self::path()?;
+        if !path.exists() {
+            return Ok(Credentials { server: DEFAULT_SERVER.to_string(), ..Default::default() });
+        }
+        let text = std::fs::read_to_string(&path)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        Ok(serde_json::from_str(&text)?)
+    }
+
+    fn save(&self) -> Result<()> {
+        let path = Self::path()?;
+        std::fs::create_dir_all(path.parent().unwrap())?;
+        let mut options = std::fs::OpenOptions::new();
+        options.write(true).create(true).truncate(true);
+        #[cfg(unix)]
+        std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+        let mut file = options
+            .open(&path)
+            .with_context(|| format!("Failed to write {}", path.display()))?;
+        file.write_all(serde_json::to_string_pretty(self)?.as_bytes())?;
+        Ok(())
+    }
+}
+
+struct Api {
+    http: reqwest::Client,
+    server: String,
+    token: Option<String>,
+}
+
+impl Api {
+    fn request(&self, method: Method, path: &str) -> Result<RequestBuilder> {
+        let token = self.token.as_ref().ok_or(anyhow!("Not logged in; run `orbix-cli login`"))?;
+        Ok(self
+            .http
+            .request(method, format!("{}{}", self.server.trim_end_matches('/'), path))
+            .bearer_auth(token))
+    }
+
+    async fn send<T: DeserializeOwned>(request: RequestBuilder) -> Result<T> {
+        let response = request.send().await.context("Could not reach the API")?;
+        let status = response.status();
+        if !status.is_success() {
+            #[derive(Deserialize)]
+            struct ErrorBody {
+                error: String,
+            }
+            let message = response
+                .json::<ErrorBody>()
+                .await
+                .map(|body| body.error)
+                .unwrap_or_else(|_| status.to_string());
+            if status == StatusCode::UNAUTHORIZED {
+                bail!("{} (try `orbix-cli login` again)", message);
+            }
+            bail!("{}", message);
+        }
+        Ok(response.json().await?)
+    }
+
+    async fn get<T: DeserializeOwned>(&self, path: &str, query: &[(&str, String)]) -> Result<T> {
+        Self::send(self.request(Method::GET, path)?.query(query)).await
+    }
+
+    async fn post<T: DeserializeOwned>(&self, path: &str, body: &impl Serialize) -> Result<T> {
+        Self::send(self.request(Method::POST, path)?.json(body)).await
+    }
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let cli = Cli::parse();
+    let mut credentials = Credentials::load()?;
+    if let Some(server) = cli.server {
+        credentials.server = server;
+    }
+    let api = Api {
+        http: reqwest::Client::new(),
+        server: credentials.server.clone(),
+        token: credentials.token.clone(),
+    };
+
+    match cli.command {
+        Command::Login { email } => {
+            let password = rpassword::prompt_password("Password: ")?;
+            let request = api
+                .http
+                .post(format!("{}/api/auth/login", api.server.trim_end_matches('/')))
+                .json(&LoginRequest { email: email.clone(), password });
+            let auth: AuthResponse = Api::send(request).await?;
+            credentials.token = Some(auth.token);
+            credentials.email = Some(email);
+            credentials.save()?;
+            println!("Logged in as {}", auth.user.name);
+        }
+        Command::Logout => {
+            credentials.token = None;
+            credentials.save()?;
+            println!("Logged out");
+        }
+        Command::Start { project, description, switch } => {
+            let project = find_project(&api, &project).await?;
+            let request = CreateTimeEntryRequest {
+                project_id: project.id.map(|id| id.to_hex()),
+                task_id: None,
+                tags: None,
+                description: description.join(" "),
+                start_time: Utc::now(),
+                is_billable: None,
+                hourly_rate: None,
+                stop_running: Some(switch),
+            };
+            let entry: TimeEntry = api.post("/api/time-tracking", &request).await?;
+            let timezone = user_timezone(&api).await?;
+            println!(
+                "Started \"{}\" on {} at {}",
+                entry.description,
+                project.name,
+                clock(entry.start_time, timezone)
+            );
+        }
+        Command::Stop => {
+            let running = running_entry(&api).await?.ok_or(anyhow!("No timer is running"))?;
+            let path = format!("/api/time-tracking/{}/stop", running.id.unwrap().to_hex());
+            let entry: TimeEntry = api.post(&path, &StopTimeEntryRequest { end_time: Utc::now() }).await?;
+            println!(
+                "Stopped \"{}\" after {}",
+                entry.description,
+                format_duration(entry.duration.unwrap_or(0))
+            );
+        }
+        Command::Status => match running_entry(&api).await? {
+            Some(entry) => {
+                let projects = projects(&api).await?;
+                let timezone = user_timezone(&api).await?;
+                println!(
+                    "Running: \"{}\" on {} since {} ({})",
+                    entry.description,
+                    project_name(&projects, &entry),
+                    clock(entry.start_time, timezone),
+                    format_duration((Utc::now() - entry.start_time).num_seconds())
+                );
+            }
+            None => println!("No timer is running"),
+        },
+        Command::Log => {
+            let settings: UserSettings = api.get("/api/settings", &[]).await?;
+            let timezone = timezone(&settings);
+            let today = Utc::now().with_timezone(&timezone).date_naive();
+            let (from, to) = (day_start(timezone, today), day_start(timezone, today + Duration::days(1)));
+
+            let entries: Vec<TimeEntry> = api
+                .get(
+                    "/api/time-tracking",
+                    &[("from", rfc3339(from)), ("to", rfc3339(to)), ("order", "asc".to_string())],
+                )
+                .await?;
+            let projects = projects(&api).await?;
+
+            let mut total = 0;
+            for entry in &entries {
+                let seconds = entry
+                    .duration
+                    .unwrap_or_else(|| (Utc::now() - entry.start_time).num_seconds());
+                total += seconds;
+                println!(
+                    "{}-{}  {:>8}  {:<20}  {}",
+                    clock(entry.start_time, timezone),
+                    entry.end_time.map(|end| clock(end, timezone)).unwrap_or_else(|| "now  ".to_string()),
+                    format_duration(seconds),
+                    project_name(&projects, entry),
+                    entry.description
+                );
+            }
+            if entries.is_empty() {
+                println!("Nothing logged today");
+            } else {
+                println!("{:>20}", format_duration(total));
+            }
+        }
+        Command::Report { week } => {
+            if !week {
+                bail!("Only `report --week` is supported");
+            }
+            let settings: UserSettings = api.get("/api/settings", &[]).await?;
+            let timezone = timezone(&settings);
+            let today = Utc::now().with_timezone(&timezone).date_naive();
+            let week_start = start_of_week(today, settings.week_start.unwrap_or(DayOfWeek::Monday));
+            let (from, to) = (
+                day_start(timezone, week_start),
+                day_start(timezone, week_start + Duration::days(7)),
+            );
+
+            let report: SummaryReport = api
+                .get(
+                    "/api/reports/time/summary",
+                    &[("from", rfc3339(from)), ("to", rfc3339(to)), ("group_by", "day".to_string())],
+                )
+                .await?;
+
+            println!("Week of {} ({})", week_start, report.timezone);
+            for row in &report.rows {
+                println!(
+                    "{:<12} {:>7.2} h  {:>7.2} billable  {}",
+                    row.label, row.totals.hours, row.totals.billable_hours, amounts(&row.totals)
+                );
+            }
+            println!(
+                "{:<12} {:>7.2} h  {:>7.2} billable  {}",
+                "Total",
+                report.totals.hours,
+                report.totals.billable_hours,
+                amounts(&report.totals)
+            );
+        }
+    }
+
+    Ok(())
+}
+
+/// Billable amounts such as `120.00 EUR, 80.00 USD`.
+fn amounts(totals: &ReportTotals) -> String {
+    totals
+        .billable_amounts
+        .iter()
+        .map(|amount| format!("{:.2} {}", amount.amount, amount.currency))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+async fn running_entry(api: &Api) -> Result<Option<TimeEntry>> {
+    api.get("/api/time-tracking/current", &[]).await
+}
+
+async fn projects(api: &Api) -> Result<Vec<Project>> {
+    api.get("/api/projects", &[]).await
+}
+
+/// Matches a project by ID, exact name or unique name prefix, ignoring case.
+async fn find_project(api: &Api, wanted: &str) -> Result<Project> {
+    let projects = projects(api).await?;
+    let wanted_lower = wanted.to_lowercase();
+
+    if let Some(project) = projects.iter().find(|p| {
+        p.id.is_some_and(|id| id.to_hex() == wanted) || p.name.to_lowercase() == wanted_lower
+    }) {
+        return Ok(project.clone());
+    }
+
+    let matches: Vec<&Project> = projects
+        .iter()
+        .filter(|p| p.name.to_lowercase().starts_with(&wanted_lower))
+        .collect();
+    match matches.as_slice() {
+        [project] => Ok((*project).clone()),
+        [] => bail!("No project matches \"{}\"", wanted),
+        _ => bail!(
+            "\"{}\" matches several projects: {}",
+            wanted,
+            matches.iter().map(|p| p.name.as_str()).collect::<Vec<_>>().join(", ")
+        ),
+    }
+}
+
+fn project_name(projects: &[Project], entry: &TimeEntry) -> String {
+    entry
+        .project_id
+        .and_then(|id| projects.iter().find(|p| p.id == Some(id)))
+        .map(|p| p.name.clone())
+        .unwrap_or_else(|| "-".to_string())
+}
+
+fn timezone(settings: &UserSettings) -> Tz {
+    settings.timezone.as_deref().and_then(|name| name.parse().ok()).unwrap_or(Tz::UTC)
+}
+
+async fn user_timezone(api: &Api) -> Result<Tz> {
+    let settings: UserSettings = api.get("/api/settings", &[]).await?;
+    Ok(timezone(&settings))
+}
+
+/// Local midnight, or an hour later where a DST change skips it, as the
+/// server counts days.
+fn day_start(timezone: Tz, date: NaiveDate) -> DateTime<Utc> {
+    let midnight = date.and_hms_opt(0, 0, 0).unwrap();
+    timezone
+        .from_local_datetime(&midnight)
+        .earliest()
+        .or_else(|| timezone.from_local_datetime(&(midnight + Duration::hours(1))).earliest())
+        .map(|time| time.with_timezone(&Utc))
+        .unwrap_or_else(|| midnight.and_utc())
+}
+
+fn start_of_week(date: NaiveDate, week_start: DayOfWeek) -> NaiveDate {
+    let first = match week_start {
+        DayOfWeek::Monday => 0,
+        DayOfWeek::Tuesday => 1,
+        DayOfWeek::Wednesday => 2,
+        DayOfWeek::Thursday => 3,
+        DayOfWeek::Friday => 4,
+        DayOfWeek::Saturday => 5,
+        DayOfWeek::Sunday => 6,
+    };
+    let offset = (date.weekday().num_days_from_monday() + 7 - first) % 7;
+    date - Duration::days(offset as i64)
+}
+
+fn rfc3339(time: DateTime<Utc>) -> String {
+    time.to_rfc3339_opts(SecondsFormat::Secs, true)
+}
+
+/// Wall-clock time in the user's timezone, the one days are counted in.
+fn clock(time: DateTime<Utc>, timezone: Tz) -> String {
+    time.with_timezone(&timezone).format("%H:%M").to_string()
+}
+
+fn format_duration(seconds: i64) -> String {
+    format!("{}:{:02}", seconds / 3600, seconds % 3600 / 60)
+}
@@ -1,4 +1,8 @@
-use mongodb::{Client, Database as MongoDatabase, Collection};
+use mongodb::{
+    bson::{doc, Document},
+    options::IndexOptions,
+    Client, Collection, Database as MongoDatabase, IndexModel,
+};
 use anyhow::Result;
 
 #[derive(Clone)]
@@ -17,6 +21,80 @@ impl Database {
         Ok(Database { client, db })
     }
 
+    pub async fn ensure_indexes(&self) -> Result<()> {
+        // At most one running timer (no end_time) per user
+        let running_timer = IndexModel::builder()
+            .keys(doc! { "user_id": 1 })
+            .options(
+                IndexOptions::builder()
+                    .name("one_running_timer_per_user".to_string())
+                    .unique(true)
+                    .partial_filter_expression(doc! { "end_time": { "$type": "null" } })
+                    .build(),
+            )
+            .build();
+        self.time_entries().create_index(running_timer, None).await?;
+
+        // One submission per user and week; rejected ones are resubmitted in place
+        let submission_week = IndexModel::builder()
+            .keys(doc! { "user_id": 1, "week_start": 1 })
+            .options(
+                IndexOptions::builder()
+                    .name("one_submission_per_week".to_string())
+                    .unique(true)
+                    .build(),
+            )
+            .build();
+        self.timesheet_submissions().create_index(submission_week, None).await?;
+
+        // Tag names are unique per user
+        let tag_name = IndexModel::builder()
+            .keys(doc! { "user_id": 1, "name": 1 })
+            .options(
+                IndexOptions::builder()
+                    .name("one_tag_name_per_user".to_string())
+                    .unique(true)
+                    .build(),
+            )
+            .build();
+        self.tags().create_index(tag_name, None).await?;
+
+        Ok(())
+    }
+
+    /// Converts timestamps written as RFC 3339 strings by older versions
+    /// into BSON dates, so range filters and sorting see them.
+    pub async fn migrate_timestamps(&self) -> Result<()> {
+        const FIELDS: &[(&str, &[&str])] = &[
+            ("time_entries", &["start_time", "end_time", "created_at", "updated_at"]),
+            ("invoices", &["date", "due_date", "paid_at", "created_at", "updated_at"]),
+            ("clients", &["archived_at", "created_at", "updated_at"]),
+            ("projects", &["start_date", "end_date", "archived_at", "created_at", "updated_at"]),
+            ("contracts", &["start_date", "end_date", "signed_date", "created_at", "updated_at"]),
+            ("retainer_transactions", &["date", "created_at"]),
+            ("tags", &["created_at", "updated_at"]),
+            ("tasks", &["created_at", "updated_at"]),
+            ("users", &["created_at", "updated_at"]),
+            ("resumes", &["created_at", "updated_at"]),
+        ];
+
+        for (collection, fields) in FIELDS {
+            let collection = self.db.collection::<Document>(collection);
+            for field in *fields {
+                let path = format!("${}", field);
+                collection
+                    .update_many(
+                        doc! { *field: { "$type": "string" } },
+                        vec![doc! { "$set": { *field: { "$toDate": path } } }],
+                        None,
+                    )
+                    .await?;
+            }
+        }
+
+        Ok(())
+    }
+
     pub fn users(&self) -> Collection<crate::models::User> {
         self.db.collection("users")
     }
@@ -25,6 +103,14 @@ impl Database {
         self.db.collection("clients")
     }
 
+    pub fn client_contacts(&self) -> Collection<crate::models::ClientContact> {
+        self.db.collection("client_contacts")
+    }
+
+    pub fn client_merges(&self) -> Collection<crate::models::ClientMerge> {
+        self.db.collection("client_merges")
+    }
+
     pub fn invoices(&self) -> Collection<crate::models::Invoice> {
         self.db.collection("invoices")
     }
@@ -44,4 +130,20 @@ impl Database {
     pub fn resumes(&self) -> Collection<crate::models::Resume> {
         self.db.collection("resumes")
     }
+
+    pub fn tags(&self) -> Collection<crate::models::Tag> {
+        self.db.collection("tags")
+    }
+
+    pub fn tasks(&self) -> Collection<crate::models::Task> {
+        self.db.collection("tasks")
+    }
+
+    pub fn timesheet_submissions(&self) -> Collection<crate::models::TimesheetSubmission> {
+        self.db.collection("timesheet_submissions")
+    }
+
+    pub fn retainer_transactions(&self) -> Collection<crate::models::RetainerTransaction> {
+        self.db.collection("retainer_transactions")
+    }
 }
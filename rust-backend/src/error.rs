@@ -36,6 +36,18 @@ impl From<mongodb::error::Error> for AppError {
     }
 }
 
+/// Whether `err` is a unique index violation (server error code 11000).
+pub fn is_duplicate_key(err: &mongodb::error::Error) -> bool {
+    match err.kind.as_ref() {
+        mongodb::error::ErrorKind::Write(mongodb::error::WriteFailure::WriteError(write_error)) => {
+            write_error.code == 11000
+        }
+        // find_one_and_update reports it as a command error
+        mongodb::error::ErrorKind::Command(command_error) => command_error.code == 11000,
+        _ => false,
+    }
+}
+
 impl From<bcrypt::BcryptError> for AppError {
     fn from(err: bcrypt::BcryptError) -> Self {
         AppError::InternalError(err.to_string())
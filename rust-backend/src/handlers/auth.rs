@@ -10,7 +10,7 @@ use jsonwebtoken::{encode, Header, EncodingKey};
 use mongodb::bson::doc;
 
 use crate::{
-    models::{User, CreateUserRequest, LoginRequest, AuthResponse},
+    models::{User, UserSettings, CreateUserRequest, LoginRequest, AuthResponse},
     middleware::auth::Claims,
     error::{AppError, Result},
     AppState,
@@ -44,6 +44,8 @@ async fn register(
         password: hashed_password,
         name: payload.name,
         avatar: None,
+        settings: UserSettings::default(),
+        calendar_token: None,
         created_at: Utc::now(),
         updated_at: Utc::now(),
     };
@@ -0,0 +1,194 @@
+use std::collections::HashMap;
+
+use axum::{
+    extract::{Path, State, Extension},
+    http::header,
+    response::{IntoResponse, Json, Response},
+    routing::{get, post},
+    Router, middleware,
+};
+use chrono::{Duration, Utc};
+use mongodb::{
+    bson::{doc, oid::ObjectId, Bson},
+    options::FindOptions,
+};
+use serde_json::{json, Value};
+use uuid::Uuid;
+
+use crate::{
+    models::{
+        CalendarFeedResponse, CalendarRule, ImportCalendarRequest, ImportCalendarResponse,
+        SaveCalendarRulesRequest,
+    },
+    middleware::{auth_middleware, AuthUser},
+    error::{AppError, Result},
+    services::{ical, settings::user_settings},
+    AppState,
+};
+
+pub fn routes() -> Router<AppState> {
+    Router::new()
+        .route("/feed", post(create_feed).delete(delete_feed))
+        .route("/rules", get(get_rules).put(save_rules))
+        .route("/import", post(import_calendar))
+        .route_layer(middleware::from_fn(auth_middleware))
+        // Calendar apps can't log in; the secret token in the path is the credential
+        .route("/feed/:token", get(calendar_feed))
+}
+
+fn feed_url(token: &str) -> String {
+    format!("/api/calendar/feed/{}.ics", token)
+}
+
+/// Creates the feed URL, or replaces it so the previous one stops working.
+async fn create_feed(
+    State(state): State<AppState>,
+    Extension(auth_user): Extension<AuthUser>,
+) -> Result<Json<CalendarFeedResponse>> {
+    let token = Uuid::new_v4().simple().to_string();
+
+    state
+        .db
+        .users()
+        .update_one(
+            doc! { "_id": auth_user.user_id },
+            doc! { "$set": { "calendar_token": &token, "updated_at": Utc::now() } },
+            None,
+        )
+        .await?;
+
+    Ok(Json(CalendarFeedResponse { url: feed_url(&token) }))
+}
+
+async fn delete_feed(
+    State(state): State<AppState>,
+    Extension(auth_user): Extension<AuthUser>,
+) -> Result<Json<Value>> {
+    state
+        .db
+        .users()
+        .update_one(
+            doc! { "_id": auth_user.user_id },
+            doc! {
+                "$unset": { "calendar_token": "" },
+                "$set": { "updated_at": Utc::now() },
+            },
+            None,
+        )
+        .await?;
+
+    Ok(Json(json!({"message": "Calendar feed disabled"})))
+}
+
+async fn get_rules(
+    State(state): State<AppState>,
+    Extension(auth_user): Extension<AuthUser>,
+) -> Result<Json<Vec<CalendarRule>>> {
+    Ok(Json(user_settings(&state.db, auth_user.user_id).await?.calendar_rules))
+}
+
+/// Replaces the keyword rules. Order matters: the first match wins.
+async fn save_rules(
+    State(state): State<AppState>,
+    Extension(auth_user): Extension<AuthUser>,
+    Json(payload): Json<SaveCalendarRulesRequest>,
+) -> Result<Json<Vec<CalendarRule>>> {
+    let mut rules = Vec::new();
+    for rule in payload.rules {
+        let keyword = rule.keyword.trim().to_string();
+        if keyword.is_empty() {
+            return Err(AppError::BadRequest("Keyword cannot be empty".to_string()));
+        }
+        let project_id = ObjectId::parse_str(&rule.project_id)
+            .map_err(|_| AppError::BadRequest("Invalid project ID".to_string()))?;
+        state
+            .db
+            .projects()
+            .find_one(doc! { "_id": project_id, "user_id": auth_user.user_id }, None)
+            .await?
+            .ok_or(AppError::NotFound("Project not found".to_string()))?;
+
+        rules.push(CalendarRule { keyword, project_id });
+    }
+
+    let stored: Vec<Bson> = rules
+        .iter()
+        .map(|rule| Bson::Document(doc! { "keyword": &rule.keyword, "project_id": rule.project_id }))
+        .collect();
+    state
+        .db
+        .users()
+        .update_one(
+            doc! { "_id": auth_user.user_id },
+            doc! { "$set": { "settings.calendar_rules": stored, "updated_at": Utc::now() } },
+            None,
+        )
+        .await?;
+
+    Ok(Json(rules))
+}
+
+/// Imports events from an `.ics` file as draft entries. Drafts stay out of
+/// lists, reports and invoices until confirmed with `PUT /api/time-tracking/:id`
+/// and `"draft": false`.
+async fn import_calendar(
+    State(state): State<AppState>,
+    Extension(auth_user): Extension<AuthUser>,
+    Json(payload): Json<ImportCalendarRequest>,
+) -> Result<Json<ImportCalendarResponse>> {
+    let response = ical::import_calendar(&state.db, auth_user.user_id, payload).await?;
+    if !response.dry_run && response.imported > 0 {
+        state.timer_events.publish_resync(auth_user.user_id);
+    }
+
+    Ok(Json(response))
+}
+
+/// The last year of finished, confirmed entries.
+async fn calendar_feed(
+    State(state): State<AppState>,
+    Path(token): Path<String>,
+) -> Result<Response> {
+    let token = token.strip_suffix(".ics").unwrap_or(&token);
+    let user = state
+        .db
+        .users()
+        .find_one(doc! { "calendar_token": token }, None)
+        .await?
+        .ok_or(AppError::NotFound("Calendar not found".to_string()))?;
+    let user_id = user.id.unwrap();
+
+    let options = FindOptions::builder().sort(doc! { "start_time": 1 }).build();
+    let mut cursor = state
+        .db
+        .time_entries()
+        .find(
+            doc! {
+                "user_id": user_id,
+                "end_time": { "$ne": null },
+                "draft": { "$ne": true },
+                "start_time": { "$gte": Utc::now() - Duration::days(365) },
+            },
+            options,
+        )
+        .await?;
+    let mut entries = Vec::new();
+    while cursor.advance().await? {
+        entries.push(cursor.deserialize_current()?);
+    }
+
+    let mut projects = HashMap::new();
+    let mut cursor = state.db.projects().find(doc! { "user_id": user_id }, None).await?;
+    while cursor.advance().await? {
+        let project = cursor.deserialize_current()?;
+        if let Some(id) = project.id {
+            projects.insert(id, project.name);
+        }
+    }
+
+    Ok((
+        [(header::CONTENT_TYPE, "text/calendar; charset=utf-8")],
+        ical::render_feed(&entries, &projects),
+    )
+        .into_response())
+}
@@ -1,35 +1,68 @@
 use axum::{
-    extract::{Path, State, Extension},
-    response::Json,
-    routing::{get, post, put, delete},
+    extract::{Path, Query, State, Extension},
+    http::{header, StatusCode},
+    response::{IntoResponse, Json, Response},
+    routing::{get, post, put},
     Router, middleware,
 };
 use chrono::Utc;
-use mongodb::bson::{doc, oid::ObjectId};
+use mongodb::{
+    bson::{doc, oid::ObjectId, Bson},
+    options::FindOptions,
+};
 
 use crate::{
-    models::{Client, CreateClientRequest, UpdateClientRequest},
+    models::{
+        Client, CreateClientRequest, UpdateClientRequest, RecordRetainerDepositRequest,
+        RetainerLedgerResponse, RetainerTransaction, DeleteQuery, DependentsAction, ClientContact,
+        CreateContactRequest, UpdateContactRequest, Recipients, RecipientsQuery,
+        TaxIdValidation, ValidateTaxIdRequest, ClientExportFormat, ClientExportQuery,
+        ImportClientsRequest, ImportClientsResponse, ImportVcardRequest, ClientMerge,
+        DuplicateClientsQuery, DuplicateGroup, MergeClientsRequest, ClientListQuery, ClientMetrics,
+    },
     middleware::{auth_middleware, AuthUser},
     error::{AppError, Result},
+    services::{
+        billing::{validate_currency, validate_hourly_rate, validate_rounding_rule}, client_csv, client_merge, client_metrics, contacts, dependents,
+        retainer, tax, vcard,
+    },
     AppState,
 };
 
 pub fn routes() -> Router<AppState> {
     Router::new()
         .route("/", get(list_clients).post(create_client))
+        .route("/tax-id/validate", post(validate_tax_id))
+        .route("/import", post(import_clients))
+        .route("/import/vcard", post(import_vcard))
+        .route("/export", get(export_clients))
+        .route("/duplicates", get(find_duplicates))
+        .route("/merges", get(list_merges))
+        .route("/metrics", get(list_client_metrics))
         .route("/:id", get(get_client).put(update_client).delete(delete_client))
+        .route("/:id/contacts", get(list_contacts).post(create_contact))
+        .route("/:id/contacts/:contact_id", put(update_contact).delete(delete_contact))
+        .route("/:id/recipients", get(get_recipients))
+        .route("/:id/vcard", get(export_client_vcard))
+        .route("/:id/merge", post(merge_clients))
+        .route("/:id/archive", post(archive_client))
+        .route("/:id/unarchive", post(unarchive_client))
+        .route("/:id/metrics", get(get_client_metrics))
+        .route("/:id/retainer", get(get_retainer_ledger))
+        .route("/:id/retainer/deposits", post(record_retainer_deposit))
         .route_layer(middleware::from_fn(auth_middleware))
 }
 
 async fn list_clients(
     State(state): State<AppState>,
     Extension(auth_user): Extension<AuthUser>,
+    Query(query): Query<ClientListQuery>,
 ) -> Result<Json<Vec<Client>>> {
-    let mut cursor = state
-        .db
-        .clients()
-        .find(doc! { "user_id": auth_user.user_id }, None)
-        .await?;
+    let mut filter = doc! { "user_id": auth_user.user_id };
+    if !query.include_archived.unwrap_or(false) {
+        filter.insert("archived_at", Bson::Null);
+    }
+    let mut cursor = state.db.clients().find(filter, None).await?;
 
     let mut clients = Vec::new();
     while cursor.advance().await? {
@@ -44,6 +77,20 @@ async fn create_client(
     Extension(auth_user): Extension<AuthUser>,
     Json(payload): Json<CreateClientRequest>,
 ) -> Result<Json<Client>> {
+    if let Some(rule) = &payload.time_rounding {
+        validate_rounding_rule(rule)?;
+    }
+    validate_hourly_rate(payload.default_hourly_rate)?;
+    let currency = payload.currency.as_deref().map(validate_currency).transpose()?.flatten();
+    if !payload.email.is_empty() {
+        contacts::validate_email(&payload.email)?;
+    }
+    let billing_address = payload.billing_address.map(tax::validate_address).transpose()?;
+    let country = payload
+        .country
+        .or_else(|| billing_address.as_ref().map(|address| address.country.clone()));
+    let identity = tax::tax_identity(country.as_deref(), payload.tax_id_type, payload.tax_id.as_deref())?;
+
     let client = Client {
         id: None,
         user_id: auth_user.user_id,
@@ -52,7 +99,16 @@ async fn create_client(
         phone: payload.phone,
         company: payload.company,
         address: payload.address,
+        billing_address,
+        country: identity.country,
+        tax_id_type: identity.tax_id_type,
+        tax_id: identity.tax_id,
         notes: payload.notes,
+        retainer_balance: 0.0,
+        time_rounding: payload.time_rounding,
+        default_hourly_rate: payload.default_hourly_rate,
+        currency,
+        archived_at: None,
         created_at: Utc::now(),
         updated_at: Utc::now(),
     };
@@ -61,9 +117,236 @@ async fn create_client(
     let mut client_with_id = client;
     client_with_id.id = Some(result.inserted_id.as_object_id().unwrap());
 
+    if !client_with_id.email.is_empty() {
+        state
+            .db
+            .client_contacts()
+            .insert_one(contacts::primary_from(&client_with_id), None)
+            .await?;
+    }
+
     Ok(Json(client_with_id))
 }
 
+async fn import_clients(
+    State(state): State<AppState>,
+    Extension(auth_user): Extension<AuthUser>,
+    Json(payload): Json<ImportClientsRequest>,
+) -> Result<Json<ImportClientsResponse>> {
+    Ok(Json(client_csv::import_csv(&state.db, auth_user.user_id, payload).await?))
+}
+
+async fn import_vcard(
+    State(state): State<AppState>,
+    Extension(auth_user): Extension<AuthUser>,
+    Json(payload): Json<ImportVcardRequest>,
+) -> Result<Json<ImportClientsResponse>> {
+    Ok(Json(vcard::import_cards(&state.db, auth_user.user_id, payload).await?))
+}
+
+/// The whole client list as CSV, or every contact as vCards.
+async fn export_clients(
+    State(state): State<AppState>,
+    Extension(auth_user): Extension<AuthUser>,
+    Query(query): Query<ClientExportQuery>,
+) -> Result<Response> {
+    Ok(match query.format.unwrap_or_default() {
+        ClientExportFormat::Csv => attachment(
+            "text/csv",
+            "clients.csv",
+            client_csv::export_csv(&state.db, auth_user.user_id).await?,
+        ),
+        ClientExportFormat::Vcard => {
+            let mut cursor = state
+                .db
+                .clients()
+                .find(doc! { "user_id": auth_user.user_id }, None)
+                .await?;
+            let mut clients = Vec::new();
+            while cursor.advance().await? {
+                clients.push(cursor.deserialize_current()?);
+            }
+            let cards = vcard::with_contacts(&state.db, clients).await?;
+            attachment("text/vcard; charset=utf-8", "contacts.vcf", vcard::render_cards(&cards).into_bytes())
+        }
+    })
+}
+
+async fn export_client_vcard(
+    State(state): State<AppState>,
+    Extension(auth_user): Extension<AuthUser>,
+    Path(id): Path<String>,
+) -> Result<Response> {
+    let object_id = ObjectId::parse_str(&id)
+        .map_err(|_| AppError::BadRequest("Invalid ID".to_string()))?;
+
+    let client = state
+        .db
+        .clients()
+        .find_one(doc! { "_id": object_id, "user_id": auth_user.user_id }, None)
+        .await?
+        .ok_or(AppError::NotFound("Client not found".to_string()))?;
+    let filename = format!("{}.vcf", client.name.replace(|c: char| !c.is_alphanumeric(), "-"));
+    let cards = vcard::with_contacts(&state.db, vec![client]).await?;
+
+    Ok(attachment("text/vcard; charset=utf-8", &filename, vcard::render_cards(&cards).into_bytes()))
+}
+
+fn attachment(content_type: &'static str, filename: &str, body: Vec<u8>) -> Response {
+    (
+        [
+            (header::CONTENT_TYPE, content_type.to_string()),
+            (header::CONTENT_DISPOSITION, format!("attachment; filename=\"{}\"", filename)),
+        ],
+        body,
+    )
+        .into_response()
+}
+
+/// Metrics of every listed client; archived ones with `include_archived`.
+async fn list_client_metrics(
+    State(state): State<AppState>,
+    Extension(auth_user): Extension<AuthUser>,
+    Query(query): Query<ClientListQuery>,
+) -> Result<Json<Vec<ClientMetrics>>> {
+    let mut filter = doc! { "user_id": auth_user.user_id };
+    if !query.include_archived.unwrap_or(false) {
+        filter.insert("archived_at", Bson::Null);
+    }
+    let client_ids: Vec<ObjectId> = state
+        .db
+        .clients()
+        .distinct("_id", filter, None)
+        .await?
+        .into_iter()
+        .filter_map(|id| id.as_object_id())
+        .collect();
+
+    Ok(Json(client_metrics::client_metrics(&state.db, auth_user.user_id, &client_ids).await?))
+}
+
+async fn get_client_metrics(
+    State(state): State<AppState>,
+    Extension(auth_user): Extension<AuthUser>,
+    Path(id): Path<String>,
+) -> Result<Json<ClientMetrics>> {
+    let object_id = ObjectId::parse_str(&id)
+        .map_err(|_| AppError::BadRequest("Invalid ID".to_string()))?;
+
+    state
+        .db
+        .clients()
+        .find_one(doc! { "_id": object_id, "user_id": auth_user.user_id }, None)
+        .await?
+        .ok_or(AppError::NotFound("Client not found".to_string()))?;
+    let metrics = client_metrics::client_metrics(&state.db, auth_user.user_id, &[object_id])
+        .await?
+        .remove(0);
+
+    Ok(Json(metrics))
+}
+
+/// Archives the client and its projects. Archived clients are left out of
+/// lists and can't take new records, but keep everything they have.
+async fn archive_client(
+    State(state): State<AppState>,
+    Extension(auth_user): Extension<AuthUser>,
+    Path(id): Path<String>,
+) -> Result<Json<Client>> {
+    let object_id = ObjectId::parse_str(&id)
+        .map_err(|_| AppError::BadRequest("Invalid ID".to_string()))?;
+
+    dependents::archive_client(&state.db, auth_user.user_id, object_id).await?;
+    Ok(Json(find_client(&state, auth_user.user_id, object_id).await?))
+}
+
+/// Restores the client along with the projects archived with it.
+async fn unarchive_client(
+    State(state): State<AppState>,
+    Extension(auth_user): Extension<AuthUser>,
+    Path(id): Path<String>,
+) -> Result<Json<Client>> {
+    let object_id = ObjectId::parse_str(&id)
+        .map_err(|_| AppError::BadRequest("Invalid ID".to_string()))?;
+
+    dependents::unarchive_client(&state.db, auth_user.user_id, object_id).await?;
+    Ok(Json(find_client(&state, auth_user.user_id, object_id).await?))
+}
+
+async fn find_client(state: &AppState, user_id: ObjectId, id: ObjectId) -> Result<Client> {
+    state
+        .db
+        .clients()
+        .find_one(doc! { "_id": id, "user_id": user_id }, None)
+        .await?
+        .ok_or(AppError::NotFound("Client not found".to_string()))
+}
+
+async fn find_duplicates(
+    State(state): State<AppState>,
+    Extension(auth_user): Extension<AuthUser>,
+    Query(query): Query<DuplicateClientsQuery>,
+) -> Result<Json<Vec<DuplicateGroup>>> {
+    let min_similarity = query.min_similarity.unwrap_or(client_merge::DEFAULT_MIN_SIMILARITY);
+    if !(0.0..=1.0).contains(&min_similarity) {
+        return Err(AppError::BadRequest("min_similarity must be between 0 and 1".to_string()));
+    }
+
+    let groups = client_merge::find_duplicates(
+        &state.db,
+        auth_user.user_id,
+        min_similarity,
+        query.include_archived,
+    )
+    .await?;
+
+    Ok(Json(groups))
+}
+
+/// Merges the clients in `merge_ids` into this one and deletes them.
+async fn merge_clients(
+    State(state): State<AppState>,
+    Extension(auth_user): Extension<AuthUser>,
+    Path(id): Path<String>,
+    Json(payload): Json<MergeClientsRequest>,
+) -> Result<Json<ClientMerge>> {
+    Ok(Json(client_merge::merge(&state.db, auth_user.user_id, &id, &payload.merge_ids).await?))
+}
+
+async fn list_merges(
+    State(state): State<AppState>,
+    Extension(auth_user): Extension<AuthUser>,
+) -> Result<Json<Vec<ClientMerge>>> {
+    Ok(Json(client_merge::list_merges(&state.db, auth_user.user_id).await?))
+}
+
+/// Checks a tax ID offline without saving it anywhere.
+async fn validate_tax_id(
+    Json(payload): Json<ValidateTaxIdRequest>,
+) -> Result<Json<TaxIdValidation>> {
+    let validation = match tax::tax_identity(
+        payload.country.as_deref(),
+        payload.tax_id_type,
+        Some(&payload.tax_id),
+    ) {
+        Ok(identity) => TaxIdValidation {
+            valid: identity.tax_id.is_some(),
+            tax_id_type: identity.tax_id_type,
+            normalized: identity.tax_id,
+            error: None,
+        },
+        Err(AppError::BadRequest(error)) => TaxIdValidation {
+            valid: false,
+            tax_id_type: payload.tax_id_type,
+            normalized: None,
+            error: Some(error),
+        },
+        Err(err) => return Err(err),
+    };
+
+    Ok(Json(validation))
+}
+
 async fn get_client(
     State(state): State<AppState>,
     Extension(auth_user): Extension<AuthUser>,
@@ -96,10 +379,13 @@ async fn update_client(
     if let Some(name) = payload.name {
         update_doc.insert("name", name);
     }
-    if let Some(email) = payload.email {
+    if let Some(email) = &payload.email {
+        if !email.is_empty() {
+            contacts::validate_email(email)?;
+        }
         update_doc.insert("email", email);
     }
-    if let Some(phone) = payload.phone {
+    if let Some(phone) = &payload.phone {
         update_doc.insert("phone", phone);
     }
     if let Some(company) = payload.company {
@@ -111,6 +397,50 @@ async fn update_client(
     if let Some(notes) = payload.notes {
         update_doc.insert("notes", notes);
     }
+    if let Some(rule) = payload.time_rounding {
+        validate_rounding_rule(&rule)?;
+        update_doc.insert("time_rounding", bson::to_bson(&rule)?);
+    }
+    if let Some(rate) = payload.default_hourly_rate {
+        validate_hourly_rate(Some(rate))?;
+        update_doc.insert("default_hourly_rate", rate);
+    }
+    if let Some(currency) = &payload.currency {
+        update_doc.insert("currency", validate_currency(currency)?);
+    }
+
+    // Country and tax ID are checked together, against the stored values
+    // for whichever of them this update leaves alone
+    if payload.billing_address.is_some()
+        || payload.country.is_some()
+        || payload.tax_id_type.is_some()
+        || payload.tax_id.is_some()
+    {
+        let current = state
+            .db
+            .clients()
+            .find_one(doc! { "_id": object_id, "user_id": auth_user.user_id }, None)
+            .await?
+            .ok_or(AppError::NotFound("Client not found".to_string()))?;
+
+        let billing_address = payload.billing_address.map(tax::validate_address).transpose()?;
+        let country = payload
+            .country
+            .or_else(|| billing_address.as_ref().map(|address| address.country.clone()))
+            .or(current.country);
+        let tax_id_type = payload
+            .tax_id_type
+            .or(if payload.tax_id.is_some() { None } else { current.tax_id_type });
+        let tax_id = payload.tax_id.or(current.tax_id);
+        let identity = tax::tax_identity(country.as_deref(), tax_id_type, tax_id.as_deref())?;
+
+        if let Some(address) = billing_address {
+            update_doc.insert("billing_address", bson::to_bson(&address)?);
+        }
+        update_doc.insert("country", identity.country);
+        update_doc.insert("tax_id_type", bson::to_bson(&identity.tax_id_type)?);
+        update_doc.insert("tax_id", identity.tax_id);
+    }
 
     let client = state
         .db
@@ -123,17 +453,50 @@ async fn update_client(
         .await?
         .ok_or(AppError::NotFound("Client not found".to_string()))?;
 
+    // The client's email and phone are its primary contact's
+    contacts::sync_primary(&state.db, &client, payload.email.as_deref(), payload.phone.as_deref()).await?;
+
     Ok(Json(client))
 }
 
+/// Deletes a client nothing refers to. With `dependents=archive` the client
+/// is archived instead; with `dependents=reassign&reassign_to=<id>` its
+/// records move to another client first.
 async fn delete_client(
     State(state): State<AppState>,
     Extension(auth_user): Extension<AuthUser>,
     Path(id): Path<String>,
-) -> Result<Json<serde_json::Value>> {
+    Query(query): Query<DeleteQuery>,
+) -> Result<Response> {
     let object_id = ObjectId::parse_str(&id)
         .map_err(|_| AppError::BadRequest("Invalid ID".to_string()))?;
 
+    match query.dependents {
+        DependentsAction::Refuse => {
+            let dependents = dependents::client_dependents(&state.db, auth_user.user_id, object_id).await?;
+            if !dependents.is_empty() {
+                return Ok((
+                    StatusCode::CONFLICT,
+                    Json(serde_json::json!({
+                        "error": "Client is still referenced by other records",
+                        "dependents": dependents,
+                    })),
+                )
+                    .into_response());
+            }
+        }
+        DependentsAction::Archive => {
+            dependents::archive_client(&state.db, auth_user.user_id, object_id).await?;
+            return Ok(Json(serde_json::json!({ "message": "Client archived" })).into_response());
+        }
+        DependentsAction::Reassign => {
+            let target = query
+                .reassign_to
+                .ok_or(AppError::BadRequest("reassign_to is required".to_string()))?;
+            dependents::reassign_client(&state.db, auth_user.user_id, object_id, &target).await?;
+        }
+    }
+
     let result = state
         .db
         .clients()
@@ -144,5 +507,145 @@ async fn delete_client(
         return Err(AppError::NotFound("Client not found".to_string()));
     }
 
-    Ok(Json(serde_json::json!({ "message": "Client deleted" })))
+    // A reassigned client's contacts have already moved to the target
+    if !matches!(query.dependents, DependentsAction::Reassign) {
+        state
+            .db
+            .client_contacts()
+            .delete_many(doc! { "client_id": object_id, "user_id": auth_user.user_id }, None)
+            .await?;
+    }
+
+    Ok(Json(serde_json::json!({ "message": "Client deleted" })).into_response())
+}
+
+async fn list_contacts(
+    State(state): State<AppState>,
+    Extension(auth_user): Extension<AuthUser>,
+    Path(id): Path<String>,
+) -> Result<Json<Vec<ClientContact>>> {
+    let object_id = ObjectId::parse_str(&id)
+        .map_err(|_| AppError::BadRequest("Invalid ID".to_string()))?;
+
+    Ok(Json(contacts::list(&state.db, auth_user.user_id, object_id).await?))
+}
+
+async fn create_contact(
+    State(state): State<AppState>,
+    Extension(auth_user): Extension<AuthUser>,
+    Path(id): Path<String>,
+    Json(payload): Json<CreateContactRequest>,
+) -> Result<Json<ClientContact>> {
+    let object_id = ObjectId::parse_str(&id)
+        .map_err(|_| AppError::BadRequest("Invalid ID".to_string()))?;
+
+    Ok(Json(contacts::create(&state.db, auth_user.user_id, object_id, payload).await?))
+}
+
+async fn update_contact(
+    State(state): State<AppState>,
+    Extension(auth_user): Extension<AuthUser>,
+    Path((id, contact_id)): Path<(String, String)>,
+    Json(payload): Json<UpdateContactRequest>,
+) -> Result<Json<ClientContact>> {
+    let object_id = ObjectId::parse_str(&id)
+        .map_err(|_| AppError::BadRequest("Invalid ID".to_string()))?;
+    let contact_id = ObjectId::parse_str(&contact_id)
+        .map_err(|_| AppError::BadRequest("Invalid contact ID".to_string()))?;
+
+    let contact = contacts::update(&state.db, auth_user.user_id, object_id, contact_id, payload).await?;
+    Ok(Json(contact))
+}
+
+async fn delete_contact(
+    State(state): State<AppState>,
+    Extension(auth_user): Extension<AuthUser>,
+    Path((id, contact_id)): Path<(String, String)>,
+) -> Result<Json<serde_json::Value>> {
+    let object_id = ObjectId::parse_str(&id)
+        .map_err(|_| AppError::BadRequest("Invalid ID".to_string()))?;
+    let contact_id = ObjectId::parse_str(&contact_id)
+        .map_err(|_| AppError::BadRequest("Invalid contact ID".to_string()))?;
+
+    contacts::delete(&state.db, auth_user.user_id, object_id, contact_id).await?;
+    Ok(Json(serde_json::json!({ "message": "Contact deleted" })))
+}
+
+/// Who mail of the given purpose goes to, by contact role.
+async fn get_recipients(
+    State(state): State<AppState>,
+    Extension(auth_user): Extension<AuthUser>,
+    Path(id): Path<String>,
+    Query(query): Query<RecipientsQuery>,
+) -> Result<Json<Recipients>> {
+    let object_id = ObjectId::parse_str(&id)
+        .map_err(|_| AppError::BadRequest("Invalid ID".to_string()))?;
+
+    let client = state
+        .db
+        .clients()
+        .find_one(doc! { "_id": object_id, "user_id": auth_user.user_id }, None)
+        .await?
+        .ok_or(AppError::NotFound("Client not found".to_string()))?;
+
+    Ok(Json(contacts::recipients(&state.db, &client, query.purpose).await?))
+}
+
+async fn get_retainer_ledger(
+    State(state): State<AppState>,
+    Extension(auth_user): Extension<AuthUser>,
+    Path(id): Path<String>,
+) -> Result<Json<RetainerLedgerResponse>> {
+    let object_id = ObjectId::parse_str(&id)
+        .map_err(|_| AppError::BadRequest("Invalid ID".to_string()))?;
+
+    let client = state
+        .db
+        .clients()
+        .find_one(doc! { "_id": object_id, "user_id": auth_user.user_id }, None)
+        .await?
+        .ok_or(AppError::NotFound("Client not found".to_string()))?;
+
+    let options = FindOptions::builder()
+        .sort(doc! { "date": 1, "created_at": 1 })
+        .build();
+    let mut cursor = state
+        .db
+        .retainer_transactions()
+        .find(doc! { "client_id": object_id, "user_id": auth_user.user_id }, options)
+        .await?;
+
+    let mut transactions = Vec::new();
+    while cursor.advance().await? {
+        transactions.push(cursor.deserialize_current()?);
+    }
+
+    Ok(Json(RetainerLedgerResponse {
+        client_id: id,
+        balance: client.retainer_balance,
+        transactions,
+    }))
+}
+
+async fn record_retainer_deposit(
+    State(state): State<AppState>,
+    Extension(auth_user): Extension<AuthUser>,
+    Path(id): Path<String>,
+    Json(payload): Json<RecordRetainerDepositRequest>,
+) -> Result<Json<RetainerTransaction>> {
+    let object_id = ObjectId::parse_str(&id)
+        .map_err(|_| AppError::BadRequest("Invalid ID".to_string()))?;
+
+    let transaction = retainer::deposit(
+        &state.db,
+        auth_user.user_id,
+        object_id,
+        payload.amount,
+        payload.date.unwrap_or_else(Utc::now),
+        payload.reference,
+        payload.notes,
+    )
+    .await?;
+
+    Ok(Json(transaction))
 }
@@ -1,7 +1,7 @@
 use axum::{
     extract::{Path, State, Extension},
     response::Json,
-    routing::{get, post, put, delete},
+    routing::get,
     Router, middleware,
 };
 use chrono::Utc;
@@ -11,6 +11,7 @@ use crate::{
     models::{Contract, CreateContractRequest, UpdateContractRequest},
     middleware::{auth_middleware, AuthUser},
     error::{AppError, Result},
+    services::references::resolve_client,
     AppState,
 };
 
@@ -44,8 +45,10 @@ async fn create_contract(
     Extension(auth_user): Extension<AuthUser>,
     Json(payload): Json<CreateContractRequest>,
 ) -> Result<Json<Contract>> {
-    let client_id = ObjectId::parse_str(&payload.client_id)
-        .map_err(|_| AppError::BadRequest("Invalid client ID".to_string()))?;
+    let client_id = resolve_client(&state.db, auth_user.user_id, &payload.client_id)
+        .await?
+        .id
+        .unwrap();
 
     let contract = Contract {
         id: None,
@@ -99,6 +102,10 @@ async fn update_contract(
 
     let mut update_doc = doc! { "updated_at": Utc::now() };
     
+    if let Some(client_id) = payload.client_id {
+        let client = resolve_client(&state.db, auth_user.user_id, &client_id).await?;
+        update_doc.insert("client_id", client.id);
+    }
     if let Some(title) = payload.title {
         update_doc.insert("title", title);
     }
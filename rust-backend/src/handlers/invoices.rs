@@ -1,16 +1,17 @@
 use axum::{
     extract::{Path, State, Extension},
     response::Json,
-    routing::{get, post, put},
+    routing::get,
     Router, middleware,
 };
 use chrono::Utc;
-use mongodb::bson::{doc, oid::ObjectId};
+use mongodb::bson::{doc, oid::ObjectId, Bson};
 
 use crate::{
-    models::{Invoice, InvoiceStatus, CreateInvoiceRequest, UpdateInvoiceStatusRequest},
+    models::{Invoice, InvoiceItem, InvoiceStatus, CreateInvoiceRequest, UpdateInvoiceStatusRequest},
     middleware::{auth_middleware, AuthUser},
     error::{AppError, Result},
+    services::{billing, locks, references::resolve_client, retainer, settings::user_settings, tax},
     AppState,
 };
 
@@ -44,13 +45,61 @@ async fn create_invoice(
     Extension(auth_user): Extension<AuthUser>,
     Json(payload): Json<CreateInvoiceRequest>,
 ) -> Result<Json<Invoice>> {
-    let client_id = ObjectId::parse_str(&payload.client_id)
-        .map_err(|_| AppError::BadRequest("Invalid client ID".to_string()))?;
+    let client = resolve_client(&state.db, auth_user.user_id, &payload.client_id).await?;
+    let client_id = client.id.unwrap();
+    let settings = user_settings(&state.db, auth_user.user_id).await?;
+
+    let mut items = payload.items;
+
+    // Time entries are billed one line each and locked once the invoice exists
+    let entry_ids = match &payload.time_entry_ids {
+        Some(ids) if !ids.is_empty() => {
+            let (entry_ids, entry_items) =
+                locks::invoice_items(&state.db, auth_user.user_id, client_id, ids).await?;
+            items.extend(entry_items);
+            entry_ids
+        }
+        _ => Vec::new(),
+    };
+    // Under the reverse charge the client accounts for VAT, so none is charged
+    let reverse_charge = tax::reverse_charge_applies(&settings, &client);
+    let (tax, notes) = if reverse_charge {
+        let notes = match payload.notes {
+            Some(notes) if !notes.trim().is_empty() => format!("{}\n\n{}", notes, tax::REVERSE_CHARGE_NOTE),
+            _ => tax::REVERSE_CHARGE_NOTE.to_string(),
+        };
+        (0.0, Some(notes))
+    } else {
+        (payload.tax.unwrap_or(0.0), payload.notes)
+    };
+    let discount = payload.discount.unwrap_or(0.0);
+
+    // Retainer draw-downs appear as a negative line on the invoice. The
+    // balance is only debited once the invoice leaves draft, so an abandoned
+    // draft doesn't use up the retainer.
+    let retainer_applied = payload.apply_retainer.unwrap_or(0.0);
+    if retainer_applied < 0.0 {
+        return Err(AppError::BadRequest("Retainer amount cannot be negative".to_string()));
+    }
+    if retainer_applied > 0.0 {
+        let amount_due: f64 = items.iter().map(|item| item.amount).sum::<f64>() + tax - discount;
+        if retainer_applied > amount_due {
+            return Err(AppError::BadRequest(
+                "Retainer amount exceeds the invoice total".to_string(),
+            ));
+        }
+        retainer::ensure_available(&state.db, auth_user.user_id, client_id, retainer_applied).await?;
+
+        items.push(InvoiceItem {
+            description: "Retainer applied".to_string(),
+            quantity: 1.0,
+            rate: -retainer_applied,
+            amount: -retainer_applied,
+        });
+    }
 
     // Calculate totals
-    let subtotal: f64 = payload.items.iter().map(|item| item.amount).sum();
-    let tax = payload.tax.unwrap_or(0.0);
-    let discount = payload.discount.unwrap_or(0.0);
+    let subtotal: f64 = items.iter().map(|item| item.amount).sum();
     let total = subtotal + tax - discount;
 
     // Generate invoice number
@@ -68,15 +117,23 @@ async fn create_invoice(
         invoice_number,
         date: Utc::now(),
         due_date: payload.due_date,
-        items: payload.items,
+        items,
         subtotal,
         tax,
         discount,
+        retainer_applied,
         total,
-        currency: payload.currency.unwrap_or_else(|| "USD".to_string()),
+        reverse_charge,
+        seller_tax_id: settings.business_tax_id,
+        client_tax_id: client.tax_id,
+        currency: payload
+            .currency
+            .or(client.currency)
+            .unwrap_or_else(|| billing::DEFAULT_CURRENCY.to_string()),
         status: InvoiceStatus::Draft,
-        notes: payload.notes,
+        notes,
         payment_terms: payload.payment_terms,
+        paid_at: None,
         created_at: Utc::now(),
         updated_at: Utc::now(),
     };
@@ -84,6 +141,17 @@ async fn create_invoice(
     let result = state.db.invoices().insert_one(&invoice, None).await?;
     let mut invoice_with_id = invoice;
     invoice_with_id.id = Some(result.inserted_id.as_object_id().unwrap());
+    let invoice_id = invoice_with_id.id.unwrap();
+
+    if !entry_ids.is_empty() {
+        if let Err(err) =
+            locks::lock_invoiced(&state.db, auth_user.user_id, &entry_ids, invoice_id).await
+        {
+            state.db.invoices().delete_one(doc! { "_id": invoice_id }, None).await?;
+            return Err(err);
+        }
+        state.timer_events.publish_resync(auth_user.user_id);
+    }
 
     Ok(Json(invoice_with_id))
 }
@@ -115,16 +183,72 @@ async fn update_invoice_status(
     let object_id = ObjectId::parse_str(&id)
         .map_err(|_| AppError::BadRequest("Invalid ID".to_string()))?;
 
+    // Marking an invoice paid again keeps the date it was first paid
+    let now = Utc::now();
+    let paid_at = match payload.status {
+        InvoiceStatus::Paid => Bson::Document(doc! { "$ifNull": ["$paid_at", now] }),
+        _ => Bson::Null,
+    };
     let invoice = state
         .db
         .invoices()
         .find_one_and_update(
             doc! { "_id": object_id, "user_id": auth_user.user_id },
-            doc! { "$set": { "status": bson::to_bson(&payload.status)?, "updated_at": Utc::now() } },
+            vec![doc! {
+                "$set": {
+                    "status": bson::to_bson(&payload.status)?,
+                    "paid_at": paid_at,
+                    "updated_at": now,
+                }
+            }],
             None,
         )
         .await?
         .ok_or(AppError::NotFound("Invoice not found".to_string()))?;
 
+    if let Err(err) = sync_retainer(&state, &invoice, &payload.status).await {
+        state
+            .db
+            .invoices()
+            .update_one(
+                doc! { "_id": object_id },
+                doc! {
+                    "$set": {
+                        "status": bson::to_bson(&invoice.status)?,
+                        "paid_at": invoice.paid_at.map(bson::DateTime::from_chrono),
+                        "updated_at": invoice.updated_at,
+                    }
+                },
+                None,
+            )
+            .await?;
+        return Err(err);
+    }
+
     Ok(Json(invoice))
 }
+
+/// Draws the retainer when the invoice is issued and credits it back when
+/// the invoice returns to draft.
+async fn sync_retainer(state: &AppState, invoice: &Invoice, status: &InvoiceStatus) -> Result<()> {
+    if invoice.retainer_applied <= 0.0 {
+        return Ok(());
+    }
+
+    let invoice_id = invoice.id.unwrap();
+    match (&invoice.status, status) {
+        (InvoiceStatus::Draft, InvoiceStatus::Draft) => Ok(()),
+        (InvoiceStatus::Draft, _) => {
+            retainer::draw_down_once(
+                &state.db,
+                invoice.user_id,
+                invoice.client_id,
+                invoice.retainer_applied,
+                invoice_id,
+            )
+            .await
+        }
+        (_, InvoiceStatus::Draft) => retainer::reverse(&state.db, invoice.user_id, invoice_id).await,
+        _ => Ok(()),
+    }
+}
@@ -5,3 +5,8 @@ pub mod time_tracking;
 pub mod contracts;
 pub mod resumes;
 pub mod projects;
+pub mod reports;
+pub mod settings;
+pub mod tags;
+pub mod timesheets;
+pub mod calendar;
@@ -1,16 +1,28 @@
 use axum::{
-    extract::{Path, State, Extension},
-    response::Json,
-    routing::{get, post, put, delete},
+    extract::{Path, Query, State, Extension},
+    http::StatusCode,
+    response::{IntoResponse, Json, Response},
+    routing::{get, put},
     Router, middleware,
 };
 use chrono::Utc;
-use mongodb::bson::{doc, oid::ObjectId};
+use mongodb::{
+    bson::{doc, oid::ObjectId, Bson},
+    options::{FindOneAndUpdateOptions, ReturnDocument},
+};
 
 use crate::{
-    models::{Project, ProjectStatus, CreateProjectRequest, UpdateProjectRequest},
+    models::{
+        Project, ProjectStatus, CreateProjectRequest, UpdateProjectRequest, Task, TaskResponse,
+        CreateTaskRequest, UpdateTaskRequest, DeleteQuery, DependentsAction, ProjectListQuery,
+    },
     middleware::{auth_middleware, AuthUser},
     error::{AppError, Result},
+    services::{
+        billing::{validate_hourly_rate, validate_rounding_rule},
+        dependents,
+        references::resolve_client,
+    },
     AppState,
 };
 
@@ -18,17 +30,25 @@ pub fn routes() -> Router<AppState> {
     Router::new()
         .route("/", get(list_projects).post(create_project))
         .route("/:id", get(get_project).put(update_project).delete(delete_project))
+        .route("/:id/tasks", get(list_tasks).post(create_task))
+        .route("/:id/tasks/:task_id", put(update_task).delete(delete_task))
         .route_layer(middleware::from_fn(auth_middleware))
 }
 
 async fn list_projects(
     State(state): State<AppState>,
     Extension(auth_user): Extension<AuthUser>,
+    Query(query): Query<ProjectListQuery>,
 ) -> Result<Json<Vec<Project>>> {
+    let mut filter = doc! { "user_id": auth_user.user_id };
+    if !query.include_archived.unwrap_or(false) {
+        filter.insert("archived_at", Bson::Null);
+    }
+
     let mut cursor = state
         .db
         .projects()
-        .find(doc! { "user_id": auth_user.user_id }, None)
+        .find(filter, None)
         .await?;
 
     let mut projects = Vec::new();
@@ -44,9 +64,13 @@ async fn create_project(
     Extension(auth_user): Extension<AuthUser>,
     Json(payload): Json<CreateProjectRequest>,
 ) -> Result<Json<Project>> {
+    if let Some(rule) = &payload.time_rounding {
+        validate_rounding_rule(rule)?;
+    }
+    validate_hourly_rate(payload.hourly_rate)?;
+
     let client_id = match payload.client_id {
-        Some(id) => Some(ObjectId::parse_str(&id)
-            .map_err(|_| AppError::BadRequest("Invalid client ID".to_string()))?),
+        Some(id) => resolve_client(&state.db, auth_user.user_id, &id).await?.id,
         None => None,
     };
 
@@ -61,6 +85,8 @@ async fn create_project(
         budget: payload.budget,
         start_date: payload.start_date,
         end_date: None,
+        time_rounding: payload.time_rounding,
+        archived_at: None,
         created_at: Utc::now(),
         updated_at: Utc::now(),
     };
@@ -101,6 +127,10 @@ async fn update_project(
 
     let mut update_doc = doc! { "updated_at": Utc::now() };
     
+    if let Some(client_id) = payload.client_id {
+        let client = resolve_client(&state.db, auth_user.user_id, &client_id).await?;
+        update_doc.insert("client_id", client.id);
+    }
     if let Some(name) = payload.name {
         update_doc.insert("name", name);
     }
@@ -111,6 +141,7 @@ async fn update_project(
         update_doc.insert("status", bson::to_bson(&status)?);
     }
     if let Some(hourly_rate) = payload.hourly_rate {
+        validate_hourly_rate(Some(hourly_rate))?;
         update_doc.insert("hourly_rate", hourly_rate);
     }
     if let Some(budget) = payload.budget {
@@ -119,6 +150,10 @@ async fn update_project(
     if let Some(end_date) = payload.end_date {
         update_doc.insert("end_date", end_date);
     }
+    if let Some(rule) = payload.time_rounding {
+        validate_rounding_rule(&rule)?;
+        update_doc.insert("time_rounding", bson::to_bson(&rule)?);
+    }
 
     let project = state
         .db
@@ -134,14 +169,44 @@ async fn update_project(
     Ok(Json(project))
 }
 
+/// Deletes a project without tasks or time entries. `dependents=archive`
+/// archives it instead; `dependents=reassign&reassign_to=<id>` moves them to
+/// another project first.
 async fn delete_project(
     State(state): State<AppState>,
     Extension(auth_user): Extension<AuthUser>,
     Path(id): Path<String>,
-) -> Result<Json<serde_json::Value>> {
+    Query(query): Query<DeleteQuery>,
+) -> Result<Response> {
     let object_id = ObjectId::parse_str(&id)
         .map_err(|_| AppError::BadRequest("Invalid ID".to_string()))?;
 
+    match query.dependents {
+        DependentsAction::Refuse => {
+            let dependents = dependents::project_dependents(&state.db, auth_user.user_id, object_id).await?;
+            if !dependents.is_empty() {
+                return Ok((
+                    StatusCode::CONFLICT,
+                    Json(serde_json::json!({
+                        "error": "Project is still referenced by other records",
+                        "dependents": dependents,
+                    })),
+                )
+                    .into_response());
+            }
+        }
+        DependentsAction::Archive => {
+            dependents::archive_project(&state.db, auth_user.user_id, object_id).await?;
+            return Ok(Json(serde_json::json!({ "message": "Project archived" })).into_response());
+        }
+        DependentsAction::Reassign => {
+            let target = query
+                .reassign_to
+                .ok_or(AppError::BadRequest("reassign_to is required".to_string()))?;
+            dependents::reassign_project(&state.db, auth_user.user_id, object_id, &target).await?;
+        }
+    }
+
     let result = state
         .db
         .projects()
@@ -152,5 +217,199 @@ async fn delete_project(
         return Err(AppError::NotFound("Project not found".to_string()));
     }
 
-    Ok(Json(serde_json::json!({ "message": "Project deleted" })))
+    // Calendar import rules for the project go with it
+    state
+        .db
+        .users()
+        .update_one(
+            doc! { "_id": auth_user.user_id },
+            doc! { "$pull": { "settings.calendar_rules": { "project_id": object_id } } },
+            None,
+        )
+        .await?;
+
+    Ok(Json(serde_json::json!({ "message": "Project deleted" })).into_response())
+}
+
+async fn list_tasks(
+    State(state): State<AppState>,
+    Extension(auth_user): Extension<AuthUser>,
+    Path(id): Path<String>,
+) -> Result<Json<Vec<TaskResponse>>> {
+    let project_id = ObjectId::parse_str(&id)
+        .map_err(|_| AppError::BadRequest("Invalid ID".to_string()))?;
+
+    // Time tracked per task, to compare against estimates
+    let mut cursor = state
+        .db
+        .time_entries()
+        .aggregate(
+            [
+                doc! {
+                    "$match": {
+                        "user_id": auth_user.user_id,
+                        "project_id": project_id,
+                        "task_id": { "$ne": null },
+                    }
+                },
+                doc! {
+                    "$group": {
+                        "_id": "$task_id",
+                        "seconds": { "$sum": { "$ifNull": ["$duration", 0] } },
+                    }
+                },
+            ],
+            None,
+        )
+        .await?;
+    let mut tracked = std::collections::HashMap::new();
+    while cursor.advance().await? {
+        let row = cursor.deserialize_current()?;
+        let seconds = match row.get("seconds") {
+            Some(Bson::Int32(n)) => i64::from(*n),
+            Some(Bson::Int64(n)) => *n,
+            _ => 0,
+        };
+        if let Ok(task_id) = row.get_object_id("_id") {
+            tracked.insert(task_id, seconds);
+        }
+    }
+
+    let mut cursor = state
+        .db
+        .tasks()
+        .find(doc! { "user_id": auth_user.user_id, "project_id": project_id }, None)
+        .await?;
+
+    let mut tasks = Vec::new();
+    while cursor.advance().await? {
+        let task: Task = cursor.deserialize_current()?;
+        let tracked_seconds = task.id.and_then(|id| tracked.get(&id).copied()).unwrap_or(0);
+        tasks.push(TaskResponse { task, tracked_seconds });
+    }
+
+    Ok(Json(tasks))
+}
+
+async fn create_task(
+    State(state): State<AppState>,
+    Extension(auth_user): Extension<AuthUser>,
+    Path(id): Path<String>,
+    Json(payload): Json<CreateTaskRequest>,
+) -> Result<Json<Task>> {
+    let project_id = ObjectId::parse_str(&id)
+        .map_err(|_| AppError::BadRequest("Invalid ID".to_string()))?;
+
+    state
+        .db
+        .projects()
+        .find_one(doc! { "_id": project_id, "user_id": auth_user.user_id }, None)
+        .await?
+        .ok_or(AppError::NotFound("Project not found".to_string()))?;
+
+    validate_hourly_rate(payload.hourly_rate)?;
+
+    let task = Task {
+        id: None,
+        user_id: auth_user.user_id,
+        project_id,
+        name: payload.name,
+        hourly_rate: payload.hourly_rate,
+        estimated_hours: payload.estimated_hours,
+        is_done: false,
+        created_at: Utc::now(),
+        updated_at: Utc::now(),
+    };
+
+    let result = state.db.tasks().insert_one(&task, None).await?;
+    let mut task_with_id = task;
+    task_with_id.id = Some(result.inserted_id.as_object_id().unwrap());
+
+    Ok(Json(task_with_id))
+}
+
+async fn update_task(
+    State(state): State<AppState>,
+    Extension(auth_user): Extension<AuthUser>,
+    Path((id, task_id)): Path<(String, String)>,
+    Json(payload): Json<UpdateTaskRequest>,
+) -> Result<Json<Task>> {
+    let project_id = ObjectId::parse_str(&id)
+        .map_err(|_| AppError::BadRequest("Invalid ID".to_string()))?;
+    let task_id = ObjectId::parse_str(&task_id)
+        .map_err(|_| AppError::BadRequest("Invalid task ID".to_string()))?;
+
+    let mut update_doc = doc! { "updated_at": Utc::now() };
+
+    if let Some(name) = payload.name {
+        update_doc.insert("name", name);
+    }
+    if let Some(hourly_rate) = payload.hourly_rate {
+        validate_hourly_rate(Some(hourly_rate))?;
+        update_doc.insert("hourly_rate", hourly_rate);
+    }
+    if let Some(estimated_hours) = payload.estimated_hours {
+        update_doc.insert("estimated_hours", estimated_hours);
+    }
+    if let Some(is_done) = payload.is_done {
+        update_doc.insert("is_done", is_done);
+    }
+
+    let task = state
+        .db
+        .tasks()
+        .find_one_and_update(
+            doc! { "_id": task_id, "project_id": project_id, "user_id": auth_user.user_id },
+            doc! { "$set": update_doc },
+            FindOneAndUpdateOptions::builder()
+                .return_document(ReturnDocument::After)
+                .build(),
+        )
+        .await?
+        .ok_or(AppError::NotFound("Task not found".to_string()))?;
+
+    Ok(Json(task))
+}
+
+async fn delete_task(
+    State(state): State<AppState>,
+    Extension(auth_user): Extension<AuthUser>,
+    Path((id, task_id)): Path<(String, String)>,
+) -> Result<Json<serde_json::Value>> {
+    let project_id = ObjectId::parse_str(&id)
+        .map_err(|_| AppError::BadRequest("Invalid ID".to_string()))?;
+    let task_id = ObjectId::parse_str(&task_id)
+        .map_err(|_| AppError::BadRequest("Invalid task ID".to_string()))?;
+
+    // Locked entries can't change, so they have to keep pointing at the task
+    let locked = doc! { "user_id": auth_user.user_id, "task_id": task_id, "locked": true };
+    if state.db.time_entries().count_documents(locked, None).await? > 0 {
+        return Err(AppError::Conflict("Task has locked time entries".to_string()));
+    }
+
+    let result = state
+        .db
+        .tasks()
+        .delete_one(
+            doc! { "_id": task_id, "project_id": project_id, "user_id": auth_user.user_id },
+            None,
+        )
+        .await?;
+
+    if result.deleted_count == 0 {
+        return Err(AppError::NotFound("Task not found".to_string()));
+    }
+
+    // Entries keep their project but no longer point at the task
+    state
+        .db
+        .time_entries()
+        .update_many(
+            doc! { "user_id": auth_user.user_id, "task_id": task_id, "locked": { "$ne": true } },
+            doc! { "$unset": { "task_id": "" } },
+            None,
+        )
+        .await?;
+
+    Ok(Json(serde_json::json!({ "message": "Task deleted" })))
 }
@@ -0,0 +1,63 @@
+use axum::{
+    extract::{Query, State, Extension},
+    http::header,
+    response::{IntoResponse, Json, Response},
+    routing::get,
+    Router, middleware,
+};
+
+use crate::{
+    models::{ReportFormat, ReportFormatQuery, TimeEntryQuery},
+    middleware::{auth_middleware, AuthUser},
+    error::Result,
+    services::reports,
+    AppState,
+};
+
+pub fn routes() -> Router<AppState> {
+    Router::new()
+        .route("/time/summary", get(time_summary_report))
+        .route("/time/detailed", get(time_detailed_report))
+        .route_layer(middleware::from_fn(auth_middleware))
+}
+
+async fn time_summary_report(
+    State(state): State<AppState>,
+    Extension(auth_user): Extension<AuthUser>,
+    Query(query): Query<TimeEntryQuery>,
+    Query(output): Query<ReportFormatQuery>,
+) -> Result<Response> {
+    let report = reports::summary_report(&state.db, auth_user.user_id, &query).await?;
+
+    Ok(match output.format.unwrap_or_default() {
+        ReportFormat::Json => Json(report).into_response(),
+        ReportFormat::Csv => attachment("text/csv", "time-summary.csv", reports::summary_csv(&report)?),
+        ReportFormat::Pdf => attachment("application/pdf", "time-summary.pdf", reports::summary_pdf(&report)),
+    })
+}
+
+async fn time_detailed_report(
+    State(state): State<AppState>,
+    Extension(auth_user): Extension<AuthUser>,
+    Query(query): Query<TimeEntryQuery>,
+    Query(output): Query<ReportFormatQuery>,
+) -> Result<Response> {
+    let report = reports::detailed_report(&state.db, auth_user.user_id, &query).await?;
+
+    Ok(match output.format.unwrap_or_default() {
+        ReportFormat::Json => Json(report).into_response(),
+        ReportFormat::Csv => attachment("text/csv", "time-detailed.csv", reports::detailed_csv(&report)?),
+        ReportFormat::Pdf => attachment("application/pdf", "time-detailed.pdf", reports::detailed_pdf(&report)),
+    })
+}
+
+fn attachment(content_type: &'static str, filename: &str, body: Vec<u8>) -> Response {
+    (
+        [
+            (header::CONTENT_TYPE, content_type.to_string()),
+            (header::CONTENT_DISPOSITION, format!("attachment; filename=\"{}\"", filename)),
+        ],
+        body,
+    )
+        .into_response()
+}
@@ -1,7 +1,7 @@
 use axum::{
     extract::{Path, State, Extension},
     response::Json,
-    routing::{get, post, put, delete},
+    routing::{get, post},
     Router, middleware,
 };
 use chrono::Utc;
@@ -0,0 +1,146 @@
+use axum::{
+    extract::{State, Extension},
+    response::Json,
+    routing::get,
+    Router, middleware,
+};
+use chrono::Utc;
+use mongodb::{
+    bson::{doc, Bson, Document},
+    options::{FindOneAndUpdateOptions, ReturnDocument},
+};
+
+use crate::{
+    models::{UserSettings, UpdateUserSettingsRequest},
+    middleware::{auth_middleware, AuthUser},
+    error::{AppError, Result},
+    services::{
+        approvals,
+        billing::{validate_hourly_rate, validate_rounding_rule},
+        calendar::{validate_locale, validate_timezone},
+        settings::user_settings,
+        tax,
+    },
+    AppState,
+};
+
+pub fn routes() -> Router<AppState> {
+    Router::new()
+        .route("/", get(get_settings).put(update_settings))
+        .route_layer(middleware::from_fn(auth_middleware))
+}
+
+async fn get_settings(
+    State(state): State<AppState>,
+    Extension(auth_user): Extension<AuthUser>,
+) -> Result<Json<UserSettings>> {
+    Ok(Json(user_settings(&state.db, auth_user.user_id).await?))
+}
+
+async fn update_settings(
+    State(state): State<AppState>,
+    Extension(auth_user): Extension<AuthUser>,
+    Json(payload): Json<UpdateUserSettingsRequest>,
+) -> Result<Json<UserSettings>> {
+    let mut update_doc = doc! { "updated_at": Utc::now() };
+
+    if let Some(reject) = payload.reject_overlapping_time_entries {
+        update_doc.insert("settings.reject_overlapping_time_entries", reject);
+    }
+    if let Some(rule) = payload.time_rounding {
+        validate_rounding_rule(&rule)?;
+        update_doc.insert("settings.time_rounding", bson::to_bson(&rule)?);
+    }
+    if let Some(rate) = payload.default_hourly_rate {
+        validate_hourly_rate(Some(rate))?;
+        update_doc.insert("settings.default_hourly_rate", rate);
+    }
+    if let Some(timezone) = payload.timezone {
+        validate_timezone(&timezone)?;
+        update_doc.insert("settings.timezone", timezone);
+    }
+    if let Some(week_start) = payload.week_start {
+        update_doc.insert("settings.week_start", bson::to_bson(&week_start)?);
+    }
+    if let Some(locale) = payload.locale {
+        validate_locale(&locale)?;
+        update_doc.insert("settings.locale", locale);
+    }
+    if let Some(minutes) = payload.idle_threshold_minutes {
+        update_doc.insert("settings.idle_threshold_minutes", (minutes > 0).then_some(minutes as i64));
+    }
+    if let Some(hours) = payload.max_timer_hours {
+        update_doc.insert("settings.max_timer_hours", (hours > 0).then_some(hours as i64));
+    }
+
+    // The approver has to accept an invite, so whether the email belongs
+    // to anyone isn't revealed here
+    if let Some(email) = payload.timesheet_approver_email {
+        match email.trim() {
+            "" => {
+                update_doc.insert("settings.timesheet_approver_id", Bson::Null);
+                update_doc.insert("settings.timesheet_approver_invite", Bson::Null);
+            }
+            email => {
+                let user = state
+                    .db
+                    .users()
+                    .find_one(doc! { "_id": auth_user.user_id }, None)
+                    .await?
+                    .ok_or(AppError::NotFound("User not found".to_string()))?;
+                if user.email == email {
+                    return Err(AppError::BadRequest(
+                        "You can't approve your own timesheets".to_string(),
+                    ));
+                }
+                update_doc.insert("settings.timesheet_approver_invite", email);
+            }
+        }
+    }
+    if let Some(required) = payload.require_timesheet_approval {
+        update_doc.insert("settings.require_timesheet_approval", required);
+    }
+    if update_doc.contains_key("settings.timesheet_approver_id")
+        || update_doc.contains_key("settings.require_timesheet_approval")
+    {
+        let current = user_settings(&state.db, auth_user.user_id).await?;
+        let required = payload.require_timesheet_approval.unwrap_or(current.require_timesheet_approval);
+        let has_approver = match update_doc.get("settings.timesheet_approver_id") {
+            Some(approver) => approver != &Bson::Null,
+            None => current.timesheet_approver_id.is_some(),
+        };
+        if required && !has_approver {
+            return Err(AppError::BadRequest(
+                "Timesheet approval needs an approver who has accepted the invite".to_string(),
+            ));
+        }
+        let periods: Vec<Document> = approvals::approval_periods(&current, required, Utc::now())
+            .into_iter()
+            .map(|period| doc! { "from": period.from, "until": period.until })
+            .collect();
+        update_doc.insert("settings.timesheet_approval_periods", periods);
+    }
+    if payload.business_country.is_some() || payload.business_tax_id.is_some() {
+        let current = user_settings(&state.db, auth_user.user_id).await?;
+        let country = payload.business_country.or(current.business_country);
+        let tax_id = payload.business_tax_id.or(current.business_tax_id);
+        let identity = tax::tax_identity(country.as_deref(), None, tax_id.as_deref())?;
+        update_doc.insert("settings.business_country", identity.country);
+        update_doc.insert("settings.business_tax_id", identity.tax_id);
+    }
+
+    let user = state
+        .db
+        .users()
+        .find_one_and_update(
+            doc! { "_id": auth_user.user_id },
+            doc! { "$set": update_doc },
+            FindOneAndUpdateOptions::builder()
+                .return_document(ReturnDocument::After)
+                .build(),
+        )
+        .await?
+        .ok_or(AppError::NotFound("User not found".to_string()))?;
+
+    Ok(Json(user.settings))
+}
@@ -0,0 +1,145 @@
+use axum::{
+    extract::{Path, State, Extension},
+    response::Json,
+    routing::{get, put},
+    Router, middleware,
+};
+use chrono::Utc;
+use mongodb::{
+    bson::{doc, oid::ObjectId},
+    options::{FindOneAndUpdateOptions, ReturnDocument},
+};
+
+use crate::{
+    models::{Tag, CreateTagRequest, UpdateTagRequest},
+    middleware::{auth_middleware, AuthUser},
+    error::{self, AppError, Result},
+    AppState,
+};
+
+pub fn routes() -> Router<AppState> {
+    Router::new()
+        .route("/", get(list_tags).post(create_tag))
+        .route("/:id", put(update_tag).delete(delete_tag))
+        .route_layer(middleware::from_fn(auth_middleware))
+}
+
+async fn list_tags(
+    State(state): State<AppState>,
+    Extension(auth_user): Extension<AuthUser>,
+) -> Result<Json<Vec<Tag>>> {
+    let mut cursor = state
+        .db
+        .tags()
+        .find(doc! { "user_id": auth_user.user_id }, None)
+        .await?;
+
+    let mut tags = Vec::new();
+    while cursor.advance().await? {
+        tags.push(cursor.deserialize_current()?);
+    }
+
+    Ok(Json(tags))
+}
+
+async fn create_tag(
+    State(state): State<AppState>,
+    Extension(auth_user): Extension<AuthUser>,
+    Json(payload): Json<CreateTagRequest>,
+) -> Result<Json<Tag>> {
+    let tag = Tag {
+        id: None,
+        user_id: auth_user.user_id,
+        name: payload.name,
+        color: payload.color,
+        created_at: Utc::now(),
+        updated_at: Utc::now(),
+    };
+
+    // Names are unique per user through an index
+    let result = state.db.tags().insert_one(&tag, None).await.map_err(tag_exists)?;
+    let mut tag_with_id = tag;
+    tag_with_id.id = Some(result.inserted_id.as_object_id().unwrap());
+
+    Ok(Json(tag_with_id))
+}
+
+async fn update_tag(
+    State(state): State<AppState>,
+    Extension(auth_user): Extension<AuthUser>,
+    Path(id): Path<String>,
+    Json(payload): Json<UpdateTagRequest>,
+) -> Result<Json<Tag>> {
+    let object_id = ObjectId::parse_str(&id)
+        .map_err(|_| AppError::BadRequest("Invalid ID".to_string()))?;
+
+    let mut update_doc = doc! { "updated_at": Utc::now() };
+
+    if let Some(name) = payload.name {
+        update_doc.insert("name", name);
+    }
+    if let Some(color) = payload.color {
+        update_doc.insert("color", color);
+    }
+
+    let tag = state
+        .db
+        .tags()
+        .find_one_and_update(
+            doc! { "_id": object_id, "user_id": auth_user.user_id },
+            doc! { "$set": update_doc },
+            FindOneAndUpdateOptions::builder()
+                .return_document(ReturnDocument::After)
+                .build(),
+        )
+        .await
+        .map_err(tag_exists)?
+        .ok_or(AppError::NotFound("Tag not found".to_string()))?;
+
+    Ok(Json(tag))
+}
+
+fn tag_exists(err: mongodb::error::Error) -> AppError {
+    if error::is_duplicate_key(&err) {
+        AppError::Conflict("Tag already exists".to_string())
+    } else {
+        err.into()
+    }
+}
+
+async fn delete_tag(
+    State(state): State<AppState>,
+    Extension(auth_user): Extension<AuthUser>,
+    Path(id): Path<String>,
+) -> Result<Json<serde_json::Value>> {
+    let object_id = ObjectId::parse_str(&id)
+        .map_err(|_| AppError::BadRequest("Invalid ID".to_string()))?;
+
+    // Locked entries can't change, so the tag has to stay on them
+    let locked = doc! { "user_id": auth_user.user_id, "tags": object_id, "locked": true };
+    if state.db.time_entries().count_documents(locked, None).await? > 0 {
+        return Err(AppError::Conflict("Tag is on locked time entries".to_string()));
+    }
+
+    let result = state
+        .db
+        .tags()
+        .delete_one(doc! { "_id": object_id, "user_id": auth_user.user_id }, None)
+        .await?;
+
+    if result.deleted_count == 0 {
+        return Err(AppError::NotFound("Tag not found".to_string()));
+    }
+
+    state
+        .db
+        .time_entries()
+        .update_many(
+            doc! { "user_id": auth_user.user_id, "tags": object_id, "locked": { "$ne": true } },
+            doc! { "$pull": { "tags": object_id } },
+            None,
+        )
+        .await?;
+
+    Ok(Json(serde_json::json!({ "message": "Tag deleted" })))
+}
@@ -1,82 +1,236 @@
 use axum::{
-    extract::{Path, State, Extension},
-    response::Json,
-    routing::{get, post, put, delete},
+    extract::{
+        ws::{Message, WebSocket, WebSocketUpgrade},
+        Path, Query, State, Extension,
+    },
+    http::{header, HeaderMap, HeaderValue},
+    response::{Json, Response},
+    routing::{get, post},
     Router, middleware,
 };
-use chrono::Utc;
-use mongodb::bson::{doc, oid::ObjectId};
+use chrono::{DateTime, Utc};
+use mongodb::{
+    bson::{doc, oid::ObjectId},
+    options::{FindOneAndUpdateOptions, FindOptions, ReturnDocument},
+};
 
 use crate::{
-    models::{TimeEntry, CreateTimeEntryRequest, StopTimeEntryRequest, UpdateTimeEntryRequest},
-    middleware::{auth_middleware, AuthUser},
-    error::{AppError, Result},
+    models::{
+        TimeEntry, TimeEntryResponse, CreateTimeEntryRequest, StopTimeEntryRequest,
+        UpdateTimeEntryRequest, OverlapQuery, OverlappingPair, TimeEntryQuery, TimeEntryGroup,
+        TimeEntrySortField, SortOrder, ImportTimeEntriesRequest, ImportTimeEntriesResponse,
+        UnlockTimeEntryRequest, ClosePeriodRequest, ClosePeriodResponse, TimerEvent,
+        TimerEventKind, TimerSocketQuery, IdleAction, ResolveIdleRequest,
+    },
+    middleware::{auth_middleware, verify_token, AuthUser},
+    error::{self, AppError, Result},
+    services::{
+        billing::{validate_hourly_rate, BillingContext},
+        idle::idle_period,
+        locks::{self, ensure_open, ensure_unlocked},
+        references::resolve_project,
+        settings::user_settings,
+        time_import::import_time_entries,
+        time_entries::{
+            build_filter, check_overlaps, duration_between, overlapping_pairs, resolve_tags,
+            resolve_task, summarize,
+        },
+    },
     AppState,
 };
 
 pub fn routes() -> Router<AppState> {
     Router::new()
         .route("/", get(list_time_entries).post(create_time_entry))
+        .route("/current", get(get_current_time_entry))
+        .route("/overlaps", get(list_overlaps))
+        .route("/summary", get(summarize_time_entries))
+        .route("/import", post(import_entries))
+        .route("/close-period", post(close_period))
         .route("/:id", get(get_time_entry).put(update_time_entry).delete(delete_time_entry))
         .route("/:id/stop", post(stop_time_entry))
+        .route("/:id/unlock", post(unlock_time_entry))
+        .route("/:id/heartbeat", post(heartbeat))
+        .route("/:id/idle", post(resolve_idle))
         .route_layer(middleware::from_fn(auth_middleware))
+        // Authenticates itself, since browsers can't send headers with the upgrade
+        .route("/ws", get(timer_socket))
 }
 
 async fn list_time_entries(
     State(state): State<AppState>,
     Extension(auth_user): Extension<AuthUser>,
-) -> Result<Json<Vec<TimeEntry>>> {
+    Query(query): Query<TimeEntryQuery>,
+) -> Result<(HeaderMap, Json<Vec<TimeEntryResponse>>)> {
+    let filter = build_filter(&state.db, auth_user.user_id, &query).await?;
+
+    let sort_field = match query.sort_by.unwrap_or(TimeEntrySortField::StartTime) {
+        TimeEntrySortField::StartTime => "start_time",
+        TimeEntrySortField::EndTime => "end_time",
+        TimeEntrySortField::Duration => "duration",
+        TimeEntrySortField::CreatedAt => "created_at",
+    };
+    let direction = match query.order.unwrap_or(SortOrder::Desc) {
+        SortOrder::Asc => 1,
+        SortOrder::Desc => -1,
+    };
+    let mut options = FindOptions::builder()
+        .sort(doc! { sort_field: direction, "_id": direction })
+        .build();
+
+    // Without `per_page` everything is returned, as before pagination existed
+    let mut headers = HeaderMap::new();
+    if let Some(per_page) = query.per_page {
+        if per_page == 0 || per_page > 500 {
+            return Err(AppError::BadRequest("per_page must be between 1 and 500".to_string()));
+        }
+        let page = query.page.unwrap_or(1).max(1);
+        options.skip = Some((page - 1) * per_page);
+        options.limit = Some(per_page as i64);
+
+        let total = state.db.time_entries().count_documents(filter.clone(), None).await?;
+        headers.insert("x-total-count", HeaderValue::from(total));
+    }
+
     let mut cursor = state
         .db
         .time_entries()
-        .find(doc! { "user_id": auth_user.user_id }, None)
+        .find(filter, options)
         .await?;
 
+    let billing = BillingContext::load(&state.db, auth_user.user_id).await?;
     let mut entries = Vec::new();
     while cursor.advance().await? {
-        entries.push(cursor.deserialize_current()?);
+        entries.push(billing.response(cursor.deserialize_current()?));
     }
 
-    Ok(Json(entries))
+    Ok((headers, Json(entries)))
+}
+
+async fn summarize_time_entries(
+    State(state): State<AppState>,
+    Extension(auth_user): Extension<AuthUser>,
+    Query(query): Query<TimeEntryQuery>,
+) -> Result<Json<Vec<TimeEntryGroup>>> {
+    let grouping = query
+        .group_by
+        .ok_or(AppError::BadRequest("group_by is required".to_string()))?;
+
+    let filter = build_filter(&state.db, auth_user.user_id, &query).await?;
+
+    Ok(Json(summarize(&state.db, auth_user.user_id, filter, grouping).await?))
 }
 
 async fn create_time_entry(
     State(state): State<AppState>,
     Extension(auth_user): Extension<AuthUser>,
     Json(payload): Json<CreateTimeEntryRequest>,
-) -> Result<Json<TimeEntry>> {
-    let project_id = match payload.project_id {
-        Some(id) => Some(ObjectId::parse_str(&id)
-            .map_err(|_| AppError::BadRequest("Invalid project ID".to_string()))?),
+) -> Result<Json<TimeEntryResponse>> {
+    let mut project_id = match payload.project_id {
+        Some(id) => resolve_project(&state.db, auth_user.user_id, &id).await?.id,
         None => None,
     };
 
+    // An entry logged against a task is logged against the task's project
+    let task_id = match payload.task_id {
+        Some(id) => {
+            let task = resolve_task(&state.db, auth_user.user_id, &id, project_id).await?;
+            project_id = Some(task.project_id);
+            task.id
+        }
+        None => None,
+    };
+    let tags = resolve_tags(&state.db, auth_user.user_id, &payload.tags.unwrap_or_default()).await?;
+    validate_hourly_rate(payload.hourly_rate)?;
+    ensure_open(&user_settings(&state.db, auth_user.user_id).await?, payload.start_time)?;
+
+    let running = find_running_entry(&state, auth_user.user_id).await?;
+    if let Some(running) = &running {
+        if !payload.stop_running.unwrap_or(false) {
+            return Err(AppError::Conflict("A timer is already running".to_string()));
+        }
+        if payload.start_time < running.start_time {
+            return Err(AppError::BadRequest(
+                "New timer cannot start before the running timer".to_string(),
+            ));
+        }
+    }
+
+    // The running timer ends where the new one starts, so it can't overlap;
+    // it is only stopped once the new timer is known to be allowed
+    let running_id = running.as_ref().and_then(|running| running.id);
+    let overlaps =
+        check_overlaps(&state.db, auth_user.user_id, running_id, payload.start_time, None).await?;
+    if let Some(running) = &running {
+        let stopped = finish_entry(&state, auth_user.user_id, running, payload.start_time).await?;
+        state.timer_events.publish(TimerEventKind::Stopped, &stopped);
+    }
+
     let entry = TimeEntry {
         id: None,
         user_id: auth_user.user_id,
         project_id,
+        task_id,
+        tags,
         description: payload.description,
         start_time: payload.start_time,
         end_time: None,
         duration: None,
         is_billable: payload.is_billable.unwrap_or(true),
         hourly_rate: payload.hourly_rate,
+        invoice_id: None,
+        locked: false,
+        lock_reason: None,
+        unlocks: Vec::new(),
+        draft: false,
+        external_id: None,
+        last_activity_at: None,
+        auto_stopped: false,
+        approval_status: None,
         created_at: Utc::now(),
         updated_at: Utc::now(),
     };
 
-    let result = state.db.time_entries().insert_one(&entry, None).await?;
+    // The partial unique index catches timers started concurrently
+    let result = state
+        .db
+        .time_entries()
+        .insert_one(&entry, None)
+        .await
+        .map_err(|err| {
+            if error::is_duplicate_key(&err) {
+                AppError::Conflict("A timer is already running".to_string())
+            } else {
+                err.into()
+            }
+        })?;
     let mut entry_with_id = entry;
     entry_with_id.id = Some(result.inserted_id.as_object_id().unwrap());
+    state.timer_events.publish(TimerEventKind::Started, &entry_with_id);
 
-    Ok(Json(entry_with_id))
+    let billing = BillingContext::load(&state.db, auth_user.user_id).await?;
+    Ok(Json(TimeEntryResponse { overlaps, ..billing.response(entry_with_id) }))
+}
+
+async fn get_current_time_entry(
+    State(state): State<AppState>,
+    Extension(auth_user): Extension<AuthUser>,
+) -> Result<Json<Option<TimeEntryResponse>>> {
+    let billing = BillingContext::load(&state.db, auth_user.user_id).await?;
+    let settings = user_settings(&state.db, auth_user.user_id).await?;
+    let entry = find_running_entry(&state, auth_user.user_id).await?;
+
+    Ok(Json(entry.map(|entry| TimeEntryResponse {
+        idle: idle_period(&settings, &entry, Utc::now()),
+        ..billing.response(entry)
+    })))
 }
 
 async fn get_time_entry(
     State(state): State<AppState>,
     Extension(auth_user): Extension<AuthUser>,
     Path(id): Path<String>,
-) -> Result<Json<TimeEntry>> {
+) -> Result<Json<TimeEntryResponse>> {
     let object_id = ObjectId::parse_str(&id)
         .map_err(|_| AppError::BadRequest("Invalid ID".to_string()))?;
 
@@ -87,7 +241,8 @@ async fn get_time_entry(
         .await?
         .ok_or(AppError::NotFound("Time entry not found".to_string()))?;
 
-    Ok(Json(entry))
+    let billing = BillingContext::load(&state.db, auth_user.user_id).await?;
+    Ok(Json(billing.response(entry)))
 }
 
 async fn stop_time_entry(
@@ -95,7 +250,7 @@ async fn stop_time_entry(
     Extension(auth_user): Extension<AuthUser>,
     Path(id): Path<String>,
     Json(payload): Json<StopTimeEntryRequest>,
-) -> Result<Json<TimeEntry>> {
+) -> Result<Json<TimeEntryResponse>> {
     let object_id = ObjectId::parse_str(&id)
         .map_err(|_| AppError::BadRequest("Invalid ID".to_string()))?;
 
@@ -105,27 +260,22 @@ async fn stop_time_entry(
         .find_one(doc! { "_id": object_id, "user_id": auth_user.user_id }, None)
         .await?
         .ok_or(AppError::NotFound("Time entry not found".to_string()))?;
+    ensure_unlocked(&entry)?;
 
-    let duration = (payload.end_time - entry.start_time).num_seconds();
+    let overlaps = check_overlaps(
+        &state.db,
+        auth_user.user_id,
+        entry.id,
+        entry.start_time,
+        Some(payload.end_time),
+    )
+    .await?;
 
-    let updated_entry = state
-        .db
-        .time_entries()
-        .find_one_and_update(
-            doc! { "_id": object_id, "user_id": auth_user.user_id },
-            doc! { 
-                "$set": { 
-                    "end_time": payload.end_time,
-                    "duration": duration,
-                    "updated_at": Utc::now()
-                } 
-            },
-            None,
-        )
-        .await?
-        .ok_or(AppError::NotFound("Time entry not found".to_string()))?;
+    let updated_entry = finish_entry(&state, auth_user.user_id, &entry, payload.end_time).await?;
+    state.timer_events.publish(TimerEventKind::Stopped, &updated_entry);
 
-    Ok(Json(updated_entry))
+    let billing = BillingContext::load(&state.db, auth_user.user_id).await?;
+    Ok(Json(TimeEntryResponse { overlaps, ..billing.response(updated_entry) }))
 }
 
 async fn update_time_entry(
@@ -133,40 +283,93 @@ async fn update_time_entry(
     Extension(auth_user): Extension<AuthUser>,
     Path(id): Path<String>,
     Json(payload): Json<UpdateTimeEntryRequest>,
-) -> Result<Json<TimeEntry>> {
+) -> Result<Json<TimeEntryResponse>> {
     let object_id = ObjectId::parse_str(&id)
         .map_err(|_| AppError::BadRequest("Invalid ID".to_string()))?;
 
+    let existing = state
+        .db
+        .time_entries()
+        .find_one(doc! { "_id": object_id, "user_id": auth_user.user_id }, None)
+        .await?
+        .ok_or(AppError::NotFound("Time entry not found".to_string()))?;
+    ensure_unlocked(&existing)?;
+
     let mut update_doc = doc! { "updated_at": Utc::now() };
-    
+
+    let mut unset_doc = doc! {};
+    let mut project_id = existing.project_id;
+    if let Some(id) = payload.project_id {
+        project_id = resolve_project(&state.db, auth_user.user_id, &id).await?.id;
+        update_doc.insert("project_id", project_id);
+        // A task from the old project no longer applies
+        if project_id != existing.project_id && payload.task_id.is_none() {
+            unset_doc.insert("task_id", "");
+        }
+    }
+    if let Some(task_id) = payload.task_id {
+        let task = resolve_task(&state.db, auth_user.user_id, &task_id, project_id).await?;
+        update_doc.insert("task_id", task.id);
+        update_doc.insert("project_id", task.project_id);
+    }
+    if let Some(tags) = payload.tags {
+        update_doc.insert("tags", resolve_tags(&state.db, auth_user.user_id, &tags).await?);
+    }
     if let Some(description) = payload.description {
         update_doc.insert("description", description);
     }
     if let Some(start_time) = payload.start_time {
+        // Entries may stay in a closed period once unlocked, but not move into one
+        if start_time < existing.start_time {
+            ensure_open(&user_settings(&state.db, auth_user.user_id).await?, start_time)?;
+        }
         update_doc.insert("start_time", start_time);
     }
     if let Some(end_time) = payload.end_time {
         update_doc.insert("end_time", end_time);
     }
+
+    // Keep duration consistent with whatever the range ends up being
+    let start_time = payload.start_time.unwrap_or(existing.start_time);
+    let end_time = payload.end_time.or(existing.end_time);
+    if let Some(end_time) = end_time {
+        update_doc.insert("duration", duration_between(start_time, end_time)?);
+    }
+
+    let overlaps = check_overlaps(&state.db, auth_user.user_id, existing.id, start_time, end_time).await?;
+
     if let Some(is_billable) = payload.is_billable {
         update_doc.insert("is_billable", is_billable);
     }
     if let Some(hourly_rate) = payload.hourly_rate {
+        validate_hourly_rate(Some(hourly_rate))?;
         update_doc.insert("hourly_rate", hourly_rate);
     }
+    if let Some(draft) = payload.draft {
+        update_doc.insert("draft", draft);
+    }
+
+    let mut update = doc! { "$set": update_doc };
+    if !unset_doc.is_empty() {
+        update.insert("$unset", unset_doc);
+    }
 
     let entry = state
         .db
         .time_entries()
         .find_one_and_update(
-            doc! { "_id": object_id, "user_id": auth_user.user_id },
-            doc! { "$set": update_doc },
-            None,
+            doc! { "_id": object_id, "user_id": auth_user.user_id, "locked": { "$ne": true } },
+            update,
+            FindOneAndUpdateOptions::builder()
+                .return_document(ReturnDocument::After)
+                .build(),
         )
         .await?
         .ok_or(AppError::NotFound("Time entry not found".to_string()))?;
+    state.timer_events.publish(TimerEventKind::Updated, &entry);
 
-    Ok(Json(entry))
+    let billing = BillingContext::load(&state.db, auth_user.user_id).await?;
+    Ok(Json(TimeEntryResponse { overlaps, ..billing.response(entry) }))
 }
 
 async fn delete_time_entry(
@@ -177,15 +380,334 @@ async fn delete_time_entry(
     let object_id = ObjectId::parse_str(&id)
         .map_err(|_| AppError::BadRequest("Invalid ID".to_string()))?;
 
+    let entry = state
+        .db
+        .time_entries()
+        .find_one(doc! { "_id": object_id, "user_id": auth_user.user_id }, None)
+        .await?
+        .ok_or(AppError::NotFound("Time entry not found".to_string()))?;
+    ensure_unlocked(&entry)?;
+
     let result = state
         .db
         .time_entries()
-        .delete_one(doc! { "_id": object_id, "user_id": auth_user.user_id }, None)
+        .delete_one(
+            doc! { "_id": object_id, "user_id": auth_user.user_id, "locked": { "$ne": true } },
+            None,
+        )
         .await?;
 
     if result.deleted_count == 0 {
-        return Err(AppError::NotFound("Time entry not found".to_string()));
+        return Err(AppError::Conflict("Time entry is locked".to_string()));
     }
+    state.timer_events.publish(TimerEventKind::Deleted, &entry);
 
     Ok(Json(serde_json::json!({ "message": "Time entry deleted" })))
 }
+
+async fn unlock_time_entry(
+    State(state): State<AppState>,
+    Extension(auth_user): Extension<AuthUser>,
+    Path(id): Path<String>,
+    Json(payload): Json<UnlockTimeEntryRequest>,
+) -> Result<Json<TimeEntryResponse>> {
+    let object_id = ObjectId::parse_str(&id)
+        .map_err(|_| AppError::BadRequest("Invalid ID".to_string()))?;
+
+    let entry = locks::unlock(&state.db, auth_user.user_id, object_id, &payload.reason).await?;
+    state.timer_events.publish(TimerEventKind::Updated, &entry);
+
+    let billing = BillingContext::load(&state.db, auth_user.user_id).await?;
+    Ok(Json(billing.response(entry)))
+}
+
+/// Records that the user is active. Once the timer has gone idle the
+/// heartbeat isn't recorded; the idle period is returned instead and stays
+/// until resolved through `/:id/idle`.
+async fn heartbeat(
+    State(state): State<AppState>,
+    Extension(auth_user): Extension<AuthUser>,
+    Path(id): Path<String>,
+) -> Result<Json<TimeEntryResponse>> {
+    let object_id = ObjectId::parse_str(&id)
+        .map_err(|_| AppError::BadRequest("Invalid ID".to_string()))?;
+
+    let entry = find_running_entry_by_id(&state, auth_user.user_id, object_id).await?;
+    let settings = user_settings(&state.db, auth_user.user_id).await?;
+    let billing = BillingContext::load(&state.db, auth_user.user_id).await?;
+
+    let now = Utc::now();
+    if let Some(idle) = idle_period(&settings, &entry, now) {
+        return Ok(Json(TimeEntryResponse { idle: Some(idle), ..billing.response(entry) }));
+    }
+
+    let entry = record_activity(&state, &entry, now).await?;
+    Ok(Json(billing.response(entry)))
+}
+
+async fn resolve_idle(
+    State(state): State<AppState>,
+    Extension(auth_user): Extension<AuthUser>,
+    Path(id): Path<String>,
+    Json(payload): Json<ResolveIdleRequest>,
+) -> Result<Json<TimeEntryResponse>> {
+    let object_id = ObjectId::parse_str(&id)
+        .map_err(|_| AppError::BadRequest("Invalid ID".to_string()))?;
+
+    let entry = find_running_entry_by_id(&state, auth_user.user_id, object_id).await?;
+    let settings = user_settings(&state.db, auth_user.user_id).await?;
+    let now = Utc::now();
+    let idle = idle_period(&settings, &entry, now)
+        .ok_or(AppError::BadRequest("Timer is not idle".to_string()))?;
+
+    let entry = match payload.action {
+        IdleAction::Keep => {
+            let entry = record_activity(&state, &entry, now).await?;
+            state.timer_events.publish(TimerEventKind::Updated, &entry);
+            entry
+        }
+        IdleAction::Trim => {
+            let stopped = finish_entry(&state, auth_user.user_id, &entry, idle.since).await?;
+            state.timer_events.publish(TimerEventKind::Stopped, &stopped);
+            stopped
+        }
+        IdleAction::Discard => {
+            let stopped = finish_entry(&state, auth_user.user_id, &entry, idle.since).await?;
+            state.timer_events.publish(TimerEventKind::Stopped, &stopped);
+
+            let restarted = TimeEntry {
+                id: None,
+                start_time: now,
+                end_time: None,
+                duration: None,
+                last_activity_at: Some(now),
+                created_at: now,
+                updated_at: now,
+                ..stopped
+            };
+            let result = state
+                .db
+                .time_entries()
+                .insert_one(&restarted, None)
+                .await
+                .map_err(|err| {
+                    if error::is_duplicate_key(&err) {
+                        AppError::Conflict("A timer is already running".to_string())
+                    } else {
+                        err.into()
+                    }
+                })?;
+            let restarted = TimeEntry { id: result.inserted_id.as_object_id(), ..restarted };
+            state.timer_events.publish(TimerEventKind::Started, &restarted);
+            restarted
+        }
+    };
+
+    let billing = BillingContext::load(&state.db, auth_user.user_id).await?;
+    Ok(Json(billing.response(entry)))
+}
+
+async fn close_period(
+    State(state): State<AppState>,
+    Extension(auth_user): Extension<AuthUser>,
+    Json(payload): Json<ClosePeriodRequest>,
+) -> Result<Json<ClosePeriodResponse>> {
+    if payload.until > Utc::now() {
+        return Err(AppError::BadRequest("Cannot close a period that hasn't ended".to_string()));
+    }
+
+    let response = locks::close_period(&state.db, auth_user.user_id, payload.until).await?;
+    if response.locked > 0 {
+        state.timer_events.publish_resync(auth_user.user_id);
+    }
+
+    Ok(Json(response))
+}
+
+async fn import_entries(
+    State(state): State<AppState>,
+    Extension(auth_user): Extension<AuthUser>,
+    Json(payload): Json<ImportTimeEntriesRequest>,
+) -> Result<Json<ImportTimeEntriesResponse>> {
+    let response = import_time_entries(&state.db, auth_user.user_id, payload).await?;
+    if !response.dry_run && response.imported > 0 {
+        state.timer_events.publish_resync(auth_user.user_id);
+    }
+
+    Ok(Json(response))
+}
+
+async fn list_overlaps(
+    State(state): State<AppState>,
+    Extension(auth_user): Extension<AuthUser>,
+    Query(query): Query<OverlapQuery>,
+) -> Result<Json<Vec<OverlappingPair>>> {
+    if query.to <= query.from {
+        return Err(AppError::BadRequest("`to` must be after `from`".to_string()));
+    }
+
+    let options = FindOptions::builder().sort(doc! { "start_time": 1 }).build();
+    let mut cursor = state
+        .db
+        .time_entries()
+        .find(
+            doc! {
+                "user_id": auth_user.user_id,
+                "draft": { "$ne": true },
+                "start_time": { "$lt": query.to },
+                "$or": [
+                    { "end_time": { "$gt": query.from } },
+                    { "end_time": null },
+                ],
+            },
+            options,
+        )
+        .await?;
+
+    let mut entries = Vec::new();
+    while cursor.advance().await? {
+        entries.push(cursor.deserialize_current()?);
+    }
+
+    Ok(Json(overlapping_pairs(&entries)))
+}
+
+/// Pushes the user's timer events to a WebSocket. The token comes from the
+/// `token` query parameter or the usual `Authorization` header.
+async fn timer_socket(
+    State(state): State<AppState>,
+    Query(query): Query<TimerSocketQuery>,
+    headers: HeaderMap,
+    ws: WebSocketUpgrade,
+) -> Result<Response> {
+    let header_token = headers
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "));
+    let token = query.token.as_deref().or(header_token).ok_or(AppError::Unauthorized)?;
+    let user_id = verify_token(token, &state.config.jwt_secret)?;
+
+    // Subscribe before the snapshot is read so no change falls in between
+    let events = state.timer_events.subscribe();
+    Ok(ws.on_upgrade(move |socket| push_timer_events(socket, state, user_id, events)))
+}
+
+async fn push_timer_events(
+    mut socket: WebSocket,
+    state: AppState,
+    user_id: ObjectId,
+    mut events: tokio::sync::broadcast::Receiver<TimerEvent>,
+) {
+    use tokio::sync::broadcast::error::RecvError;
+
+    let mut send_snapshot = true;
+    loop {
+        if send_snapshot {
+            send_snapshot = false;
+            let Ok(running) = find_running_entry(&state, user_id).await else { break };
+            let snapshot = TimerEvent {
+                user_id,
+                kind: TimerEventKind::Snapshot,
+                entry_id: running.as_ref().and_then(|entry| entry.id),
+                entry: running,
+            };
+            if send_event(&mut socket, &snapshot).await.is_err() {
+                break;
+            }
+        }
+
+        tokio::select! {
+            event = events.recv() => match event {
+                Ok(event) if event.user_id == user_id => {
+                    if send_event(&mut socket, &event).await.is_err() {
+                        break;
+                    }
+                }
+                Ok(_) => {}
+                // Too far behind to replay; resync from the current state
+                Err(RecvError::Lagged(_)) => send_snapshot = true,
+                Err(RecvError::Closed) => break,
+            },
+            message = socket.recv() => match message {
+                Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+                _ => {}
+            },
+        }
+    }
+}
+
+async fn send_event(socket: &mut WebSocket, event: &TimerEvent) -> std::result::Result<(), axum::Error> {
+    let text = serde_json::to_string(event).unwrap_or_default();
+    socket.send(Message::Text(text)).await
+}
+
+async fn find_running_entry(state: &AppState, user_id: ObjectId) -> Result<Option<TimeEntry>> {
+    Ok(state
+        .db
+        .time_entries()
+        .find_one(doc! { "user_id": user_id, "end_time": null }, None)
+        .await?)
+}
+
+async fn find_running_entry_by_id(
+    state: &AppState,
+    user_id: ObjectId,
+    id: ObjectId,
+) -> Result<TimeEntry> {
+    let entry = state
+        .db
+        .time_entries()
+        .find_one(doc! { "_id": id, "user_id": user_id }, None)
+        .await?
+        .ok_or(AppError::NotFound("Time entry not found".to_string()))?;
+    if entry.end_time.is_some() {
+        return Err(AppError::BadRequest("Time entry is not running".to_string()));
+    }
+
+    Ok(entry)
+}
+
+async fn record_activity(state: &AppState, entry: &TimeEntry, now: DateTime<Utc>) -> Result<TimeEntry> {
+    state
+        .db
+        .time_entries()
+        .find_one_and_update(
+            doc! { "_id": entry.id, "end_time": null },
+            doc! { "$set": { "last_activity_at": now } },
+            FindOneAndUpdateOptions::builder()
+                .return_document(ReturnDocument::After)
+                .build(),
+        )
+        .await?
+        .ok_or(AppError::BadRequest("Time entry is not running".to_string()))
+}
+
+async fn finish_entry(
+    state: &AppState,
+    user_id: ObjectId,
+    entry: &TimeEntry,
+    end_time: DateTime<Utc>,
+) -> Result<TimeEntry> {
+    let duration = duration_between(entry.start_time, end_time)?;
+
+    let options = FindOneAndUpdateOptions::builder()
+        .return_document(ReturnDocument::After)
+        .build();
+
+    state
+        .db
+        .time_entries()
+        .find_one_and_update(
+            doc! { "_id": entry.id, "user_id": user_id },
+            doc! {
+                "$set": {
+                    "end_time": end_time,
+                    "duration": duration,
+                    "updated_at": Utc::now()
+                }
+            },
+            options,
+        )
+        .await?
+        .ok_or(AppError::NotFound("Time entry not found".to_string()))
+}
@@ -0,0 +1,472 @@
+use std::collections::{BTreeMap, HashMap};
+
+use axum::{
+    extract::{Path, Query, State, Extension},
+    response::Json,
+    routing::{get, post},
+    Router, middleware,
+};
+use chrono::{DateTime, Duration, NaiveDate, Utc};
+use mongodb::{
+    bson::{doc, oid::ObjectId, Document},
+    options::FindOptions,
+    ClientSession,
+};
+
+use crate::{
+    models::{
+        ApproverInvite, ReviewTimesheetRequest, SaveTimesheetRequest, SubmitTimesheetRequest, TimeEntry, Timesheet,
+        TimesheetQuery, TimesheetRow, TimesheetSubmission, TimesheetSubmissionQuery,
+        TimesheetSubmissionResponse,
+    },
+    middleware::{auth_middleware, AuthUser},
+    error::{AppError, Result},
+    services::{
+        approvals,
+        calendar::Calendar,
+        locks::{ensure_open, ensure_unlocked},
+        references::parse_id,
+        settings::user_settings,
+        time_entries::check_overlaps,
+    },
+    AppState,
+};
+
+const DAYS_PER_WEEK: usize = 7;
+
+/// A submitted row: description and billability for new entries, and the
+/// seven daily durations.
+type GridRow = (Option<String>, Option<bool>, Vec<i64>);
+
+pub fn routes() -> Router<AppState> {
+    Router::new()
+        .route("/", get(get_timesheet).put(save_timesheet))
+        .route("/submit", post(submit_timesheet))
+        .route("/submissions", get(list_submissions))
+        .route("/approvals", get(list_approvals))
+        .route("/submissions/:id", get(get_submission))
+        .route("/submissions/:id/approve", post(approve_submission))
+        .route("/submissions/:id/reject", post(reject_submission))
+        .route("/approver-invites", get(list_approver_invites))
+        .route("/approver-invites/:user_id/accept", post(accept_approver_invite))
+        .route("/approver-invites/:user_id/decline", post(decline_approver_invite))
+        .route_layer(middleware::from_fn(auth_middleware))
+}
+
+async fn get_timesheet(
+    State(state): State<AppState>,
+    Extension(auth_user): Extension<AuthUser>,
+    Query(query): Query<TimesheetQuery>,
+) -> Result<Json<Timesheet>> {
+    let calendar = Calendar::load(&state.db, auth_user.user_id).await?;
+    let week_start = calendar.start_of_week(query.week_start.unwrap_or_else(|| calendar.today()));
+
+    Ok(Json(build_timesheet(&state, &calendar, auth_user.user_id, week_start).await?))
+}
+
+/// Replaces the week's grid. Each submitted cell is reconciled against the
+/// stopped entries already logged for that project and day: extra time is
+/// added as a new entry, missing time is trimmed from the latest entries.
+/// Projects without a row in the request are left alone.
+async fn save_timesheet(
+    State(state): State<AppState>,
+    Extension(auth_user): Extension<AuthUser>,
+    Json(payload): Json<SaveTimesheetRequest>,
+) -> Result<Json<Timesheet>> {
+    let calendar = Calendar::load(&state.db, auth_user.user_id).await?;
+    let week_start = calendar.start_of_week(payload.week_start);
+
+    let mut rows = BTreeMap::new();
+    for row in payload.rows {
+        // Archived projects keep their rows, so only ownership is checked
+        let project_id = match row.project_id {
+            Some(id) => {
+                let id = parse_id(&id, "project")?;
+                state
+                    .db
+                    .projects()
+                    .find_one(doc! { "_id": id, "user_id": auth_user.user_id }, None)
+                    .await?
+                    .ok_or(AppError::NotFound("Project not found".to_string()))?;
+                Some(id)
+            }
+            None => None,
+        };
+        if row.durations.len() != DAYS_PER_WEEK {
+            return Err(AppError::BadRequest("Each row needs seven daily durations".to_string()));
+        }
+        if row.durations.iter().any(|&d| !(0..=86_400).contains(&d)) {
+            return Err(AppError::BadRequest(
+                "Daily durations must be between 0 and 86400 seconds".to_string(),
+            ));
+        }
+        if rows.insert(project_id, (row.description, row.is_billable, row.durations)).is_some() {
+            return Err(AppError::BadRequest("Duplicate project row".to_string()));
+        }
+    }
+
+    let mut session = state.db.client.start_session(None).await?;
+    session.start_transaction(None).await?;
+
+    match apply_grid(&state, &calendar, &mut session, auth_user.user_id, week_start, rows).await {
+        Ok(()) => session.commit_transaction().await?,
+        Err(err) => {
+            session.abort_transaction().await?;
+            return Err(err);
+        }
+    }
+    state.timer_events.publish_resync(auth_user.user_id);
+
+    Ok(Json(build_timesheet(&state, &calendar, auth_user.user_id, week_start).await?))
+}
+
+async fn submit_timesheet(
+    State(state): State<AppState>,
+    Extension(auth_user): Extension<AuthUser>,
+    Json(payload): Json<SubmitTimesheetRequest>,
+) -> Result<Json<TimesheetSubmission>> {
+    let submission =
+        approvals::submit(&state.db, auth_user.user_id, payload.week_start, payload.comment).await?;
+    state.timer_events.publish_resync(auth_user.user_id);
+
+    Ok(Json(submission))
+}
+
+/// The user's own submissions, newest week first.
+async fn list_submissions(
+    State(state): State<AppState>,
+    Extension(auth_user): Extension<AuthUser>,
+    Query(query): Query<TimesheetSubmissionQuery>,
+) -> Result<Json<Vec<TimesheetSubmission>>> {
+    let filter = doc! { "user_id": auth_user.user_id };
+    Ok(Json(find_submissions(&state, filter, &query).await?))
+}
+
+/// Submissions waiting on (or decided by) the user as approver.
+async fn list_approvals(
+    State(state): State<AppState>,
+    Extension(auth_user): Extension<AuthUser>,
+    Query(query): Query<TimesheetSubmissionQuery>,
+) -> Result<Json<Vec<TimesheetSubmission>>> {
+    let filter = doc! { "approver_id": auth_user.user_id };
+    Ok(Json(find_submissions(&state, filter, &query).await?))
+}
+
+async fn list_approver_invites(
+    State(state): State<AppState>,
+    Extension(auth_user): Extension<AuthUser>,
+) -> Result<Json<Vec<ApproverInvite>>> {
+    Ok(Json(approvals::approver_invites(&state.db, auth_user.user_id).await?))
+}
+
+async fn accept_approver_invite(
+    State(state): State<AppState>,
+    Extension(auth_user): Extension<AuthUser>,
+    Path(user_id): Path<String>,
+) -> Result<Json<serde_json::Value>> {
+    let user_id = parse_id(&user_id, "user")?;
+    approvals::answer_invite(&state.db, auth_user.user_id, user_id, true).await?;
+    Ok(Json(serde_json::json!({ "message": "Invite accepted" })))
+}
+
+async fn decline_approver_invite(
+    State(state): State<AppState>,
+    Extension(auth_user): Extension<AuthUser>,
+    Path(user_id): Path<String>,
+) -> Result<Json<serde_json::Value>> {
+    let user_id = parse_id(&user_id, "user")?;
+    approvals::answer_invite(&state.db, auth_user.user_id, user_id, false).await?;
+    Ok(Json(serde_json::json!({ "message": "Invite declined" })))
+}
+
+async fn get_submission(
+    State(state): State<AppState>,
+    Extension(auth_user): Extension<AuthUser>,
+    Path(id): Path<String>,
+) -> Result<Json<TimesheetSubmissionResponse>> {
+    let object_id = ObjectId::parse_str(&id)
+        .map_err(|_| AppError::BadRequest("Invalid ID".to_string()))?;
+
+    let submission = state
+        .db
+        .timesheet_submissions()
+        .find_one(
+            doc! {
+                "_id": object_id,
+                "$or": [{ "user_id": auth_user.user_id }, { "approver_id": auth_user.user_id }],
+            },
+            None,
+        )
+        .await?
+        .ok_or(AppError::NotFound("Timesheet submission not found".to_string()))?;
+
+    Ok(Json(approvals::with_entries(&state.db, submission).await?))
+}
+
+async fn approve_submission(
+    State(state): State<AppState>,
+    Extension(auth_user): Extension<AuthUser>,
+    Path(id): Path<String>,
+    Json(payload): Json<ReviewTimesheetRequest>,
+) -> Result<Json<TimesheetSubmission>> {
+    let object_id = ObjectId::parse_str(&id)
+        .map_err(|_| AppError::BadRequest("Invalid ID".to_string()))?;
+
+    let submission =
+        approvals::review(&state.db, auth_user.user_id, object_id, true, payload.comment).await?;
+    // The entries are the submitter's, not the approver's
+    state.timer_events.publish_resync(submission.user_id);
+    Ok(Json(submission))
+}
+
+async fn reject_submission(
+    State(state): State<AppState>,
+    Extension(auth_user): Extension<AuthUser>,
+    Path(id): Path<String>,
+    Json(payload): Json<ReviewTimesheetRequest>,
+) -> Result<Json<TimesheetSubmission>> {
+    let object_id = ObjectId::parse_str(&id)
+        .map_err(|_| AppError::BadRequest("Invalid ID".to_string()))?;
+
+    let submission =
+        approvals::review(&state.db, auth_user.user_id, object_id, false, payload.comment).await?;
+    state.timer_events.publish_resync(submission.user_id);
+    Ok(Json(submission))
+}
+
+async fn find_submissions(
+    state: &AppState,
+    mut filter: Document,
+    query: &TimesheetSubmissionQuery,
+) -> Result<Vec<TimesheetSubmission>> {
+    if let Some(status) = query.status {
+        filter.insert("status", bson::to_bson(&status)?);
+    }
+
+    let options = FindOptions::builder().sort(doc! { "week_start": -1 }).build();
+    let mut cursor = state.db.timesheet_submissions().find(filter, options).await?;
+    let mut submissions = Vec::new();
+    while cursor.advance().await? {
+        submissions.push(cursor.deserialize_current()?);
+    }
+
+    Ok(submissions)
+}
+
+async fn apply_grid(
+    state: &AppState,
+    calendar: &Calendar,
+    session: &mut ClientSession,
+    user_id: ObjectId,
+    week_start: NaiveDate,
+    rows: BTreeMap<Option<ObjectId>, GridRow>,
+) -> Result<()> {
+    let collection = state.db.time_entries();
+    let settings = user_settings(&state.db, user_id).await?;
+    let (from, to) = week_bounds(calendar, week_start);
+
+    let options = FindOptions::builder().sort(doc! { "start_time": 1 }).build();
+    let mut cursor = collection
+        .find_with_session(
+            doc! {
+                "user_id": user_id,
+                "start_time": { "$gte": from, "$lt": to },
+                "end_time": { "$ne": null },
+                "draft": { "$ne": true },
+            },
+            options,
+            session,
+        )
+        .await?;
+
+    let mut cells: HashMap<(Option<ObjectId>, usize), Vec<TimeEntry>> = HashMap::new();
+    // New entries on a day are placed after everything already logged that day
+    let mut day_cursors: Vec<DateTime<Utc>> = (0..DAYS_PER_WEEK)
+        .map(|day| day_start(calendar, week_start, day) + Duration::hours(9))
+        .collect();
+    while let Some(entry) = cursor.next(session).await.transpose()? {
+        let day = day_index(calendar, week_start, entry.start_time);
+        if let Some(end_time) = entry.end_time {
+            day_cursors[day] = day_cursors[day].max(end_time);
+        }
+        cells.entry((entry.project_id, day)).or_default().push(entry);
+    }
+
+    // A running timer extends to now on every day since it started
+    let running = collection
+        .find_one_with_session(doc! { "user_id": user_id, "end_time": null }, None, session)
+        .await?;
+    if let Some(running) = running {
+        let now = Utc::now();
+        for (day, day_cursor) in day_cursors.iter_mut().enumerate() {
+            if running.start_time < day_start(calendar, week_start, day + 1) {
+                *day_cursor = (*day_cursor).max(now);
+            }
+        }
+    }
+
+    for (project_id, (description, is_billable, durations)) in rows {
+        for (day, &wanted) in durations.iter().enumerate() {
+            let entries = cells.remove(&(project_id, day)).unwrap_or_default();
+            let logged: i64 = entries.iter().filter_map(|e| e.duration).sum();
+            if wanted != logged {
+                entries.iter().try_for_each(ensure_unlocked)?;
+            }
+
+            if wanted > logged {
+                let extra = wanted - logged;
+                let latest_start = day_start(calendar, week_start, day + 1) - Duration::seconds(extra);
+                let start_time = day_cursors[day].min(latest_start);
+                // Extra time is billed like the time already in the cell
+                let latest = entries.last();
+                let end_time = start_time + Duration::seconds(extra);
+                ensure_open(&settings, start_time)?;
+                check_overlaps(&state.db, user_id, None, start_time, Some(end_time)).await?;
+                day_cursors[day] = day_cursors[day].max(end_time);
+
+                let entry = TimeEntry {
+                    id: None,
+                    user_id,
+                    project_id,
+                    task_id: None,
+                    tags: Vec::new(),
+                    description: description.clone().unwrap_or_default(),
+                    start_time,
+                    end_time: Some(end_time),
+                    duration: Some(extra),
+                    is_billable: is_billable
+                        .or(latest.map(|entry| entry.is_billable))
+                        .unwrap_or(true),
+                    hourly_rate: latest.and_then(|entry| entry.hourly_rate),
+                    invoice_id: None,
+                    locked: false,
+                    lock_reason: None,
+                    unlocks: Vec::new(),
+                    draft: false,
+                    external_id: None,
+                    last_activity_at: None,
+                    auto_stopped: false,
+                    approval_status: None,
+                    created_at: Utc::now(),
+                    updated_at: Utc::now(),
+                };
+                collection.insert_one_with_session(&entry, None, session).await?;
+            } else if wanted < logged {
+                let mut excess = logged - wanted;
+                for entry in entries.iter().rev() {
+                    if excess == 0 {
+                        break;
+                    }
+                    let duration = entry.duration.unwrap_or(0);
+                    if duration <= excess {
+                        collection
+                            .delete_one_with_session(doc! { "_id": entry.id }, None, session)
+                            .await?;
+                        excess -= duration;
+                    } else {
+                        let duration = duration - excess;
+                        collection
+                            .update_one_with_session(
+                                doc! { "_id": entry.id },
+                                doc! {
+                                    "$set": {
+                                        "end_time": entry.start_time + Duration::seconds(duration),
+                                        "duration": duration,
+                                        "updated_at": Utc::now(),
+                                    }
+                                },
+                                None,
+                                session,
+                            )
+                            .await?;
+                        excess = 0;
+                    }
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+async fn build_timesheet(
+    state: &AppState,
+    calendar: &Calendar,
+    user_id: ObjectId,
+    week_start: NaiveDate,
+) -> Result<Timesheet> {
+    let (from, to) = week_bounds(calendar, week_start);
+
+    let mut cursor = state
+        .db
+        .time_entries()
+        .find(
+            doc! {
+                "user_id": user_id,
+                "start_time": { "$gte": from, "$lt": to },
+                "end_time": { "$ne": null },
+                "draft": { "$ne": true },
+            },
+            None,
+        )
+        .await?;
+
+    let mut grid: BTreeMap<Option<ObjectId>, Vec<i64>> = BTreeMap::new();
+    while cursor.advance().await? {
+        let entry: TimeEntry = cursor.deserialize_current()?;
+        let day = day_index(calendar, week_start, entry.start_time);
+        grid.entry(entry.project_id).or_insert_with(|| vec![0; DAYS_PER_WEEK])[day] +=
+            entry.duration.unwrap_or(0);
+    }
+
+    let mut project_names = HashMap::new();
+    let mut cursor = state.db.projects().find(doc! { "user_id": user_id }, None).await?;
+    while cursor.advance().await? {
+        let project = cursor.deserialize_current()?;
+        project_names.insert(project.id.unwrap(), project.name);
+    }
+
+    let mut daily_totals = vec![0; DAYS_PER_WEEK];
+    let rows: Vec<TimesheetRow> = grid
+        .into_iter()
+        .map(|(project_id, durations)| {
+            for (total, duration) in daily_totals.iter_mut().zip(&durations) {
+                *total += duration;
+            }
+            TimesheetRow {
+                project_name: project_id.and_then(|id| project_names.get(&id).cloned()),
+                project_id,
+                total: durations.iter().sum(),
+                durations,
+            }
+        })
+        .collect();
+
+    let approval_status = state
+        .db
+        .timesheet_submissions()
+        .find_one(doc! { "user_id": user_id, "week_start": week_start.to_string() }, None)
+        .await?
+        .map(|submission| submission.status);
+
+    Ok(Timesheet {
+        week_start,
+        days: (0..DAYS_PER_WEEK as i64).map(|d| week_start + Duration::days(d)).collect(),
+        total: daily_totals.iter().sum(),
+        daily_totals,
+        rows,
+        approval_status,
+    })
+}
+
+fn day_start(calendar: &Calendar, week_start: NaiveDate, day: usize) -> DateTime<Utc> {
+    calendar.day_start(week_start + Duration::days(day as i64))
+}
+
+fn week_bounds(calendar: &Calendar, week_start: NaiveDate) -> (DateTime<Utc>, DateTime<Utc>) {
+    (day_start(calendar, week_start, 0), day_start(calendar, week_start, DAYS_PER_WEEK))
+}
+
+fn day_index(calendar: &Calendar, week_start: NaiveDate, time: DateTime<Utc>) -> usize {
+    (calendar.local_date(time) - week_start).num_days().clamp(0, DAYS_PER_WEEK as i64 - 1) as usize
+}
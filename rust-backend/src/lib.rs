@@ -0,0 +1,3 @@
+//! Library target so the `orbix-cli` binary can reuse the API's model types.
+
+pub mod models;
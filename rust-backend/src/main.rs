@@ -1,12 +1,11 @@
 use axum::{
-    routing::{get, post, put, delete},
+    routing::get,
     Router,
 };
 use std::net::SocketAddr;
 use tower_http::cors::{CorsLayer, Any};
 
 mod config;
-mod models;
 mod handlers;
 mod middleware;
 mod database;
@@ -15,11 +14,14 @@ mod error;
 
 use config::Config;
 use database::Database;
+// Shared with the `orbix-cli` binary through the library target
+use orbix_backend::models;
 
 #[derive(Clone)]
 pub struct AppState {
     pub db: Database,
     pub config: Config,
+    pub timer_events: services::timer_events::TimerEvents,
 }
 
 #[tokio::main]
@@ -28,11 +30,44 @@ async fn main() -> anyhow::Result<()> {
     
     let config = Config::from_env()?;
     let db = Database::connect(&config.mongodb_uri).await?;
+
+    // Maintenance: `orbix-backend repair-durations [--dry-run]`
+    if std::env::args().nth(1).as_deref() == Some("repair-durations") {
+        let dry_run = std::env::args().any(|arg| arg == "--dry-run");
+        let report = services::time_entries::repair_durations(&db, dry_run).await?;
+        println!(
+            "🔧 Checked {} time entries: {} recomputed, {} swapped, {} cleared{}",
+            report.checked,
+            report.recomputed,
+            report.swapped,
+            report.cleared,
+            if dry_run { " (dry run)" } else { "" }
+        );
+        return Ok(());
+    }
+
+    db.migrate_timestamps().await?;
+    let migrated = services::contacts::migrate_single_contacts(&db).await?;
+    if migrated > 0 {
+        println!("📇 Moved {} client emails into contacts", migrated);
+    }
+    // The unique timer and tag indexes can't be built while duplicates exist
+    let stopped = services::time_entries::stop_duplicate_timers(&db).await?;
+    if stopped > 0 {
+        println!("⏱️  Stopped {} extra running timers", stopped);
+    }
+    let merged = services::tags::merge_duplicate_tags(&db).await?;
+    if merged > 0 {
+        println!("🏷️  Merged {} duplicate tags", merged);
+    }
+    db.ensure_indexes().await?;
     
     let app_state = AppState {
         db: db.clone(),
         config: config.clone(),
+        timer_events: Default::default(),
     };
+    tokio::spawn(services::idle::run_auto_stop(db.clone(), app_state.timer_events.clone()));
 
     let app = Router::new()
         .route("/health", get(health_check))
@@ -43,6 +78,11 @@ async fn main() -> anyhow::Result<()> {
         .nest("/api/contracts", handlers::contracts::routes())
         .nest("/api/resumes", handlers::resumes::routes())
         .nest("/api/projects", handlers::projects::routes())
+        .nest("/api/reports", handlers::reports::routes())
+        .nest("/api/settings", handlers::settings::routes())
+        .nest("/api/timesheets", handlers::timesheets::routes())
+        .nest("/api/tags", handlers::tags::routes())
+        .nest("/api/calendar", handlers::calendar::routes())
         .layer(axum::Extension(app_state.clone()))
         .layer(
             CorsLayer::new()
@@ -1,6 +1,5 @@
 use axum::{
-    extract::{Request, State},
-    http::StatusCode,
+    extract::Request,
     middleware::Next,
     response::Response,
 };
@@ -38,16 +37,21 @@ pub async fn auth_middleware(
         .strip_prefix("Bearer ")
         .ok_or(AppError::Unauthorized)?;
 
+    let user_id = verify_token(token, &state.config.jwt_secret)?;
+
+    req.extensions_mut().insert(AuthUser { user_id });
+
+    Ok(next.run(req).await)
+}
+
+/// Checks a JWT and returns the user it was issued to. Used directly by
+/// endpoints that can't take the token from the `Authorization` header.
+pub fn verify_token(token: &str, secret: &str) -> Result<ObjectId, AppError> {
     let token_data = decode::<Claims>(
         token,
-        &DecodingKey::from_secret(state.config.jwt_secret.as_ref()),
+        &DecodingKey::from_secret(secret.as_ref()),
         &Validation::default(),
     )?;
 
-    let user_id = ObjectId::parse_str(&token_data.claims.sub)
-        .map_err(|_| AppError::Unauthorized)?;
-
-    req.extensions_mut().insert(AuthUser { user_id });
-
-    Ok(next.run(req).await)
+    ObjectId::parse_str(&token_data.claims.sub).map_err(|_| AppError::Unauthorized)
 }
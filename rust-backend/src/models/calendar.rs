@@ -0,0 +1,64 @@
+use serde::{Deserialize, Serialize};
+use bson::oid::ObjectId;
+use chrono::{DateTime, Utc};
+
+/// Maps calendar events whose summary or description contains `keyword`
+/// (case-insensitive) to a project. The first matching rule wins.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct CalendarRule {
+    pub keyword: String,
+    pub project_id: ObjectId,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CalendarRuleRequest {
+    pub keyword: String,
+    pub project_id: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SaveCalendarRulesRequest {
+    pub rules: Vec<CalendarRuleRequest>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct CalendarFeedResponse {
+    pub url: String, // path of the feed; anyone holding it can read the calendar
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ImportCalendarRequest {
+    pub ics: String,
+    pub from: DateTime<Utc>,
+    pub to: DateTime<Utc>,
+    #[serde(default)]
+    pub dry_run: bool,
+}
+
+#[derive(Debug, Serialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum CalendarEventStatus {
+    Import,
+    Duplicate,
+    Skipped,
+}
+
+#[derive(Debug, Serialize)]
+pub struct CalendarImportEvent {
+    pub uid: Option<String>,
+    pub summary: String,
+    pub start_time: DateTime<Utc>,
+    pub end_time: DateTime<Utc>,
+    pub project_id: Option<ObjectId>,
+    pub status: CalendarEventStatus,
+    pub message: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ImportCalendarResponse {
+    pub dry_run: bool,
+    pub imported: usize,
+    pub duplicates: usize,
+    pub skipped: usize,
+    pub events: Vec<CalendarImportEvent>,
+}
@@ -2,6 +2,8 @@ use serde::{Deserialize, Serialize};
 use bson::oid::ObjectId;
 use chrono::{DateTime, Utc};
 
+use super::{PostalAddress, RoundingRule, TaxIdType};
+
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct Client {
     #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
@@ -12,8 +14,33 @@ pub struct Client {
     pub phone: Option<String>,
     pub company: Option<String>,
     pub address: Option<String>,
+    /// Structured address printed on invoices; `address` is free text
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub billing_address: Option<PostalAddress>,
+    /// ISO 3166-1 alpha-2 code of the country the client is established in
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub country: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tax_id_type: Option<TaxIdType>,
+    /// Normalized, e.g. `DE123456789`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tax_id: Option<String>,
     pub notes: Option<String>,
+    #[serde(default)]
+    pub retainer_balance: f64,
+    pub time_rounding: Option<RoundingRule>,
+    pub default_hourly_rate: Option<f64>,
+    /// ISO 4217 code the client is billed in; `billing::DEFAULT_CURRENCY`
+    /// when unset
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub currency: Option<String>,
+    /// Set while archived; archived clients are left out of lists and can't
+    /// take new records
+    #[serde(default, skip_serializing_if = "Option::is_none", with = "super::datetime::optional_bson_datetime")]
+    pub archived_at: Option<DateTime<Utc>>,
+    #[serde(with = "super::datetime::bson_datetime")]
     pub created_at: DateTime<Utc>,
+    #[serde(with = "super::datetime::bson_datetime")]
     pub updated_at: DateTime<Utc>,
 }
 
@@ -24,7 +51,14 @@ pub struct CreateClientRequest {
     pub phone: Option<String>,
     pub company: Option<String>,
     pub address: Option<String>,
+    pub billing_address: Option<PostalAddress>,
+    pub country: Option<String>,     // empty to clear
+    pub tax_id_type: Option<TaxIdType>,
+    pub tax_id: Option<String>,      // empty to clear
     pub notes: Option<String>,
+    pub time_rounding: Option<RoundingRule>,
+    pub default_hourly_rate: Option<f64>,
+    pub currency: Option<String>,    // empty to clear
 }
 
 #[derive(Debug, Deserialize)]
@@ -34,5 +68,35 @@ pub struct UpdateClientRequest {
     pub phone: Option<String>,
     pub company: Option<String>,
     pub address: Option<String>,
+    pub billing_address: Option<PostalAddress>,
+    pub country: Option<String>,     // empty to clear
+    pub tax_id_type: Option<TaxIdType>,
+    pub tax_id: Option<String>,      // empty to clear
     pub notes: Option<String>,
+    pub time_rounding: Option<RoundingRule>,
+    pub default_hourly_rate: Option<f64>,
+    pub currency: Option<String>,    // empty to clear
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ClientListQuery {
+    pub include_archived: Option<bool>,
+}
+
+/// Invoice amounts of one currency
+#[derive(Debug, Serialize, Default)]
+pub struct CurrencyTotals {
+    pub currency: String,
+    pub billed: f64, // every invoice that isn't a draft
+    pub paid: f64,
+    pub outstanding: f64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ClientMetrics {
+    pub client_id: ObjectId,
+    pub totals: Vec<CurrencyTotals>,
+    pub hours: f64, // on the client's projects
+    pub last_activity_at: Option<DateTime<Utc>>, // latest invoice or time entry
+    pub average_days_to_pay: Option<f64>,
 }
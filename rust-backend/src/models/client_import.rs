@@ -0,0 +1,83 @@
+use serde::{Deserialize, Serialize};
+use bson::oid::ObjectId;
+
+/// CSV header to read each client field from. Unmapped fields are read from
+/// the column named like the field, which is also what the CSV export
+/// writes.
+#[derive(Debug, Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+pub struct ClientColumns {
+    pub name: Option<String>,
+    pub email: Option<String>,
+    pub phone: Option<String>,
+    pub company: Option<String>,
+    pub address: Option<String>,
+    pub billing_line1: Option<String>,
+    pub billing_line2: Option<String>,
+    pub billing_city: Option<String>,
+    pub billing_postal_code: Option<String>,
+    pub billing_region: Option<String>,
+    pub billing_country: Option<String>,
+    pub country: Option<String>,
+    pub tax_id_type: Option<String>,
+    pub tax_id: Option<String>,
+    pub default_hourly_rate: Option<String>,
+    pub currency: Option<String>,
+    pub notes: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ImportClientsRequest {
+    pub csv: String,
+    #[serde(default)]
+    pub columns: ClientColumns,
+    #[serde(default)]
+    pub dry_run: bool,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ImportVcardRequest {
+    pub vcard: String,
+    #[serde(default)]
+    pub dry_run: bool,
+}
+
+#[derive(Debug, Serialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum ClientImportStatus {
+    Import,
+    Duplicate,
+    Error,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ClientImportRow {
+    pub line: usize, // CSV line, or position of the card in the file
+    pub status: ClientImportStatus,
+    pub name: String,
+    pub email: Option<String>,
+    pub client_id: Option<ObjectId>, // the created client, or the one a duplicate matches
+    pub message: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ImportClientsResponse {
+    pub dry_run: bool,
+    pub imported: usize,
+    pub duplicates: usize,
+    pub errors: usize,
+    pub rows: Vec<ClientImportRow>,
+}
+
+#[derive(Debug, Deserialize, Clone, Copy, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum ClientExportFormat {
+    #[default]
+    Csv,
+    Vcard,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ClientExportQuery {
+    pub format: Option<ClientExportFormat>,
+}
@@ -0,0 +1,68 @@
+use serde::{Deserialize, Serialize};
+use bson::oid::ObjectId;
+use chrono::{DateTime, Utc};
+
+#[derive(Debug, Serialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum DuplicateReason {
+    SimilarName,
+    SameEmailDomain,
+}
+
+#[derive(Debug, Serialize)]
+pub struct DuplicateCandidate {
+    pub id: ObjectId,
+    pub name: String,
+    pub company: Option<String>,
+    pub email: String,
+}
+
+/// Clients that look like the same customer, most similar names first.
+#[derive(Debug, Serialize)]
+pub struct DuplicateGroup {
+    pub clients: Vec<DuplicateCandidate>,
+    pub reasons: Vec<DuplicateReason>,
+    pub score: f64, // best name similarity in the group, 0.0 - 1.0
+}
+
+#[derive(Debug, Deserialize)]
+pub struct DuplicateClientsQuery {
+    pub min_similarity: Option<f64>, // 0.0 - 1.0, defaults to 0.85
+    #[serde(default)]
+    pub include_archived: bool,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct MergeClientsRequest {
+    pub merge_ids: Vec<String>, // clients folded into the one in the path, then deleted
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct MergedClient {
+    pub id: ObjectId,
+    pub name: String,
+    pub email: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct MergeCounts {
+    pub invoices: u64,
+    pub contracts: u64,
+    pub projects: u64,
+    pub time_entries: u64, // on the moved projects
+    pub contacts: u64,
+}
+
+/// A record of one merge. The merged clients no longer exist.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ClientMerge {
+    #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
+    pub id: Option<ObjectId>,
+    pub user_id: ObjectId,
+    pub survivor_id: ObjectId,
+    pub merged: Vec<MergedClient>,
+    pub moved: MergeCounts,
+    pub filled_fields: Vec<String>, // survivor fields taken from a merged client
+    #[serde(with = "super::datetime::bson_datetime")]
+    pub created_at: DateTime<Utc>,
+}
@@ -0,0 +1,78 @@
+use serde::{Deserialize, Serialize};
+use bson::oid::ObjectId;
+use chrono::{DateTime, Utc};
+
+/// A person at a client. The client's primary contact is mirrored into the
+/// client's own `email` and `phone`.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ClientContact {
+    #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
+    pub id: Option<ObjectId>,
+    pub user_id: ObjectId,
+    pub client_id: ObjectId,
+    pub name: String,
+    pub email: String,
+    pub phone: Option<String>,
+    pub title: Option<String>, // job title
+    #[serde(default)]
+    pub roles: Vec<ContactRole>,
+    #[serde(default)]
+    pub is_primary: bool,
+    #[serde(with = "super::datetime::bson_datetime")]
+    pub created_at: DateTime<Utc>,
+    #[serde(with = "super::datetime::bson_datetime")]
+    pub updated_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum ContactRole {
+    Billing,
+    AccountsPayable,
+    ProjectLead,
+    Legal,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CreateContactRequest {
+    pub name: String,
+    pub email: String,
+    pub phone: Option<String>,
+    pub title: Option<String>,
+    #[serde(default)]
+    pub roles: Vec<ContactRole>,
+    #[serde(default)]
+    pub is_primary: bool,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct UpdateContactRequest {
+    pub name: Option<String>,
+    pub email: Option<String>,
+    pub phone: Option<String>,
+    pub title: Option<String>,
+    pub roles: Option<Vec<ContactRole>>,
+    pub is_primary: Option<bool>, // only `true` is accepted; make another contact primary instead
+}
+
+/// What is being sent to the client, which decides who receives it.
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum MessagePurpose {
+    Invoice,  // billing contacts, accounts payable in copy
+    Contract, // legal contacts, the primary contact in copy
+    Project,  // project leads
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RecipientsQuery {
+    pub purpose: MessagePurpose,
+}
+
+/// Without a contact in the role, mail goes to the primary contact, then
+/// to the client's own email.
+#[derive(Debug, Serialize)]
+pub struct Recipients {
+    pub to: Vec<String>,
+    pub cc: Vec<String>,
+}
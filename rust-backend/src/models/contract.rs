@@ -11,12 +11,17 @@ pub struct Contract {
     pub title: String,
     pub content: String,
     pub status: ContractStatus,
+    #[serde(with = "super::datetime::bson_datetime")]
     pub start_date: DateTime<Utc>,
+    #[serde(default, with = "super::datetime::optional_bson_datetime")]
     pub end_date: Option<DateTime<Utc>>,
     pub value: Option<f64>,
     pub currency: Option<String>,
+    #[serde(default, with = "super::datetime::optional_bson_datetime")]
     pub signed_date: Option<DateTime<Utc>>,
+    #[serde(with = "super::datetime::bson_datetime")]
     pub created_at: DateTime<Utc>,
+    #[serde(with = "super::datetime::bson_datetime")]
     pub updated_at: DateTime<Utc>,
 }
 
@@ -44,6 +49,7 @@ pub struct CreateContractRequest {
 
 #[derive(Debug, Deserialize)]
 pub struct UpdateContractRequest {
+    pub client_id: Option<String>,
     pub title: Option<String>,
     pub content: Option<String>,
     pub status: Option<ContractStatus>,
@@ -0,0 +1,68 @@
+//! Serde helpers for timestamps stored in MongoDB.
+//!
+//! chrono serializes `DateTime<Utc>` as an RFC 3339 string, so documents
+//! written with `insert_one` held strings while `doc!` updates and range
+//! filters used native BSON dates, and the two never compared. These helpers
+//! write BSON dates when the driver serializes a document (which it does in
+//! non-human-readable mode) and keep RFC 3339 strings in JSON. Documents that
+//! still hold strings load fine.
+
+use chrono::{DateTime, Utc};
+use mongodb::bson::Bson;
+use serde::{de::Error, Deserialize, Deserializer, Serialize, Serializer};
+
+fn from_bson<E: Error>(value: Bson) -> Result<DateTime<Utc>, E> {
+    match value {
+        Bson::DateTime(date) => Ok(date.to_chrono()),
+        Bson::String(text) => DateTime::parse_from_rfc3339(&text)
+            .map(|date| date.with_timezone(&Utc))
+            .map_err(E::custom),
+        other => Err(E::custom(format!("expected a date, found {}", other))),
+    }
+}
+
+pub mod bson_datetime {
+    use super::*;
+
+    pub fn serialize<S: Serializer>(date: &DateTime<Utc>, serializer: S) -> Result<S::Ok, S::Error> {
+        if serializer.is_human_readable() {
+            date.serialize(serializer)
+        } else {
+            mongodb::bson::DateTime::from_chrono(*date).serialize(serializer)
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<DateTime<Utc>, D::Error> {
+        if deserializer.is_human_readable() {
+            DateTime::deserialize(deserializer)
+        } else {
+            from_bson(Bson::deserialize(deserializer)?)
+        }
+    }
+}
+
+pub mod optional_bson_datetime {
+    use super::*;
+
+    pub fn serialize<S: Serializer>(
+        date: &Option<DateTime<Utc>>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        match date {
+            Some(date) if !serializer.is_human_readable() => {
+                serializer.serialize_some(&mongodb::bson::DateTime::from_chrono(*date))
+            }
+            date => date.serialize(serializer),
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Option<DateTime<Utc>>, D::Error> {
+        if deserializer.is_human_readable() {
+            Option::deserialize(deserializer)
+        } else {
+            Option::<Bson>::deserialize(deserializer)?.map(from_bson).transpose()
+        }
+    }
+}
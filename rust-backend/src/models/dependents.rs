@@ -0,0 +1,56 @@
+use serde::{Deserialize, Serialize};
+use bson::oid::ObjectId;
+
+/// What to do with the records still pointing at a client or project being
+/// deleted.
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum DependentsAction {
+    /// Refuse with 409 and the list of dependents
+    #[default]
+    Refuse,
+    /// Archive the client or project (and a client's projects) instead of
+    /// deleting it, so references stay valid
+    Archive,
+    /// Move the dependents to `reassign_to`, then delete
+    Reassign,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct DeleteQuery {
+    #[serde(default)]
+    pub dependents: DependentsAction,
+    pub reassign_to: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct DependentRef {
+    pub id: ObjectId,
+    pub label: String,
+}
+
+/// Records referencing a client or project. Time entries are only counted.
+#[derive(Debug, Serialize, Default)]
+pub struct Dependents {
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub invoices: Vec<DependentRef>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub contracts: Vec<DependentRef>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub projects: Vec<DependentRef>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub tasks: Vec<DependentRef>,
+    pub retainer_transactions: u64,
+    pub time_entries: u64,
+}
+
+impl Dependents {
+    pub fn is_empty(&self) -> bool {
+        self.invoices.is_empty()
+            && self.contracts.is_empty()
+            && self.projects.is_empty()
+            && self.tasks.is_empty()
+            && self.retainer_transactions == 0
+            && self.time_entries == 0
+    }
+}
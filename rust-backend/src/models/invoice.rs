@@ -17,18 +17,35 @@ pub struct Invoice {
     pub user_id: ObjectId,
     pub client_id: ObjectId,
     pub invoice_number: String,
+    #[serde(with = "super::datetime::bson_datetime")]
     pub date: DateTime<Utc>,
+    #[serde(with = "super::datetime::bson_datetime")]
     pub due_date: DateTime<Utc>,
     pub items: Vec<InvoiceItem>,
     pub subtotal: f64,
     pub tax: f64,
     pub discount: f64,
+    #[serde(default)]
+    pub retainer_applied: f64,
     pub total: f64,
+    /// Intra-EU supply on which the client accounts for VAT; tax is zero
+    #[serde(default)]
+    pub reverse_charge: bool,
+    /// Tax IDs of both parties as they were when the invoice was issued
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub seller_tax_id: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub client_tax_id: Option<String>,
     pub currency: String,
     pub status: InvoiceStatus,
     pub notes: Option<String>,
     pub payment_terms: Option<String>,
+    /// When the invoice was marked paid
+    #[serde(default, skip_serializing_if = "Option::is_none", with = "super::datetime::optional_bson_datetime")]
+    pub paid_at: Option<DateTime<Utc>>,
+    #[serde(with = "super::datetime::bson_datetime")]
     pub created_at: DateTime<Utc>,
+    #[serde(with = "super::datetime::bson_datetime")]
     pub updated_at: DateTime<Utc>,
 }
 
@@ -44,10 +61,13 @@ pub enum InvoiceStatus {
 #[derive(Debug, Deserialize)]
 pub struct CreateInvoiceRequest {
     pub client_id: String,
+    #[serde(default)]
     pub items: Vec<InvoiceItem>,
+    pub time_entry_ids: Option<Vec<String>>, // billed as one line each, then locked
     pub due_date: DateTime<Utc>,
     pub tax: Option<f64>,
     pub discount: Option<f64>,
+    pub apply_retainer: Option<f64>, // amount to draw from the client's retainer
     pub currency: Option<String>,
     pub notes: Option<String>,
     pub payment_terms: Option<String>,
@@ -1,15 +1,40 @@
+pub mod datetime;
 pub mod user;
 pub mod client;
+pub mod client_import;
+pub mod client_merge;
+pub mod contact;
 pub mod invoice;
 pub mod time_entry;
 pub mod project;
 pub mod contract;
 pub mod resume;
+pub mod report;
+pub mod retainer;
+pub mod time_import;
+pub mod timesheet;
+pub mod tag;
+pub mod task;
+pub mod calendar;
+pub mod dependents;
+pub mod tax;
 
 pub use user::*;
 pub use client::*;
+pub use client_import::*;
+pub use client_merge::*;
+pub use contact::*;
 pub use invoice::*;
 pub use time_entry::*;
 pub use project::*;
 pub use contract::*;
 pub use resume::*;
+pub use report::*;
+pub use retainer::*;
+pub use time_import::*;
+pub use timesheet::*;
+pub use tag::*;
+pub use task::*;
+pub use calendar::*;
+pub use dependents::*;
+pub use tax::*;
@@ -2,6 +2,8 @@ use serde::{Deserialize, Serialize};
 use bson::oid::ObjectId;
 use chrono::{DateTime, Utc};
 
+use super::RoundingRule;
+
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct Project {
     #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
@@ -14,9 +16,17 @@ pub struct Project {
     pub status: ProjectStatus,
     pub hourly_rate: Option<f64>,
     pub budget: Option<f64>,
+    #[serde(default, with = "super::datetime::optional_bson_datetime")]
     pub start_date: Option<DateTime<Utc>>,
+    #[serde(default, with = "super::datetime::optional_bson_datetime")]
     pub end_date: Option<DateTime<Utc>>,
+    pub time_rounding: Option<RoundingRule>,
+    /// Set when archived instead of deleted because other records use it
+    #[serde(default, skip_serializing_if = "Option::is_none", with = "super::datetime::optional_bson_datetime")]
+    pub archived_at: Option<DateTime<Utc>>,
+    #[serde(with = "super::datetime::bson_datetime")]
     pub created_at: DateTime<Utc>,
+    #[serde(with = "super::datetime::bson_datetime")]
     pub updated_at: DateTime<Utc>,
 }
 
@@ -29,6 +39,11 @@ pub enum ProjectStatus {
     Cancelled,
 }
 
+#[derive(Debug, Deserialize)]
+pub struct ProjectListQuery {
+    pub include_archived: Option<bool>,
+}
+
 #[derive(Debug, Deserialize)]
 pub struct CreateProjectRequest {
     pub client_id: Option<String>,
@@ -37,14 +52,17 @@ pub struct CreateProjectRequest {
     pub hourly_rate: Option<f64>,
     pub budget: Option<f64>,
     pub start_date: Option<DateTime<Utc>>,
+    pub time_rounding: Option<RoundingRule>,
 }
 
 #[derive(Debug, Deserialize)]
 pub struct UpdateProjectRequest {
+    pub client_id: Option<String>,
     pub name: Option<String>,
     pub description: Option<String>,
     pub status: Option<ProjectStatus>,
     pub hourly_rate: Option<f64>,
     pub budget: Option<f64>,
     pub end_date: Option<DateTime<Utc>>,
+    pub time_rounding: Option<RoundingRule>,
 }
@@ -0,0 +1,88 @@
+use serde::{Deserialize, Serialize};
+use bson::oid::ObjectId;
+use chrono::{DateTime, Utc};
+
+use super::TimeEntryGrouping;
+
+#[derive(Debug, Deserialize, Clone, Copy, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum ReportFormat {
+    #[default]
+    Json,
+    Csv,
+    Pdf,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ReportFormatQuery {
+    pub format: Option<ReportFormat>,
+}
+
+/// Billable amount in one currency
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct CurrencyAmount {
+    pub currency: String,
+    pub amount: f64,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct ReportTotals {
+    pub hours: f64,
+    pub billable_hours: f64, // after rounding rules
+    pub billable_amounts: Vec<CurrencyAmount>, // by currency code
+    pub non_billable_share: f64, // fraction of tracked hours, 0.0 - 1.0
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SummaryReportRow {
+    pub key: Option<String>,
+    pub label: String,
+    #[serde(flatten)]
+    pub totals: ReportTotals,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SummaryReport {
+    pub from: Option<DateTime<Utc>>,
+    pub to: Option<DateTime<Utc>>,
+    pub group_by: TimeEntryGrouping,
+    pub timezone: String,
+    pub period: String, // `from` - `to` as local dates in the user's format
+    pub rows: Vec<SummaryReportRow>,
+    pub totals: ReportTotals,
+}
+
+#[derive(Debug, Serialize)]
+pub struct DetailedReportLine {
+    pub entry_id: ObjectId,
+    pub start_time: DateTime<Utc>,
+    pub date: String, // local date in the user's format
+    pub project: Option<String>,
+    pub client: Option<String>,
+    pub description: String,
+    pub hours: f64,
+    pub billable_hours: f64,
+    pub hourly_rate: Option<f64>,
+    pub amount: f64,
+    pub currency: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct DetailedReportGroup {
+    pub key: Option<String>,
+    pub label: String,
+    pub lines: Vec<DetailedReportLine>,
+    #[serde(flatten)]
+    pub totals: ReportTotals,
+}
+
+#[derive(Debug, Serialize)]
+pub struct DetailedReport {
+    pub from: Option<DateTime<Utc>>,
+    pub to: Option<DateTime<Utc>>,
+    pub group_by: TimeEntryGrouping,
+    pub timezone: String,
+    pub period: String,
+    pub groups: Vec<DetailedReportGroup>,
+    pub totals: ReportTotals,
+}
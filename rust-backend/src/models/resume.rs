@@ -16,7 +16,9 @@ pub struct Resume {
     pub certifications: Option<Vec<Certification>>,
     pub languages: Option<Vec<Language>>,
     pub template: String,
+    #[serde(with = "super::datetime::bson_datetime")]
     pub created_at: DateTime<Utc>,
+    #[serde(with = "super::datetime::bson_datetime")]
     pub updated_at: DateTime<Utc>,
 }
 
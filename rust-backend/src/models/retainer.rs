@@ -0,0 +1,45 @@
+use serde::{Deserialize, Serialize};
+use bson::oid::ObjectId;
+use chrono::{DateTime, Utc};
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct RetainerTransaction {
+    #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
+    pub id: Option<ObjectId>,
+    pub user_id: ObjectId,
+    pub client_id: ObjectId,
+    pub kind: RetainerTransactionKind,
+    pub amount: f64, // always positive, direction comes from `kind`
+    pub balance_after: f64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub invoice_id: Option<ObjectId>,
+    pub reference: Option<String>,
+    pub notes: Option<String>,
+    #[serde(with = "super::datetime::bson_datetime")]
+    pub date: DateTime<Utc>,
+    #[serde(with = "super::datetime::bson_datetime")]
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum RetainerTransactionKind {
+    Deposit,
+    Drawdown,
+    Reversal, // a draw-down credited back when its invoice returns to draft
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RecordRetainerDepositRequest {
+    pub amount: f64,
+    pub date: Option<DateTime<Utc>>,
+    pub reference: Option<String>,
+    pub notes: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct RetainerLedgerResponse {
+    pub client_id: String,
+    pub balance: f64,
+    pub transactions: Vec<RetainerTransaction>,
+}
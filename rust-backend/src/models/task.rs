@@ -0,0 +1,41 @@
+use serde::{Deserialize, Serialize};
+use bson::oid::ObjectId;
+use chrono::{DateTime, Utc};
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Task {
+    #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
+    pub id: Option<ObjectId>,
+    pub user_id: ObjectId,
+    pub project_id: ObjectId,
+    pub name: String,
+    pub hourly_rate: Option<f64>,
+    pub estimated_hours: Option<f64>,
+    pub is_done: bool,
+    #[serde(with = "super::datetime::bson_datetime")]
+    pub created_at: DateTime<Utc>,
+    #[serde(with = "super::datetime::bson_datetime")]
+    pub updated_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct TaskResponse {
+    #[serde(flatten)]
+    pub task: Task,
+    pub tracked_seconds: i64,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CreateTaskRequest {
+    pub name: String,
+    pub hourly_rate: Option<f64>,
+    pub estimated_hours: Option<f64>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct UpdateTaskRequest {
+    pub name: Option<String>,
+    pub hourly_rate: Option<f64>,
+    pub estimated_hours: Option<f64>,
+    pub is_done: Option<bool>,
+}
@@ -0,0 +1,38 @@
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct PostalAddress {
+    pub line1: String,
+    pub line2: Option<String>,
+    pub city: String,
+    pub postal_code: Option<String>,
+    pub region: Option<String>, // state, province or county
+    pub country: String,        // ISO 3166-1 alpha-2
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum TaxIdType {
+    EuVat,
+    GbVat,
+    ChVat,
+    NoVat,
+    AuAbn,
+    UsEin,
+    Other, // stored as given, without validation
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ValidateTaxIdRequest {
+    pub tax_id: String,
+    pub tax_id_type: Option<TaxIdType>, // derived from the country when omitted
+    pub country: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct TaxIdValidation {
+    pub valid: bool,
+    pub tax_id_type: Option<TaxIdType>,
+    pub normalized: Option<String>,
+    pub error: Option<String>,
+}
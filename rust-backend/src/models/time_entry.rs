@@ -2,6 +2,8 @@ use serde::{Deserialize, Serialize};
 use bson::oid::ObjectId;
 use chrono::{DateTime, Utc};
 
+use super::TimesheetStatus;
+
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct TimeEntry {
     #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
@@ -9,35 +11,300 @@ pub struct TimeEntry {
     pub user_id: ObjectId,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub project_id: Option<ObjectId>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub task_id: Option<ObjectId>,
+    #[serde(default)]
+    pub tags: Vec<ObjectId>,
     pub description: String,
+    #[serde(with = "super::datetime::bson_datetime")]
     pub start_time: DateTime<Utc>,
+    #[serde(default, with = "super::datetime::optional_bson_datetime")]
     pub end_time: Option<DateTime<Utc>>,
     pub duration: Option<i64>, // in seconds
     pub is_billable: bool,
     pub hourly_rate: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub invoice_id: Option<ObjectId>, // set once the entry has been billed
+    /// Locked entries can't be edited, stopped or deleted until unlocked
+    #[serde(default)]
+    pub locked: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub lock_reason: Option<LockReason>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub unlocks: Vec<TimeEntryUnlock>,
+    /// Imported from elsewhere and not yet confirmed; left out of totals,
+    /// reports and invoices
+    #[serde(default)]
+    pub draft: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub external_id: Option<String>, // e.g. `ical:<uid>@<start>`, for duplicate detection
+    /// Last heartbeat from a client while the timer was running
+    #[serde(default, skip_serializing_if = "Option::is_none", with = "super::datetime::optional_bson_datetime")]
+    pub last_activity_at: Option<DateTime<Utc>>,
+    /// Stopped by the server after running for the maximum timer duration, or
+    /// at startup because a newer timer of the user was running as well
+    #[serde(default)]
+    pub auto_stopped: bool,
+    /// Where the entry stands in timesheet approval, once submitted
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub approval_status: Option<TimesheetStatus>,
+    #[serde(with = "super::datetime::bson_datetime")]
     pub created_at: DateTime<Utc>,
+    #[serde(with = "super::datetime::bson_datetime")]
     pub updated_at: DateTime<Utc>,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum LockReason {
+    Invoiced,
+    PeriodClosed,
+    Submitted, // on a timesheet awaiting approval
+    Approved,
+}
+
+/// Audit record kept on the entry each time a lock is lifted.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct TimeEntryUnlock {
+    pub user_id: ObjectId,
+    pub reason: String,
+    pub previous_reason: Option<LockReason>,
+    #[serde(with = "super::datetime::bson_datetime")]
+    pub unlocked_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
 pub struct CreateTimeEntryRequest {
     pub project_id: Option<String>,
+    pub task_id: Option<String>,
+    pub tags: Option<Vec<String>>,
     pub description: String,
     pub start_time: DateTime<Utc>,
     pub is_billable: Option<bool>,
     pub hourly_rate: Option<f64>,
+    pub stop_running: Option<bool>, // stop the running timer instead of rejecting
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, Serialize)]
 pub struct StopTimeEntryRequest {
     pub end_time: DateTime<Utc>,
 }
 
+/// Time since the last heartbeat of a running timer, once it exceeds the
+/// user's idle threshold. Returned until resolved with one of [`IdleAction`].
+#[derive(Debug, Serialize, Clone, Copy)]
+pub struct IdlePeriod {
+    pub since: DateTime<Utc>,
+    pub until: DateTime<Utc>,
+    pub seconds: i64,
+}
+
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum IdleAction {
+    /// Count the idle time and keep the timer running
+    Keep,
+    /// Stop the timer where the idle period began
+    Trim,
+    /// Cut the idle period out: stop where it began and start an identical
+    /// timer now
+    Discard,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ResolveIdleRequest {
+    pub action: IdleAction,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct UnlockTimeEntryRequest {
+    pub reason: String,
+}
+
+/// Locks every stopped entry that ended on or before `until`.
+#[derive(Debug, Deserialize)]
+pub struct ClosePeriodRequest {
+    pub until: DateTime<Utc>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ClosePeriodResponse {
+    pub closed_until: DateTime<Utc>,
+    pub locked: u64,
+}
+
 #[derive(Debug, Deserialize)]
 pub struct UpdateTimeEntryRequest {
+    pub project_id: Option<String>,
+    pub task_id: Option<String>,
+    pub tags: Option<Vec<String>>,
     pub description: Option<String>,
     pub start_time: Option<DateTime<Utc>>,
     pub end_time: Option<DateTime<Utc>>,
     pub is_billable: Option<bool>,
     pub hourly_rate: Option<f64>,
+    pub draft: Option<bool>, // `false` confirms an imported draft
+}
+
+#[derive(Debug, Serialize)]
+pub struct TimeEntryResponse {
+    #[serde(flatten)]
+    pub entry: TimeEntry,
+    /// Other entries of the same user whose range overlaps this one
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub overlaps: Vec<TimeEntryOverlap>,
+    /// Duration after the applicable rounding rule, for stopped billable entries
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub billable_duration: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub billable_amount: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub effective_rate: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rate_source: Option<RateSource>,
+    /// Set on a running timer that has gone idle
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub idle: Option<IdlePeriod>,
+}
+
+/// Pushed over the timer socket whenever one of the user's time entries
+/// changes. `snapshot` is sent on connect (and after missed events) with the
+/// running timer, if any. `resync` carries no entry: many entries changed at
+/// once, e.g. a timesheet save or an import, and should be reloaded.
+#[derive(Debug, Serialize, Clone)]
+pub struct TimerEvent {
+    #[serde(skip)]
+    pub user_id: ObjectId,
+    #[serde(rename = "type")]
+    pub kind: TimerEventKind,
+    pub entry_id: Option<ObjectId>,
+    pub entry: Option<TimeEntry>,
+}
+
+#[derive(Debug, Serialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum TimerEventKind {
+    Snapshot,
+    Started,
+    Stopped,
+    Updated,
+    Deleted,
+    Resync,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct TimerSocketQuery {
+    pub token: Option<String>, // browsers can't set headers on WebSocket requests
+}
+
+/// Where the hourly rate applied to a time entry came from, from most to
+/// least specific.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum RateSource {
+    Entry,
+    Task,
+    Project,
+    Client,
+    User,
+}
+
+#[derive(Debug, Serialize, Clone)]
+pub struct TimeEntryOverlap {
+    pub entry_id: ObjectId,
+    pub project_id: Option<ObjectId>,
+    pub description: String,
+    pub start_time: DateTime<Utc>,
+    pub end_time: Option<DateTime<Utc>>,
+    pub overlap_seconds: i64,
+}
+
+/// Filters shared by the time entry listing and summary endpoints. Sorting
+/// and pagination only apply to the listing, `group_by` only to the summary.
+#[derive(Debug, Deserialize, Default)]
+pub struct TimeEntryQuery {
+    pub from: Option<DateTime<Utc>>,
+    pub to: Option<DateTime<Utc>>,
+    pub project_id: Option<String>,
+    pub client_id: Option<String>,
+    pub task_id: Option<String>,
+    pub tag: Option<String>, // tag ID
+    pub billable: Option<bool>,
+    pub billed: Option<bool>,
+    pub locked: Option<bool>,
+    pub draft: Option<bool>, // drafts are excluded unless asked for
+    pub approved: Option<bool>,
+    pub sort_by: Option<TimeEntrySortField>,
+    pub order: Option<SortOrder>,
+    pub page: Option<u64>, // 1-based
+    pub per_page: Option<u64>,
+    pub group_by: Option<TimeEntryGrouping>,
+}
+
+#[derive(Debug, Deserialize, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+pub enum TimeEntrySortField {
+    StartTime,
+    EndTime,
+    Duration,
+    CreatedAt,
+}
+
+#[derive(Debug, Deserialize, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum SortOrder {
+    Asc,
+    Desc,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum TimeEntryGrouping {
+    Day,
+    Week,
+    Month,
+    Project,
+    Client,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct TimeEntryGroup {
+    pub key: Option<String>, // date bucket, or project/client ID (`None` when unassigned)
+    pub label: Option<String>,
+    pub total_seconds: i64,
+    pub billable_seconds: i64,
+    pub entry_count: i64,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct OverlapQuery {
+    pub from: DateTime<Utc>,
+    pub to: DateTime<Utc>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct OverlappingPair {
+    pub first: TimeEntry,
+    pub second: TimeEntry,
+    pub overlap_start: DateTime<Utc>,
+    pub overlap_end: DateTime<Utc>,
+    pub overlap_seconds: i64,
+}
+
+/// How tracked time is rounded before it is billed. The raw `duration` on a
+/// `TimeEntry` is never modified.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct RoundingRule {
+    pub increment_minutes: u32, // 0 disables rounding
+    pub mode: RoundingMode,
+    #[serde(default)]
+    pub minimum_minutes: u32,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum RoundingMode {
+    Up,
+    Down,
+    Nearest,
 }
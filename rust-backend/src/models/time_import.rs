@@ -0,0 +1,52 @@
+use serde::{Deserialize, Serialize};
+use chrono::{DateTime, Utc};
+
+#[derive(Debug, Deserialize, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum TimeImportSource {
+    Toggl,
+    Clockify,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ImportTimeEntriesRequest {
+    pub source: TimeImportSource,
+    pub csv: String,
+    pub timezone: Option<String>, // IANA name the export was made in, defaults to the user's
+    #[serde(default)]
+    pub create_missing: bool, // create unknown projects and clients
+    #[serde(default)]
+    pub dry_run: bool,
+}
+
+#[derive(Debug, Serialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum ImportRowStatus {
+    Import,
+    Duplicate,
+    Error,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ImportRow {
+    pub line: usize,
+    pub status: ImportRowStatus,
+    pub description: String,
+    pub project: Option<String>,
+    pub client: Option<String>,
+    pub start_time: Option<DateTime<Utc>>,
+    pub end_time: Option<DateTime<Utc>>,
+    pub duration: Option<i64>,
+    pub message: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ImportTimeEntriesResponse {
+    pub dry_run: bool,
+    pub imported: usize,
+    pub duplicates: usize,
+    pub errors: usize,
+    pub created_projects: Vec<String>,
+    pub created_clients: Vec<String>,
+    pub rows: Vec<ImportRow>,
+}
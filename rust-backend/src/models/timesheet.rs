@@ -0,0 +1,120 @@
+use serde::{Deserialize, Serialize};
+use bson::oid::ObjectId;
+use chrono::{DateTime, NaiveDate, Utc};
+
+use super::TimeEntry;
+
+#[derive(Debug, Deserialize)]
+pub struct TimesheetQuery {
+    pub week_start: Option<NaiveDate>, // defaults to the current week
+}
+
+/// A week of logged time as a project × day grid of durations in seconds.
+#[derive(Debug, Serialize)]
+pub struct Timesheet {
+    pub week_start: NaiveDate,
+    pub days: Vec<NaiveDate>,
+    pub rows: Vec<TimesheetRow>,
+    pub daily_totals: Vec<i64>,
+    pub total: i64,
+    pub approval_status: Option<TimesheetStatus>, // `None` until submitted
+}
+
+#[derive(Debug, Serialize)]
+pub struct TimesheetRow {
+    pub project_id: Option<ObjectId>,
+    pub project_name: Option<String>,
+    pub durations: Vec<i64>,
+    pub total: i64,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SaveTimesheetRequest {
+    pub week_start: NaiveDate,
+    pub rows: Vec<SaveTimesheetRow>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SaveTimesheetRow {
+    pub project_id: Option<String>,
+    pub description: Option<String>, // used for entries created from the grid
+    /// For entries created from the grid; defaults to the billability of the
+    /// cell's existing entries, or billable when it has none
+    pub is_billable: Option<bool>,
+    pub durations: Vec<i64>, // seconds per day, seven values
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum TimesheetStatus {
+    Submitted,
+    Approved,
+    Rejected,
+}
+
+/// A user's week sent to their approver. The covered entries are locked
+/// while it waits and stay locked once approved; a rejection releases them
+/// for correction and resubmission.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct TimesheetSubmission {
+    #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
+    pub id: Option<ObjectId>,
+    pub user_id: ObjectId,
+    pub approver_id: ObjectId,
+    pub week_start: NaiveDate,
+    pub status: TimesheetStatus,
+    pub entry_ids: Vec<ObjectId>,
+    pub total_seconds: i64,
+    #[serde(default)]
+    pub comments: Vec<TimesheetComment>,
+    #[serde(with = "super::datetime::bson_datetime")]
+    pub submitted_at: DateTime<Utc>,
+    #[serde(default, with = "super::datetime::optional_bson_datetime")]
+    pub decided_at: Option<DateTime<Utc>>,
+    #[serde(with = "super::datetime::bson_datetime")]
+    pub created_at: DateTime<Utc>,
+    #[serde(with = "super::datetime::bson_datetime")]
+    pub updated_at: DateTime<Utc>,
+}
+
+/// One step in a submission's history, with what was said about it.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct TimesheetComment {
+    pub user_id: ObjectId,
+    pub status: TimesheetStatus,
+    pub comment: Option<String>,
+    #[serde(with = "super::datetime::bson_datetime")]
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct TimesheetSubmissionResponse {
+    #[serde(flatten)]
+    pub submission: TimesheetSubmission,
+    pub user_name: Option<String>,
+    pub entries: Vec<TimeEntry>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SubmitTimesheetRequest {
+    pub week_start: NaiveDate,
+    pub comment: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ReviewTimesheetRequest {
+    pub comment: Option<String>, // required when rejecting
+}
+
+/// A user asking to have their timesheets approved by the current user.
+#[derive(Debug, Serialize)]
+pub struct ApproverInvite {
+    pub user_id: ObjectId,
+    pub name: String,
+    pub email: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct TimesheetSubmissionQuery {
+    pub status: Option<TimesheetStatus>,
+}
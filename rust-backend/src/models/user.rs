@@ -2,6 +2,8 @@ use serde::{Deserialize, Serialize};
 use bson::oid::ObjectId;
 use chrono::{DateTime, Utc};
 
+use super::{CalendarRule, RoundingRule};
+
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct User {
     #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
@@ -10,10 +12,98 @@ pub struct User {
     pub password: String,
     pub name: String,
     pub avatar: Option<String>,
+    #[serde(default)]
+    pub settings: UserSettings,
+    /// Secret in the calendar feed URL, `None` while the feed is disabled
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub calendar_token: Option<String>,
+    #[serde(with = "super::datetime::bson_datetime")]
     pub created_at: DateTime<Utc>,
+    #[serde(with = "super::datetime::bson_datetime")]
     pub updated_at: DateTime<Utc>,
 }
 
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct UserSettings {
+    /// Refuse overlapping time entries instead of returning them as warnings
+    #[serde(default)]
+    pub reject_overlapping_time_entries: bool,
+    /// Default rounding for billing, overridden per client and project
+    pub time_rounding: Option<RoundingRule>,
+    /// Fallback hourly rate when no entry, project or client rate applies
+    pub default_hourly_rate: Option<f64>,
+    /// Time up to here is closed: its entries are locked and no new ones
+    /// can be logged in it
+    #[serde(default, with = "super::datetime::optional_bson_datetime")]
+    pub closed_until: Option<DateTime<Utc>>,
+    /// IANA timezone that days and weeks are counted in, UTC when unset
+    pub timezone: Option<String>,
+    /// First day of the week, Monday when unset
+    pub week_start: Option<DayOfWeek>,
+    /// BCP 47 tag such as `en-US` or `de-DE`, used to format dates in
+    /// generated documents
+    pub locale: Option<String>,
+    #[serde(default)]
+    pub calendar_rules: Vec<CalendarRule>,
+    /// Minutes without a heartbeat after which a running timer counts as
+    /// idle; idle detection is off when unset
+    pub idle_threshold_minutes: Option<u32>,
+    /// Running timers are stopped automatically after this many hours
+    pub max_timer_hours: Option<u32>,
+    /// User who approves this user's weekly timesheets
+    pub timesheet_approver_id: Option<ObjectId>,
+    /// Email of the user asked to become the approver. They take over from
+    /// the current approver once they accept.
+    pub timesheet_approver_invite: Option<String>,
+    /// Only entries on approved timesheets can be invoiced
+    #[serde(default)]
+    pub require_timesheet_approval: bool,
+    /// When approval was required. Entries logged in these periods still
+    /// need it to be invoiced after approval is turned off.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub timesheet_approval_periods: Vec<ApprovalPeriod>,
+    /// ISO 3166-1 alpha-2 code of the country the business is established in
+    pub business_country: Option<String>,
+    /// The business's own VAT or tax ID, printed on invoices
+    pub business_tax_id: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ApprovalPeriod {
+    #[serde(with = "super::datetime::bson_datetime")]
+    pub from: DateTime<Utc>,
+    #[serde(default, with = "super::datetime::optional_bson_datetime")]
+    pub until: Option<DateTime<Utc>>, // `None` while approval is required
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum DayOfWeek {
+    Monday,
+    Tuesday,
+    Wednesday,
+    Thursday,
+    Friday,
+    Saturday,
+    Sunday,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct UpdateUserSettingsRequest {
+    pub reject_overlapping_time_entries: Option<bool>,
+    pub time_rounding: Option<RoundingRule>,
+    pub default_hourly_rate: Option<f64>,
+    pub timezone: Option<String>,
+    pub week_start: Option<DayOfWeek>,
+    pub locale: Option<String>,
+    pub idle_threshold_minutes: Option<u32>, // 0 turns idle detection off
+    pub max_timer_hours: Option<u32>,        // 0 removes the limit
+    pub timesheet_approver_email: Option<String>, // invites an approver; empty to remove it
+    pub require_timesheet_approval: Option<bool>,
+    pub business_country: Option<String>, // empty to clear
+    pub business_tax_id: Option<String>,  // empty to clear
+}
+
 #[derive(Debug, Deserialize)]
 pub struct CreateUserRequest {
     pub email: String,
@@ -21,13 +111,13 @@ pub struct CreateUserRequest {
     pub name: String,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, Serialize)]
 pub struct LoginRequest {
     pub email: String,
     pub password: String,
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, Deserialize)]
 pub struct UserResponse {
     pub id: String,
     pub email: String,
@@ -35,7 +125,7 @@ pub struct UserResponse {
     pub avatar: Option<String>,
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, Deserialize)]
 pub struct AuthResponse {
     pub user: UserResponse,
     pub token: String,
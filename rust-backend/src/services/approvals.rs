@@ -0,0 +1,346 @@
+//! Weekly timesheet approval: a user submits a week to their approver, who
+//! approves or rejects it. The status is mirrored on every covered entry.
+
+use chrono::{DateTime, Duration, NaiveDate, Utc};
+use mongodb::{
+    bson::{doc, oid::ObjectId},
+    options::{FindOneAndUpdateOptions, FindOptions, ReturnDocument},
+};
+
+use crate::{
+    database::Database,
+    error::{AppError, Result},
+    models::{
+        ApprovalPeriod, ApproverInvite, LockReason, TimeEntry, User, UserSettings, TimesheetComment,
+        TimesheetStatus, TimesheetSubmission, TimesheetSubmissionResponse,
+    },
+    services::{calendar::Calendar, settings::user_settings},
+};
+
+/// Whether an entry starting at `start_time` can only be invoiced once its
+/// timesheet is approved: approval is required now, or was when the entry
+/// was logged.
+pub fn approval_required(settings: &UserSettings, start_time: DateTime<Utc>) -> bool {
+    settings.require_timesheet_approval
+        || settings
+            .timesheet_approval_periods
+            .iter()
+            .any(|period| period.from <= start_time && period.until.is_none_or(|until| start_time < until))
+}
+
+/// The approval periods after `require_timesheet_approval` changes to
+/// `required` at `now`. Approval required before periods were recorded
+/// counts as required from the start.
+pub fn approval_periods(settings: &UserSettings, required: bool, now: DateTime<Utc>) -> Vec<ApprovalPeriod> {
+    let mut periods = settings.timesheet_approval_periods.clone();
+    match (settings.require_timesheet_approval, required) {
+        (false, true) => periods.push(ApprovalPeriod { from: now, until: None }),
+        (true, false) => match periods.last_mut().filter(|period| period.until.is_none()) {
+            Some(period) => period.until = Some(now),
+            None => periods.push(ApprovalPeriod { from: DateTime::UNIX_EPOCH, until: Some(now) }),
+        },
+        _ => {}
+    }
+    periods
+}
+
+async fn email_of(db: &Database, user_id: ObjectId) -> Result<String> {
+    let user = db
+        .users()
+        .find_one(doc! { "_id": user_id }, None)
+        .await?
+        .ok_or(AppError::NotFound("User not found".to_string()))?;
+
+    Ok(user.email)
+}
+
+/// Users who invited `approver_id` to approve their timesheets.
+pub async fn approver_invites(db: &Database, approver_id: ObjectId) -> Result<Vec<ApproverInvite>> {
+    let email = email_of(db, approver_id).await?;
+    let mut cursor = db
+        .users()
+        .find(doc! { "settings.timesheet_approver_invite": &email }, None)
+        .await?;
+
+    let mut invites = Vec::new();
+    while cursor.advance().await? {
+        let user: User = cursor.deserialize_current()?;
+        invites.push(ApproverInvite { user_id: user.id.unwrap(), name: user.name, email: user.email });
+    }
+
+    Ok(invites)
+}
+
+/// Accepts or declines `user_id`'s invite. Accepting makes `approver_id`
+/// their approver; later submissions go to them.
+pub async fn answer_invite(
+    db: &Database,
+    approver_id: ObjectId,
+    user_id: ObjectId,
+    accept: bool,
+) -> Result<()> {
+    let email = email_of(db, approver_id).await?;
+    let mut update = doc! { "settings.timesheet_approver_invite": null, "updated_at": Utc::now() };
+    if accept {
+        update.insert("settings.timesheet_approver_id", approver_id);
+    }
+
+    let result = db
+        .users()
+        .update_one(
+            doc! { "_id": user_id, "settings.timesheet_approver_invite": &email },
+            doc! { "$set": update },
+            None,
+        )
+        .await?;
+    if result.matched_count == 0 {
+        return Err(AppError::NotFound("Invite not found".to_string()));
+    }
+
+    Ok(())
+}
+
+/// Submits the week starting on (or containing) `week_start` for approval.
+/// A rejected week can be submitted again; the submission is updated with
+/// the entries logged now.
+pub async fn submit(
+    db: &Database,
+    user_id: ObjectId,
+    week_start: NaiveDate,
+    comment: Option<String>,
+) -> Result<TimesheetSubmission> {
+    let settings = user_settings(db, user_id).await?;
+    let approver_id = settings
+        .timesheet_approver_id
+        .ok_or(AppError::BadRequest("No timesheet approver is set".to_string()))?;
+    let calendar = Calendar::new(&settings);
+    let week_start = calendar.start_of_week(week_start);
+    let (from, to) = (
+        calendar.day_start(week_start),
+        calendar.day_start(week_start + Duration::days(7)),
+    );
+
+    let existing = db
+        .timesheet_submissions()
+        .find_one(doc! { "user_id": user_id, "week_start": week_start.to_string() }, None)
+        .await?;
+    match existing.as_ref().map(|submission| submission.status) {
+        Some(TimesheetStatus::Submitted) => {
+            return Err(AppError::Conflict("Timesheet is already awaiting approval".to_string()))
+        }
+        Some(TimesheetStatus::Approved) => {
+            return Err(AppError::Conflict("Timesheet has already been approved".to_string()))
+        }
+        _ => {}
+    }
+
+    let running = db
+        .time_entries()
+        .count_documents(
+            doc! { "user_id": user_id, "end_time": null, "start_time": { "$lt": to } },
+            None,
+        )
+        .await?;
+    if running > 0 {
+        return Err(AppError::BadRequest("Stop the running timer before submitting".to_string()));
+    }
+
+    let mut cursor = db
+        .time_entries()
+        .find(
+            doc! {
+                "user_id": user_id,
+                "start_time": { "$gte": from, "$lt": to },
+                "end_time": { "$ne": null },
+                "draft": { "$ne": true },
+            },
+            None,
+        )
+        .await?;
+    let mut entry_ids = Vec::new();
+    let mut total_seconds = 0;
+    while cursor.advance().await? {
+        let entry: TimeEntry = cursor.deserialize_current()?;
+        total_seconds += entry.duration.unwrap_or(0);
+        entry_ids.extend(entry.id);
+    }
+    if entry_ids.is_empty() {
+        return Err(AppError::BadRequest("No time was logged that week".to_string()));
+    }
+
+    // Entries already locked (invoiced, closed) keep their reason
+    let now = Utc::now();
+    db.time_entries()
+        .update_many(
+            doc! { "_id": { "$in": &entry_ids }, "locked": { "$ne": true } },
+            doc! {
+                "$set": {
+                    "locked": true,
+                    "lock_reason": bson::to_bson(&LockReason::Submitted)?,
+                    "updated_at": now,
+                }
+            },
+            None,
+        )
+        .await?;
+    db.time_entries()
+        .update_many(
+            doc! { "_id": { "$in": &entry_ids } },
+            doc! { "$set": { "approval_status": bson::to_bson(&TimesheetStatus::Submitted)? } },
+            None,
+        )
+        .await?;
+
+    let comment = TimesheetComment {
+        user_id,
+        status: TimesheetStatus::Submitted,
+        comment: comment.filter(|text| !text.trim().is_empty()),
+        created_at: now,
+    };
+
+    match existing {
+        Some(existing) => db
+            .timesheet_submissions()
+            .find_one_and_update(
+                doc! { "_id": existing.id, "status": bson::to_bson(&TimesheetStatus::Rejected)? },
+                doc! {
+                    "$set": {
+                        "approver_id": approver_id,
+                        "status": bson::to_bson(&TimesheetStatus::Submitted)?,
+                        "entry_ids": &entry_ids,
+                        "total_seconds": total_seconds,
+                        "submitted_at": now,
+                        "decided_at": null,
+                        "updated_at": now,
+                    },
+                    "$push": { "comments": comment_doc(&comment)? },
+                },
+                FindOneAndUpdateOptions::builder()
+                    .return_document(ReturnDocument::After)
+                    .build(),
+            )
+            .await?
+            .ok_or(AppError::Conflict("Timesheet was submitted concurrently".to_string())),
+        None => {
+            let mut submission = TimesheetSubmission {
+                id: None,
+                user_id,
+                approver_id,
+                week_start,
+                status: TimesheetStatus::Submitted,
+                entry_ids,
+                total_seconds,
+                comments: vec![comment],
+                submitted_at: now,
+                decided_at: None,
+                created_at: now,
+                updated_at: now,
+            };
+            let result = db.timesheet_submissions().insert_one(&submission, None).await?;
+            submission.id = result.inserted_id.as_object_id();
+            Ok(submission)
+        }
+    }
+}
+
+/// Approves or rejects a submission waiting on `approver_id`. Entries that
+/// were unlocked and edited since submitting are left out of an approval.
+pub async fn review(
+    db: &Database,
+    approver_id: ObjectId,
+    submission_id: ObjectId,
+    approve: bool,
+    comment: Option<String>,
+) -> Result<TimesheetSubmission> {
+    let comment = comment.filter(|text| !text.trim().is_empty());
+    if !approve && comment.is_none() {
+        return Err(AppError::BadRequest("A comment is required to reject a timesheet".to_string()));
+    }
+
+    let submission = db
+        .timesheet_submissions()
+        .find_one(doc! { "_id": submission_id, "approver_id": approver_id }, None)
+        .await?
+        .ok_or(AppError::NotFound("Timesheet submission not found".to_string()))?;
+    if submission.status != TimesheetStatus::Submitted {
+        return Err(AppError::Conflict("Timesheet is not awaiting approval".to_string()));
+    }
+
+    let status = if approve { TimesheetStatus::Approved } else { TimesheetStatus::Rejected };
+    let now = Utc::now();
+    let covered = doc! {
+        "_id": { "$in": &submission.entry_ids },
+        "user_id": submission.user_id,
+        "approval_status": bson::to_bson(&TimesheetStatus::Submitted)?,
+    };
+
+    let mut still_locked = covered.clone();
+    still_locked.insert("lock_reason", bson::to_bson(&LockReason::Submitted)?);
+    let lock_update = if approve {
+        doc! { "$set": { "lock_reason": bson::to_bson(&LockReason::Approved)?, "updated_at": now } }
+    } else {
+        doc! {
+            "$set": { "locked": false, "updated_at": now },
+            "$unset": { "lock_reason": "" },
+        }
+    };
+    db.time_entries().update_many(still_locked, lock_update, None).await?;
+    db.time_entries()
+        .update_many(covered, doc! { "$set": { "approval_status": bson::to_bson(&status)? } }, None)
+        .await?;
+
+    let comment = TimesheetComment { user_id: approver_id, status, comment, created_at: now };
+    db.timesheet_submissions()
+        .find_one_and_update(
+            doc! { "_id": submission_id, "status": bson::to_bson(&TimesheetStatus::Submitted)? },
+            doc! {
+                "$set": {
+                    "status": bson::to_bson(&status)?,
+                    "decided_at": now,
+                    "updated_at": now,
+                },
+                "$push": { "comments": comment_doc(&comment)? },
+            },
+            FindOneAndUpdateOptions::builder()
+                .return_document(ReturnDocument::After)
+                .build(),
+        )
+        .await?
+        .ok_or(AppError::Conflict("Timesheet was reviewed concurrently".to_string()))
+}
+
+/// A submission with its entries, for its owner or its approver.
+pub async fn with_entries(
+    db: &Database,
+    submission: TimesheetSubmission,
+) -> Result<TimesheetSubmissionResponse> {
+    let user_name = db
+        .users()
+        .find_one(doc! { "_id": submission.user_id }, None)
+        .await?
+        .map(|user| user.name);
+
+    let mut cursor = db
+        .time_entries()
+        .find(
+            doc! { "_id": { "$in": &submission.entry_ids }, "user_id": submission.user_id },
+            FindOptions::builder().sort(doc! { "start_time": 1 }).build(),
+        )
+        .await?;
+    let mut entries = Vec::new();
+    while cursor.advance().await? {
+        entries.push(cursor.deserialize_current()?);
+    }
+
+    Ok(TimesheetSubmissionResponse { submission, user_name, entries })
+}
+
+/// Built by hand so `created_at` is stored as a BSON date.
+fn comment_doc(comment: &TimesheetComment) -> Result<bson::Document> {
+    Ok(doc! {
+        "user_id": comment.user_id,
+        "status": bson::to_bson(&comment.status)?,
+        "comment": comment.comment.as_deref(),
+        "created_at": comment.created_at,
+    })
+}
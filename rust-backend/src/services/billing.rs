@@ -0,0 +1,223 @@
+use std::collections::HashMap;
+
+use mongodb::bson::{doc, oid::ObjectId};
+
+use crate::{
+    database::Database,
+    error::{AppError, Result},
+    models::{
+        Client, Project, RateSource, RoundingMode, RoundingRule, Task, TimeEntry,
+        TimeEntryResponse, UserSettings,
+    },
+    services::settings::user_settings,
+};
+
+pub fn validate_rounding_rule(rule: &RoundingRule) -> Result<()> {
+    if rule.increment_minutes > 24 * 60 || rule.minimum_minutes > 24 * 60 {
+        return Err(AppError::BadRequest(
+            "Rounding increments cannot exceed one day".to_string(),
+        ));
+    }
+
+    Ok(())
+}
+
+/// Rates end up as invoice amounts, so they have to be real and not below zero.
+pub fn validate_hourly_rate(rate: Option<f64>) -> Result<()> {
+    match rate {
+        Some(rate) if !rate.is_finite() || rate < 0.0 => Err(AppError::BadRequest(
+            "Hourly rate must be a number of at least zero".to_string(),
+        )),
+        _ => Ok(()),
+    }
+}
+
+/// Currency of amounts for clients that don't name one.
+pub const DEFAULT_CURRENCY: &str = "USD";
+
+/// Normalizes an ISO 4217 code such as `eur` to `EUR`. Empty clears it.
+pub fn validate_currency(code: &str) -> Result<Option<String>> {
+    let code = code.trim();
+    if code.is_empty() {
+        return Ok(None);
+    }
+    if code.len() != 3 || !code.chars().all(|c| c.is_ascii_alphabetic()) {
+        return Err(AppError::BadRequest(format!("Invalid currency '{}'", code)));
+    }
+
+    Ok(Some(code.to_ascii_uppercase()))
+}
+
+/// Applies `rule` to a duration in seconds. Zero-length entries stay at
+/// zero rather than being bumped up to the minimum.
+pub fn round_duration(seconds: i64, rule: &RoundingRule) -> i64 {
+    if seconds <= 0 {
+        return 0;
+    }
+
+    let increment = i64::from(rule.increment_minutes) * 60;
+    let rounded = if increment == 0 {
+        seconds
+    } else {
+        match rule.mode {
+            RoundingMode::Up => (seconds + increment - 1) / increment * increment,
+            RoundingMode::Down => seconds / increment * increment,
+            RoundingMode::Nearest => (seconds + increment / 2) / increment * increment,
+        }
+    };
+
+    rounded.max(i64::from(rule.minimum_minutes) * 60)
+}
+
+/// Everything needed to turn a user's time entries into billable amounts,
+/// loaded once per request so listings don't query per entry.
+pub struct BillingContext {
+    settings: UserSettings,
+    projects: HashMap<ObjectId, Project>,
+    clients: HashMap<ObjectId, Client>,
+    tasks: HashMap<ObjectId, Task>,
+}
+
+impl BillingContext {
+    pub async fn load(db: &Database, user_id: ObjectId) -> Result<Self> {
+        let settings = user_settings(db, user_id).await?;
+
+        let mut projects = HashMap::new();
+        let mut cursor = db.projects().find(doc! { "user_id": user_id }, None).await?;
+        while cursor.advance().await? {
+            let project: Project = cursor.deserialize_current()?;
+            projects.insert(project.id.unwrap(), project);
+        }
+
+        let mut clients = HashMap::new();
+        let mut cursor = db.clients().find(doc! { "user_id": user_id }, None).await?;
+        while cursor.advance().await? {
+            let client: Client = cursor.deserialize_current()?;
+            clients.insert(client.id.unwrap(), client);
+        }
+
+        let mut tasks = HashMap::new();
+        let mut cursor = db.tasks().find(doc! { "user_id": user_id }, None).await?;
+        while cursor.advance().await? {
+            let task: Task = cursor.deserialize_current()?;
+            tasks.insert(task.id.unwrap(), task);
+        }
+
+        Ok(BillingContext { settings, projects, clients, tasks })
+    }
+
+    pub fn task(&self, entry: &TimeEntry) -> Option<&Task> {
+        entry.task_id.and_then(|id| self.tasks.get(&id))
+    }
+
+    pub fn project(&self, entry: &TimeEntry) -> Option<&Project> {
+        entry.project_id.and_then(|id| self.projects.get(&id))
+    }
+
+    pub fn client(&self, entry: &TimeEntry) -> Option<&Client> {
+        self.project(entry)
+            .and_then(|project| project.client_id)
+            .and_then(|id| self.clients.get(&id))
+    }
+
+    pub fn currency(&self, entry: &TimeEntry) -> &str {
+        self.client(entry)
+            .and_then(|client| client.currency.as_deref())
+            .unwrap_or(DEFAULT_CURRENCY)
+    }
+
+    /// The most specific rounding rule: project, then client, then user.
+    pub fn rounding_rule(&self, entry: &TimeEntry) -> Option<&RoundingRule> {
+        self.project(entry)
+            .and_then(|project| project.time_rounding.as_ref())
+            .or_else(|| self.client(entry).and_then(|client| client.time_rounding.as_ref()))
+            .or(self.settings.time_rounding.as_ref())
+    }
+
+    /// The single place an hourly rate is chosen for a time entry: the
+    /// entry's own override, then its task, its project, the project's
+    /// client and finally the user's default.
+    pub fn hourly_rate(&self, entry: &TimeEntry) -> Option<(f64, RateSource)> {
+        if let Some(rate) = entry.hourly_rate {
+            return Some((rate, RateSource::Entry));
+        }
+        if let Some(rate) = self.task(entry).and_then(|task| task.hourly_rate) {
+            return Some((rate, RateSource::Task));
+        }
+        if let Some(rate) = self.project(entry).and_then(|project| project.hourly_rate) {
+            return Some((rate, RateSource::Project));
+        }
+        if let Some(rate) = self.client(entry).and_then(|client| client.default_hourly_rate) {
+            return Some((rate, RateSource::Client));
+        }
+        self.settings
+            .default_hourly_rate
+            .map(|rate| (rate, RateSource::User))
+    }
+
+    /// Rounded billable duration in seconds, `None` for running or
+    /// non-billable entries.
+    pub fn billable_duration(&self, entry: &TimeEntry) -> Option<i64> {
+        if !entry.is_billable {
+            return None;
+        }
+
+        let duration = entry.duration?;
+        Some(match self.rounding_rule(entry) {
+            Some(rule) => round_duration(duration, rule),
+            None => duration,
+        })
+    }
+
+    pub fn billable_amount(&self, entry: &TimeEntry) -> Option<f64> {
+        let seconds = self.billable_duration(entry)?;
+        let (rate, _) = self.hourly_rate(entry)?;
+        Some(seconds as f64 / 3600.0 * rate)
+    }
+
+    pub fn response(&self, entry: TimeEntry) -> TimeEntryResponse {
+        let rate = self.hourly_rate(&entry);
+        TimeEntryResponse {
+            effective_rate: rate.map(|(rate, _)| rate),
+            rate_source: rate.map(|(_, source)| source),
+            billable_duration: self.billable_duration(&entry),
+            billable_amount: self.billable_amount(&entry),
+            overlaps: Vec::new(),
+            idle: None,
+            entry,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rule(mode: RoundingMode, increment_minutes: u32, minimum_minutes: u32) -> RoundingRule {
+        RoundingRule { mode, increment_minutes, minimum_minutes }
+    }
+
+    #[test]
+    fn rounds_to_the_increment() {
+        assert_eq!(round_duration(7 * 60 + 1, &rule(RoundingMode::Up, 15, 0)), 15 * 60);
+        assert_eq!(round_duration(15 * 60, &rule(RoundingMode::Up, 15, 0)), 15 * 60);
+        assert_eq!(round_duration(29 * 60, &rule(RoundingMode::Down, 15, 0)), 15 * 60);
+        assert_eq!(round_duration(22 * 60, &rule(RoundingMode::Nearest, 15, 0)), 15 * 60);
+        assert_eq!(round_duration(22 * 60 + 30, &rule(RoundingMode::Nearest, 15, 0)), 30 * 60);
+    }
+
+    #[test]
+    fn applies_the_minimum() {
+        assert_eq!(round_duration(60, &rule(RoundingMode::Down, 15, 30)), 30 * 60);
+        assert_eq!(round_duration(45 * 60, &rule(RoundingMode::Down, 15, 30)), 45 * 60);
+        // Without an increment only the minimum applies
+        assert_eq!(round_duration(61, &rule(RoundingMode::Up, 0, 1)), 61);
+        assert_eq!(round_duration(59, &rule(RoundingMode::Up, 0, 1)), 60);
+    }
+
+    #[test]
+    fn empty_entries_stay_empty() {
+        assert_eq!(round_duration(0, &rule(RoundingMode::Up, 15, 30)), 0);
+        assert_eq!(round_duration(-60, &rule(RoundingMode::Up, 15, 30)), 0);
+    }
+}
@@ -0,0 +1,230 @@
+//! The user's calendar: the timezone days are counted in, the day weeks
+//! start on and how dates are written in generated documents.
+
+use chrono::{DateTime, Datelike, Duration, LocalResult, NaiveDate, TimeZone, Utc, Weekday};
+use chrono_tz::Tz;
+use mongodb::bson::oid::ObjectId;
+
+use crate::{
+    database::Database,
+    error::{AppError, Result},
+    models::{DayOfWeek, UserSettings},
+    services::settings::user_settings,
+};
+
+pub struct Calendar {
+    pub timezone: Tz,
+    pub week_start: Weekday,
+    date_format: &'static str,
+}
+
+impl Calendar {
+    /// Settings that fail validation (say, a timezone removed from the tz
+    /// database since it was saved) fall back to the defaults.
+    pub fn new(settings: &UserSettings) -> Self {
+        Calendar {
+            timezone: settings
+                .timezone
+                .as_deref()
+                .and_then(|name| name.parse().ok())
+                .unwrap_or(Tz::UTC),
+            week_start: match settings.week_start.unwrap_or(DayOfWeek::Monday) {
+                DayOfWeek::Monday => Weekday::Mon,
+                DayOfWeek::Tuesday => Weekday::Tue,
+                DayOfWeek::Wednesday => Weekday::Wed,
+                DayOfWeek::Thursday => Weekday::Thu,
+                DayOfWeek::Friday => Weekday::Fri,
+                DayOfWeek::Saturday => Weekday::Sat,
+                DayOfWeek::Sunday => Weekday::Sun,
+            },
+            date_format: date_format(settings.locale.as_deref().unwrap_or("")),
+        }
+    }
+
+    pub async fn load(db: &Database, user_id: ObjectId) -> Result<Self> {
+        Ok(Calendar::new(&user_settings(db, user_id).await?))
+    }
+
+    pub fn local_date(&self, time: DateTime<Utc>) -> NaiveDate {
+        time.with_timezone(&self.timezone).date_naive()
+    }
+
+    pub fn today(&self) -> NaiveDate {
+        self.local_date(Utc::now())
+    }
+
+    pub fn start_of_week(&self, date: NaiveDate) -> NaiveDate {
+        let offset = (date.weekday().num_days_from_monday() + 7
+            - self.week_start.num_days_from_monday())
+            % 7;
+        date - Duration::days(offset as i64)
+    }
+
+    /// The instant `date` begins in the user's timezone. Where midnight is
+    /// skipped by a DST change the day starts an hour later.
+    pub fn day_start(&self, date: NaiveDate) -> DateTime<Utc> {
+        let midnight = date.and_hms_opt(0, 0, 0).unwrap();
+        match self.timezone.from_local_datetime(&midnight) {
+            LocalResult::Single(time) | LocalResult::Ambiguous(time, _) => time.with_timezone(&Utc),
+            LocalResult::None => self
+                .timezone
+                .from_local_datetime(&(midnight + Duration::hours(1)))
+                .earliest()
+                .map(|time| time.with_timezone(&Utc))
+                .unwrap_or_else(|| midnight.and_utc()),
+        }
+    }
+
+    /// Splits `[start, end)` at every local midnight it crosses.
+    pub fn split_days(
+        &self,
+        start: DateTime<Utc>,
+        end: DateTime<Utc>,
+    ) -> Vec<(DateTime<Utc>, DateTime<Utc>)> {
+        let mut pieces = Vec::new();
+        let mut piece_start = start;
+        while piece_start < end {
+            let next_day = self.day_start(self.local_date(piece_start) + Duration::days(1));
+            let piece_end = next_day.min(end);
+            pieces.push((piece_start, piece_end));
+            piece_start = piece_end;
+        }
+        if pieces.is_empty() {
+            pieces.push((start, end));
+        }
+
+        pieces
+    }
+
+    pub fn format_date(&self, date: NaiveDate) -> String {
+        date.format(self.date_format).to_string()
+    }
+
+    pub fn format_time(&self, time: DateTime<Utc>) -> String {
+        self.format_date(self.local_date(time))
+    }
+}
+
+pub fn validate_timezone(name: &str) -> Result<()> {
+    name.parse::<Tz>()
+        .map(|_| ())
+        .map_err(|_| AppError::BadRequest(format!("Unknown timezone '{}'", name)))
+}
+
+pub fn validate_locale(locale: &str) -> Result<()> {
+    let mut parts = locale.split(['-', '_']);
+    let language_ok = parts
+        .next()
+        .is_some_and(|lang| (2..=3).contains(&lang.len()) && lang.chars().all(|c| c.is_ascii_alphabetic()));
+    if !language_ok || parts.any(|part| part.is_empty() || !part.chars().all(|c| c.is_ascii_alphanumeric())) {
+        return Err(AppError::BadRequest(format!("Invalid locale '{}'", locale)));
+    }
+
+    Ok(())
+}
+
+/// Numeric date format for a locale. Languages not listed use ISO 8601.
+fn date_format(locale: &str) -> &'static str {
+    let locale = locale.replace('_', "-").to_lowercase();
+    let language = locale.split('-').next().unwrap_or("");
+    match (language, locale.as_str()) {
+        (_, "en-us") | (_, "en-ph") => "%m/%d/%Y",
+        ("en", _) | ("fr", _) | ("es", _) | ("it", _) | ("pt", _) | ("el", _) | ("vi", _)
+        | ("id", _) => "%d/%m/%Y",
+        ("nl", _) => "%d-%m-%Y",
+        ("de", _) | ("ru", _) | ("pl", _) | ("cs", _) | ("fi", _) | ("nb", _) | ("da", _)
+        | ("tr", _) | ("uk", _) | ("ro", _) => "%d.%m.%Y",
+        ("zh", _) | ("ja", _) => "%Y/%m/%d",
+        _ => "%Y-%m-%d",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(time: &str) -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339(time).unwrap().with_timezone(&Utc)
+    }
+
+    fn date(year: i32, month: u32, day: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(year, month, day).unwrap()
+    }
+
+    fn calendar(timezone: &str) -> Calendar {
+        Calendar::new(&UserSettings { timezone: Some(timezone.to_string()), ..Default::default() })
+    }
+
+    #[test]
+    fn spring_forward_days_are_short() {
+        let calendar = calendar("Europe/Berlin");
+        assert_eq!(calendar.day_start(date(2024, 3, 31)), at("2024-03-30T23:00:00Z"));
+        assert_eq!(calendar.day_start(date(2024, 4, 1)), at("2024-03-31T22:00:00Z"));
+
+        // 23:00 on the 30th to 01:00 on April 1st, local time
+        let pieces = calendar.split_days(at("2024-03-30T22:00:00Z"), at("2024-03-31T23:00:00Z"));
+        assert_eq!(
+            pieces,
+            [
+                (at("2024-03-30T22:00:00Z"), at("2024-03-30T23:00:00Z")),
+                (at("2024-03-30T23:00:00Z"), at("2024-03-31T22:00:00Z")),
+                (at("2024-03-31T22:00:00Z"), at("2024-03-31T23:00:00Z")),
+            ]
+        );
+        assert_eq!(pieces[1].1 - pieces[1].0, Duration::hours(23));
+    }
+
+    #[test]
+    fn fall_back_days_are_long() {
+        let calendar = calendar("Europe/Berlin");
+        let pieces = calendar.split_days(at("2024-10-26T21:00:00Z"), at("2024-10-28T00:00:00Z"));
+        assert_eq!(
+            pieces,
+            [
+                (at("2024-10-26T21:00:00Z"), at("2024-10-26T22:00:00Z")),
+                (at("2024-10-26T22:00:00Z"), at("2024-10-27T23:00:00Z")),
+                (at("2024-10-27T23:00:00Z"), at("2024-10-28T00:00:00Z")),
+            ]
+        );
+        assert_eq!(pieces[1].1 - pieces[1].0, Duration::hours(25));
+        assert_eq!(calendar.local_date(at("2024-10-27T22:59:59Z")), date(2024, 10, 27));
+    }
+
+    #[test]
+    fn skipped_midnights_start_the_day_an_hour_later() {
+        // Chile moves its clocks from midnight straight to 01:00
+        let calendar = calendar("America/Santiago");
+        assert_eq!(calendar.day_start(date(2024, 9, 8)), at("2024-09-08T04:00:00Z"));
+        let pieces = calendar.split_days(at("2024-09-08T03:30:00Z"), at("2024-09-08T04:30:00Z"));
+        assert_eq!(pieces.len(), 2);
+        assert_eq!(pieces[0].1, at("2024-09-08T04:00:00Z"));
+    }
+
+    #[test]
+    fn splits_at_local_midnight() {
+        // 22:00 to 02:00 in New York is all on one UTC day
+        let calendar = calendar("America/New_York");
+        let pieces = calendar.split_days(at("2024-06-03T02:00:00Z"), at("2024-06-03T06:00:00Z"));
+        assert_eq!(
+            pieces,
+            [
+                (at("2024-06-03T02:00:00Z"), at("2024-06-03T04:00:00Z")),
+                (at("2024-06-03T04:00:00Z"), at("2024-06-03T06:00:00Z")),
+            ]
+        );
+
+        // Entries within a day, or ending right at midnight, stay whole
+        let (start, midnight) = (at("2024-06-03T13:00:00Z"), at("2024-06-04T04:00:00Z"));
+        assert_eq!(calendar.split_days(start, midnight), [(start, midnight)]);
+        assert_eq!(calendar.split_days(start, start), [(start, start)]);
+    }
+
+    #[test]
+    fn weeks_start_on_the_configured_day() {
+        let mut calendar = calendar("UTC");
+        assert_eq!(calendar.start_of_week(date(2024, 6, 5)), date(2024, 6, 3));
+        calendar.week_start = Weekday::Sun;
+        assert_eq!(calendar.start_of_week(date(2024, 6, 5)), date(2024, 6, 2));
+        assert_eq!(calendar.start_of_week(date(2024, 6, 2)), date(2024, 6, 2));
+    }
+}
@@ -0,0 +1,453 @@
+//! Client list as CSV: export of every client, and import with a mapping of
+//! CSV columns to client fields. Rows whose email is already used by a
+//! client or contact are skipped as duplicates.
+
+use std::collections::{HashMap, HashSet};
+
+use chrono::Utc;
+use mongodb::{
+    bson::{doc, oid::ObjectId},
+    options::FindOptions,
+};
+
+use crate::{
+    database::Database,
+    error::{AppError, Result},
+    models::{
+        Client, ClientColumns, ClientImportRow, ClientImportStatus, ImportClientsRequest,
+        ImportClientsResponse, PostalAddress, TaxIdType,
+    },
+    services::{billing, contacts, tax},
+};
+
+const HEADERS: [&str; 20] = [
+    "id", "name", "email", "phone", "company", "address", "billing_line1", "billing_line2",
+    "billing_city", "billing_postal_code", "billing_region", "billing_country", "country",
+    "tax_id_type", "tax_id", "default_hourly_rate", "currency", "retainer_balance", "notes",
+    "archived_at",
+];
+
+fn csv_error(err: impl std::fmt::Display) -> AppError {
+    AppError::InternalError(format!("Failed to write CSV: {}", err))
+}
+
+/// Every client, archived ones included, by name.
+pub async fn export_csv(db: &Database, user_id: ObjectId) -> Result<Vec<u8>> {
+    let options = FindOptions::builder().sort(doc! { "name": 1 }).build();
+    let mut cursor = db.clients().find(doc! { "user_id": user_id }, options).await?;
+
+    let mut writer = csv::Writer::from_writer(Vec::new());
+    writer.write_record(HEADERS).map_err(csv_error)?;
+    while cursor.advance().await? {
+        let client: Client = cursor.deserialize_current()?;
+        writer.write_record(record(&client)).map_err(csv_error)?;
+    }
+
+    writer.into_inner().map_err(csv_error)
+}
+
+/// A client's row, in the order of `HEADERS`.
+fn record(client: &Client) -> [String; 20] {
+    let address = client.billing_address.as_ref();
+    let address_field = |field: fn(&PostalAddress) -> Option<&str>| {
+        address.and_then(field).unwrap_or_default().to_string()
+    };
+
+    [
+        client.id.map(|id| id.to_hex()).unwrap_or_default(),
+        client.name.clone(),
+        client.email.clone(),
+        client.phone.clone().unwrap_or_default(),
+        client.company.clone().unwrap_or_default(),
+        client.address.clone().unwrap_or_default(),
+        address_field(|address| Some(&address.line1)),
+        address_field(|address| address.line2.as_deref()),
+        address_field(|address| Some(&address.city)),
+        address_field(|address| address.postal_code.as_deref()),
+        address_field(|address| address.region.as_deref()),
+        address_field(|address| Some(&address.country)),
+        client.country.clone().unwrap_or_default(),
+        client.tax_id_type.map(tax_id_type_name).unwrap_or_default().to_string(),
+        client.tax_id.clone().unwrap_or_default(),
+        client.default_hourly_rate.map(|rate| rate.to_string()).unwrap_or_default(),
+        client.currency.clone().unwrap_or_default(),
+        format!("{:.2}", client.retainer_balance),
+        client.notes.clone().unwrap_or_default(),
+        client.archived_at.map(|time| time.to_rfc3339()).unwrap_or_default(),
+    ]
+}
+
+fn tax_id_type_name(kind: TaxIdType) -> &'static str {
+    match kind {
+        TaxIdType::EuVat => "eu_vat",
+        TaxIdType::GbVat => "gb_vat",
+        TaxIdType::ChVat => "ch_vat",
+        TaxIdType::NoVat => "no_vat",
+        TaxIdType::AuAbn => "au_abn",
+        TaxIdType::UsEin => "us_ein",
+        TaxIdType::Other => "other",
+    }
+}
+
+fn parse_tax_id_type(value: &str) -> std::result::Result<TaxIdType, String> {
+    serde_json::from_value(serde_json::Value::String(value.to_lowercase()))
+        .map_err(|_| format!("Unknown tax ID type '{}'", value))
+}
+
+/// Column index of each client field, resolved against the CSV's headers.
+struct Mapping {
+    columns: HashMap<&'static str, usize>,
+}
+
+impl Mapping {
+    fn new(columns: &ClientColumns, headers: &csv::StringRecord) -> Result<Self> {
+        let headers: HashMap<String, usize> = headers
+            .iter()
+            .enumerate()
+            .map(|(index, name)| (name.trim_start_matches('\u{feff}').trim().to_lowercase(), index))
+            .collect();
+        let fields: [(&'static str, &Option<String>); 17] = [
+            ("name", &columns.name),
+            ("email", &columns.email),
+            ("phone", &columns.phone),
+            ("company", &columns.company),
+            ("address", &columns.address),
+            ("billing_line1", &columns.billing_line1),
+            ("billing_line2", &columns.billing_line2),
+            ("billing_city", &columns.billing_city),
+            ("billing_postal_code", &columns.billing_postal_code),
+            ("billing_region", &columns.billing_region),
+            ("billing_country", &columns.billing_country),
+            ("country", &columns.country),
+            ("tax_id_type", &columns.tax_id_type),
+            ("tax_id", &columns.tax_id),
+            ("default_hourly_rate", &columns.default_hourly_rate),
+            ("currency", &columns.currency),
+            ("notes", &columns.notes),
+        ];
+
+        let mut mapped = HashMap::new();
+        for (field, header) in fields {
+            let index = match header {
+                // A mapped column has to be there; an unmapped one is optional
+                Some(header) => Some(*headers.get(&header.trim().to_lowercase()).ok_or_else(|| {
+                    AppError::BadRequest(format!("CSV has no column '{}' (mapped to {})", header, field))
+                })?),
+                None => headers.get(field).copied(),
+            };
+            if let Some(index) = index {
+                mapped.insert(field, index);
+            }
+        }
+        if !mapped.contains_key("name") {
+            return Err(AppError::BadRequest(
+                "CSV has no 'name' column; map one with columns.name".to_string(),
+            ));
+        }
+
+        Ok(Mapping { columns: mapped })
+    }
+
+    fn get<'a>(&self, record: &'a csv::StringRecord, field: &str) -> Option<&'a str> {
+        self.columns
+            .get(field)
+            .and_then(|&index| record.get(index))
+            .map(str::trim)
+            .filter(|value| !value.is_empty())
+    }
+}
+
+fn parse_row(record: &csv::StringRecord, mapping: &Mapping, user_id: ObjectId) -> Result<Client> {
+    let get = |field| mapping.get(record, field).map(str::to_string);
+    let name = get("name").ok_or(AppError::BadRequest("Missing name".to_string()))?;
+    let email = get("email").unwrap_or_default();
+    if !email.is_empty() {
+        contacts::validate_email(&email)?;
+    }
+
+    let billing_address = match (get("billing_line1"), get("billing_city")) {
+        (None, None) => None,
+        (line1, city) => {
+            let country = get("billing_country")
+                .or(get("country"))
+                .ok_or(AppError::BadRequest("Billing address has no country".to_string()))?;
+            Some(tax::validate_address(PostalAddress {
+                line1: line1.unwrap_or_default(),
+                line2: get("billing_line2"),
+                city: city.unwrap_or_default(),
+                postal_code: get("billing_postal_code"),
+                region: get("billing_region"),
+                country,
+            })?)
+        }
+    };
+    let tax_id_type = get("tax_id_type")
+        .map(|value| parse_tax_id_type(&value))
+        .transpose()
+        .map_err(AppError::BadRequest)?;
+    let country = get("country").or_else(|| billing_address.as_ref().map(|address| address.country.clone()));
+    let identity = tax::tax_identity(country.as_deref(), tax_id_type, get("tax_id").as_deref())?;
+    let default_hourly_rate = get("default_hourly_rate")
+        .map(|rate| {
+            rate.parse::<f64>()
+                .map_err(|_| AppError::BadRequest(format!("Invalid hourly rate '{}'", rate)))
+        })
+        .transpose()?;
+    billing::validate_hourly_rate(default_hourly_rate)?;
+    let currency = get("currency").map(|code| billing::validate_currency(&code)).transpose()?.flatten();
+
+    Ok(Client {
+        id: None,
+        user_id,
+        name,
+        email,
+        phone: get("phone"),
+        company: get("company"),
+        address: get("address"),
+        billing_address,
+        country: identity.country,
+        tax_id_type: identity.tax_id_type,
+        tax_id: identity.tax_id,
+        notes: get("notes"),
+        retainer_balance: 0.0,
+        time_rounding: None,
+        default_hourly_rate,
+        currency,
+        archived_at: None,
+        created_at: Utc::now(),
+        updated_at: Utc::now(),
+    })
+}
+
+/// Creates a client for every valid row. With `dry_run` nothing is written
+/// and the response is a preview of what would happen.
+pub async fn import_csv(
+    db: &Database,
+    user_id: ObjectId,
+    request: ImportClientsRequest,
+) -> Result<ImportClientsResponse> {
+    let mut reader = csv::ReaderBuilder::new()
+        .flexible(true)
+        .from_reader(request.csv.as_bytes());
+    let headers = reader
+        .headers()
+        .map_err(|err| AppError::BadRequest(format!("Invalid CSV: {}", err)))?
+        .clone();
+    let mapping = Mapping::new(&request.columns, &headers)?;
+    let known = contacts::emails_in_use(db, user_id).await?;
+
+    let mut response = ImportClientsResponse {
+        dry_run: request.dry_run,
+        imported: 0,
+        duplicates: 0,
+        errors: 0,
+        rows: Vec::new(),
+    };
+    let mut seen = HashSet::new();
+
+    for (index, record) in reader.records().enumerate() {
+        let line = index + 2; // 1-based, after the header
+        let parsed = record
+            .map_err(|err| AppError::BadRequest(err.to_string()))
+            .and_then(|record| parse_row(&record, &mapping, user_id));
+        let client = match parsed {
+            Ok(client) => client,
+            Err(AppError::BadRequest(message)) => {
+                response.errors += 1;
+                response.rows.push(ClientImportRow {
+                    line,
+                    status: ClientImportStatus::Error,
+                    name: String::new(),
+                    email: None,
+                    client_id: None,
+                    message: Some(message),
+                });
+                continue;
+            }
+            Err(err) => return Err(err),
+        };
+
+        let email = client.email.to_lowercase();
+        let email_field = (!client.email.is_empty()).then(|| client.email.clone());
+        if let Some(existing) = known.get(&email).filter(|_| !email.is_empty()) {
+            response.duplicates += 1;
+            response.rows.push(ClientImportRow {
+                line,
+                status: ClientImportStatus::Duplicate,
+                name: client.name,
+                email: email_field,
+                client_id: Some(*existing),
+                message: Some("A client or contact already uses this email".to_string()),
+            });
+            continue;
+        }
+        if !email.is_empty() && !seen.insert(email) {
+            response.duplicates += 1;
+            response.rows.push(ClientImportRow {
+                line,
+                status: ClientImportStatus::Duplicate,
+                name: client.name,
+                email: email_field,
+                client_id: None,
+                message: Some("Email appears on an earlier row".to_string()),
+            });
+            continue;
+        }
+
+        let client_id = if request.dry_run { None } else { Some(insert(db, client.clone()).await?) };
+        response.imported += 1;
+        response.rows.push(ClientImportRow {
+            line,
+            status: ClientImportStatus::Import,
+            name: client.name,
+            email: email_field,
+            client_id,
+            message: None,
+        });
+    }
+
+    Ok(response)
+}
+
+/// Inserts the client together with a primary contact for its email.
+pub async fn insert(db: &Database, mut client: Client) -> Result<ObjectId> {
+    let result = db.clients().insert_one(&client, None).await?;
+    let client_id = result.inserted_id.as_object_id().unwrap();
+    client.id = Some(client_id);
+
+    if !client.email.is_empty() {
+        db.client_contacts().insert_one(contacts::primary_from(&client), None).await?;
+    }
+
+    Ok(client_id)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn client() -> Client {
+        Client {
+            id: Some(ObjectId::new()),
+            user_id: ObjectId::new(),
+            name: "Smith, Jones & \"Partners\"".to_string(),
+            email: "billing@smithjones.de".to_string(),
+            phone: Some("+49 30 1234567".to_string()),
+            company: Some("Smith Jones GmbH".to_string()),
+            address: None,
+            billing_address: Some(PostalAddress {
+                line1: "Hauptstraße 1".to_string(),
+                line2: None,
+                city: "Berlin".to_string(),
+                postal_code: Some("10115".to_string()),
+                region: None,
+                country: "DE".to_string(),
+            }),
+            country: Some("DE".to_string()),
+            tax_id_type: Some(TaxIdType::EuVat),
+            tax_id: Some("DE136695976".to_string()),
+            notes: Some("Invoices go to accounts,\nnot to the office".to_string()),
+            retainer_balance: 0.0,
+            time_rounding: None,
+            default_hourly_rate: Some(95.5),
+            currency: Some("EUR".to_string()),
+            archived_at: None,
+            created_at: Utc::now(),
+            updated_at: Utc::now(),
+        }
+    }
+
+    fn export(clients: &[Client]) -> String {
+        let mut writer = csv::Writer::from_writer(Vec::new());
+        writer.write_record(HEADERS).unwrap();
+        for client in clients {
+            writer.write_record(record(client)).unwrap();
+        }
+        String::from_utf8(writer.into_inner().unwrap()).unwrap()
+    }
+
+    fn import(csv: &str, columns: &ClientColumns) -> Result<Vec<Result<Client>>> {
+        let mut reader = csv::ReaderBuilder::new().flexible(true).from_reader(csv.as_bytes());
+        let mapping = Mapping::new(columns, &reader.headers().unwrap().clone())?;
+        Ok(reader
+            .records()
+            .map(|record| parse_row(&record.unwrap(), &mapping, ObjectId::new()))
+            .collect())
+    }
+
+    fn message(result: &Result<Client>) -> String {
+        match result {
+            Err(AppError::BadRequest(message)) => message.clone(),
+            other => panic!("expected a bad request, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn exports_round_trip() {
+        let original = client();
+        let csv = export(std::slice::from_ref(&original));
+        let imported = import(&csv, &ClientColumns::default()).unwrap().remove(0).unwrap();
+
+        assert_eq!(imported.name, original.name);
+        assert_eq!(imported.email, original.email);
+        assert_eq!(imported.phone, original.phone);
+        assert_eq!(imported.company, original.company);
+        assert_eq!(imported.billing_address, original.billing_address);
+        assert_eq!(imported.country, original.country);
+        assert_eq!(imported.tax_id_type, original.tax_id_type);
+        assert_eq!(imported.tax_id, original.tax_id);
+        assert_eq!(imported.notes, original.notes);
+        assert_eq!(imported.default_hourly_rate, original.default_hourly_rate);
+        assert_eq!(imported.currency, original.currency);
+    }
+
+    #[test]
+    fn quotes_fields_that_need_it() {
+        let csv = export(&[client()]);
+        assert!(csv.contains(",\"Smith, Jones & \"\"Partners\"\"\","));
+        assert!(csv.contains(",\"Invoices go to accounts,\nnot to the office\","));
+        assert_eq!(csv.lines().next().unwrap(), HEADERS.join(","));
+    }
+
+    #[test]
+    fn maps_columns_and_ignores_unknown_ones() {
+        let csv = "\u{feff}Company Name,E-Mail,Favourite Colour,Phone\nAcme,ap@acme.test,Blue,555-0100\n";
+        let columns = ClientColumns {
+            name: Some("company name".to_string()),
+            email: Some("E-Mail".to_string()),
+            ..Default::default()
+        };
+        let client = import(csv, &columns).unwrap().remove(0).unwrap();
+        assert_eq!(client.name, "Acme");
+        assert_eq!(client.email, "ap@acme.test");
+        assert_eq!(client.phone.as_deref(), Some("555-0100"));
+        assert_eq!(client.notes, None);
+
+        // A mapped column has to exist, and a name is needed one way or another
+        let columns = ClientColumns { email: Some("Email Address".to_string()), ..Default::default() };
+        assert!(import("name,email\nAcme,ap@acme.test\n", &columns).is_err());
+        assert!(import("company,email\nAcme,ap@acme.test\n", &ClientColumns::default()).is_err());
+    }
+
+    #[test]
+    fn reports_invalid_rows() {
+        let csv = "name,email,default_hourly_rate,currency,billing_line1\n\
+                   Acme,not-an-email,,,\n\
+                   Acme,ap@acme.test,lots,,\n\
+                   Acme,ap@acme.test,-5,,\n\
+                   Acme,ap@acme.test,90,euro,\n\
+                   ,ap@acme.test,,,\n\
+                   Acme,ap@acme.test,,,Main St 1\n\
+                   Acme,,,usd,\n";
+        let rows = import(csv, &ClientColumns::default()).unwrap();
+
+        assert_eq!(message(&rows[0]), "Invalid email address 'not-an-email'");
+        assert_eq!(message(&rows[1]), "Invalid hourly rate 'lots'");
+        assert!(rows[2].is_err());
+        assert_eq!(message(&rows[3]), "Invalid currency 'euro'");
+        assert_eq!(message(&rows[4]), "Missing name");
+        assert_eq!(message(&rows[5]), "Billing address has no country");
+        // Clients don't need an email
+        assert_eq!(rows[6].as_ref().unwrap().currency.as_deref(), Some("USD"));
+    }
+}
@@ -0,0 +1,559 @@
+//! Finding clients that are probably the same customer, and merging them.
+//!
+//! Names are compared after dropping case, punctuation and legal-form
+//! suffixes, so "Acme", "ACME Inc" and "acme corp" are the same name.
+//! Clients whose contacts share a company email domain match as well;
+//! addresses at webmail providers say nothing about the company and are
+//! ignored.
+
+use std::collections::{HashMap, HashSet};
+
+use chrono::Utc;
+use mongodb::{
+    bson::{doc, oid::ObjectId, Document},
+    options::FindOptions,
+    ClientSession,
+};
+
+use crate::{
+    database::Database,
+    error::{AppError, Result},
+    models::{
+        Client, ClientContact, ClientMerge, DuplicateCandidate, DuplicateGroup, DuplicateReason,
+        MergeCounts, MergedClient,
+    },
+    services::{
+        contacts, dependents,
+        references::{parse_id, resolve_client},
+    },
+};
+
+pub const DEFAULT_MIN_SIMILARITY: f64 = 0.85;
+
+const LEGAL_FORMS: [&str; 30] = [
+    "the", "inc", "incorporated", "corp", "corporation", "co", "company", "llc", "ltd", "limited",
+    "plc", "llp", "lp", "gmbh", "mbh", "ag", "kg", "ug", "sa", "sas", "sarl", "srl", "spa", "bv",
+    "nv", "pty", "oy", "ab", "as", "group",
+];
+
+const WEBMAIL_DOMAINS: [&str; 16] = [
+    "gmail.com", "googlemail.com", "outlook.com", "hotmail.com", "live.com", "msn.com",
+    "yahoo.com", "icloud.com", "me.com", "aol.com", "proton.me", "protonmail.com", "gmx.de",
+    "gmx.net", "web.de", "mail.com",
+];
+
+/// Lowercase words of the name without punctuation and legal forms.
+fn normalize_name(name: &str) -> String {
+    name.to_lowercase()
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty() && !LEGAL_FORMS.contains(word))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+fn levenshtein(a: &[char], b: &[char]) -> usize {
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.iter().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(ca != cb);
+            current.push(substitution.min(previous[j + 1] + 1).min(current[j] + 1));
+        }
+        previous = current;
+    }
+    previous[b.len()]
+}
+
+/// 1.0 for names equal after normalizing, falling with the edit distance.
+fn similarity(a: &str, b: &str) -> f64 {
+    let (a, b): (Vec<char>, Vec<char>) = (a.chars().collect(), b.chars().collect());
+    if a.is_empty() || b.is_empty() {
+        return 0.0;
+    }
+
+    1.0 - levenshtein(&a, &b) as f64 / a.len().max(b.len()) as f64
+}
+
+fn company_domain(email: &str) -> Option<String> {
+    let domain = email.rsplit_once('@')?.1.trim().to_lowercase();
+    (!domain.is_empty() && !WEBMAIL_DOMAINS.contains(&domain.as_str())).then_some(domain)
+}
+
+struct Profile {
+    client: Client,
+    names: Vec<String>,
+    domains: HashSet<String>,
+}
+
+impl Profile {
+    /// `domains` are those of the client's contacts.
+    fn new(client: Client, mut domains: HashSet<String>) -> Self {
+        let names = std::iter::once(&client.name)
+            .chain(client.company.as_ref())
+            .map(|name| normalize_name(name))
+            .filter(|name| !name.is_empty())
+            .collect();
+        domains.extend(company_domain(&client.email));
+        Profile { client, names, domains }
+    }
+}
+
+fn find_root(parents: &mut [usize], i: usize) -> usize {
+    let mut root = i;
+    while parents[root] != root {
+        root = parents[root];
+    }
+    parents[i] = root;
+    root
+}
+
+/// Groups of clients that match each other directly or through another
+/// client in the group.
+pub async fn find_duplicates(
+    db: &Database,
+    user_id: ObjectId,
+    min_similarity: f64,
+    include_archived: bool,
+) -> Result<Vec<DuplicateGroup>> {
+    let mut filter = doc! { "user_id": user_id };
+    if !include_archived {
+        filter.insert("archived_at", doc! { "$eq": null });
+    }
+
+    let mut domains: HashMap<ObjectId, HashSet<String>> = HashMap::new();
+    let mut cursor = db.client_contacts().find(doc! { "user_id": user_id }, None).await?;
+    while cursor.advance().await? {
+        let contact: ClientContact = cursor.deserialize_current()?;
+        domains.entry(contact.client_id).or_default().extend(company_domain(&contact.email));
+    }
+
+    let mut profiles = Vec::new();
+    let mut cursor = db.clients().find(filter, None).await?;
+    while cursor.advance().await? {
+        let client: Client = cursor.deserialize_current()?;
+        let Some(id) = client.id else { continue };
+        let client_domains = domains.remove(&id).unwrap_or_default();
+        profiles.push(Profile::new(client, client_domains));
+    }
+    profiles.sort_by_key(|profile| profile.client.created_at);
+
+    Ok(group_profiles(&profiles, min_similarity))
+}
+
+/// Groups of profiles that match each other directly or through another
+/// profile in the group, best match first.
+fn group_profiles(profiles: &[Profile], min_similarity: f64) -> Vec<DuplicateGroup> {
+    let mut parents: Vec<usize> = (0..profiles.len()).collect();
+    let mut reasons: HashMap<usize, (Vec<DuplicateReason>, f64)> = HashMap::new();
+    let mut matches = Vec::new();
+    for i in 0..profiles.len() {
+        for j in i + 1..profiles.len() {
+            let (a, b) = (&profiles[i], &profiles[j]);
+            let score = a
+                .names
+                .iter()
+                .flat_map(|x| b.names.iter().map(move |y| similarity(x, y)))
+                .fold(0.0, f64::max);
+
+            let mut found = Vec::new();
+            if score >= min_similarity {
+                found.push(DuplicateReason::SimilarName);
+            }
+            if !a.domains.is_disjoint(&b.domains) {
+                found.push(DuplicateReason::SameEmailDomain);
+            }
+            if !found.is_empty() {
+                matches.push((i, j, found, score));
+                let (root_i, root_j) = (find_root(&mut parents, i), find_root(&mut parents, j));
+                parents[root_j] = root_i;
+            }
+        }
+    }
+    for (i, _, found, score) in matches {
+        let root = find_root(&mut parents, i);
+        let (group_reasons, group_score) = reasons.entry(root).or_default();
+        for reason in found {
+            if !group_reasons.contains(&reason) {
+                group_reasons.push(reason);
+            }
+        }
+        *group_score = group_score.max(score);
+    }
+
+    let mut members: HashMap<usize, Vec<DuplicateCandidate>> = HashMap::new();
+    for (i, profile) in profiles.iter().enumerate() {
+        let root = find_root(&mut parents, i);
+        if reasons.contains_key(&root) {
+            let client = &profile.client;
+            members.entry(root).or_default().push(DuplicateCandidate {
+                id: client.id.unwrap(),
+                name: client.name.clone(),
+                company: client.company.clone(),
+                email: client.email.clone(),
+            });
+        }
+    }
+
+    let mut groups: Vec<DuplicateGroup> = members
+        .into_iter()
+        .map(|(root, clients)| {
+            let (reasons, score) = reasons.remove(&root).unwrap_or_default();
+            DuplicateGroup { clients, reasons, score }
+        })
+        .collect();
+    groups.sort_by(|a, b| b.score.total_cmp(&a.score));
+
+    groups
+}
+
+/// Folds the clients in `merge_ids` into `survivor_id`: their invoices,
+/// contracts, projects (with the projects' time entries) and contacts move
+/// to the survivor, empty survivor fields are filled from them, and they
+/// are deleted. A retainer ledger can't be merged into another client's, so
+/// a client with one can only be the survivor.
+pub async fn merge(
+    db: &Database,
+    user_id: ObjectId,
+    survivor_id: &str,
+    merge_ids: &[String],
+) -> Result<ClientMerge> {
+    let survivor = resolve_client(db, user_id, survivor_id).await?;
+    let survivor_id = survivor.id.unwrap();
+
+    let mut ids = Vec::new();
+    for id in merge_ids {
+        let id = parse_id(id, "client")?;
+        if id == survivor_id {
+            return Err(AppError::BadRequest("Can't merge a client into itself".to_string()));
+        }
+        if !ids.contains(&id) {
+            ids.push(id);
+        }
+    }
+    if ids.is_empty() {
+        return Err(AppError::BadRequest("No clients to merge".to_string()));
+    }
+
+    let mut merged = Vec::new();
+    for &id in &ids {
+        let client = db
+            .clients()
+            .find_one(doc! { "_id": id, "user_id": user_id }, None)
+            .await?
+            .ok_or(AppError::NotFound("Client not found".to_string()))?;
+        let ledger = db
+            .retainer_transactions()
+            .count_documents(doc! { "user_id": user_id, "client_id": id }, None)
+            .await?;
+        if ledger > 0 || client.retainer_balance != 0.0 {
+            return Err(AppError::Conflict(format!(
+                "'{}' has a retainer; merge the other clients into it instead",
+                client.name
+            )));
+        }
+        merged.push(client);
+    }
+
+    let (fields, filled_fields) = fill_fields(&survivor, &merged)?;
+
+    let mut session = db.client.start_session(None).await?;
+    session.start_transaction(None).await?;
+    let record = match apply_merge(db, &mut session, user_id, survivor_id, &merged, fields, filled_fields).await {
+        Ok(record) => {
+            session.commit_transaction().await?;
+            record
+        }
+        Err(err) => {
+            session.abort_transaction().await?;
+            return Err(err);
+        }
+    };
+
+    let survivor = db
+        .clients()
+        .find_one(doc! { "_id": survivor_id }, None)
+        .await?
+        .ok_or(AppError::NotFound("Client not found".to_string()))?;
+    contacts::ensure_primary(db, &survivor).await?;
+
+    Ok(record)
+}
+
+/// The writes of a merge, made in `session`. Projects keep billing as they
+/// did: those without a rate or rounding rule of their own get the merged
+/// client's, rather than falling back to the survivor's.
+async fn apply_merge(
+    db: &Database,
+    session: &mut ClientSession,
+    user_id: ObjectId,
+    survivor_id: ObjectId,
+    merged: &[Client],
+    mut fields: Document,
+    filled_fields: Vec<String>,
+) -> Result<ClientMerge> {
+    let ids: Vec<ObjectId> = merged.iter().filter_map(|client| client.id).collect();
+    for client in merged {
+        dependents::pin_client_billing(db, session, user_id, client).await?;
+    }
+
+    let filter = doc! { "user_id": user_id, "client_id": { "$in": &ids } };
+    let update = doc! { "$set": { "client_id": survivor_id, "updated_at": Utc::now() } };
+    let mut moved = MergeCounts::default();
+    let project_ids: Vec<ObjectId> = db
+        .projects()
+        .distinct_with_session("_id", filter.clone(), None, session)
+        .await?
+        .into_iter()
+        .filter_map(|id| id.as_object_id())
+        .collect();
+    moved.time_entries = db
+        .time_entries()
+        .count_documents_with_session(
+            doc! { "user_id": user_id, "project_id": { "$in": &project_ids } },
+            None,
+            session,
+        )
+        .await?;
+    moved.invoices = db
+        .invoices()
+        .update_many_with_session(filter.clone(), update.clone(), None, session)
+        .await?
+        .modified_count;
+    moved.contracts = db
+        .contracts()
+        .update_many_with_session(filter.clone(), update.clone(), None, session)
+        .await?
+        .modified_count;
+    moved.projects = db
+        .projects()
+        .update_many_with_session(filter, update, None, session)
+        .await?
+        .modified_count;
+    moved.contacts = contacts::move_to(db, session, user_id, survivor_id, &ids).await?;
+
+    if !fields.is_empty() {
+        fields.insert("updated_at", Utc::now());
+        db.clients()
+            .update_one_with_session(doc! { "_id": survivor_id }, doc! { "$set": fields }, None, session)
+            .await?;
+    }
+    db.clients()
+        .delete_many_with_session(doc! { "_id": { "$in": &ids }, "user_id": user_id }, None, session)
+        .await?;
+
+    let mut record = ClientMerge {
+        id: None,
+        user_id,
+        survivor_id,
+        merged: merged
+            .iter()
+            .map(|client| MergedClient {
+                id: client.id.unwrap(),
+                name: client.name.clone(),
+                email: client.email.clone(),
+            })
+            .collect(),
+        moved,
+        filled_fields,
+        created_at: Utc::now(),
+    };
+    let result = db.client_merges().insert_one_with_session(&record, None, session).await?;
+    record.id = result.inserted_id.as_object_id();
+
+    Ok(record)
+}
+
+/// The survivor's empty fields, filled from the first merged client that
+/// has them. Notes are kept from every client.
+fn fill_fields(survivor: &Client, merged: &[Client]) -> Result<(Document, Vec<String>)> {
+    let mut fields = Document::new();
+    let first = |value: fn(&Client) -> bool| merged.iter().find(|client| value(client));
+
+    if survivor.email.is_empty() {
+        if let Some(client) = first(|client| !client.email.is_empty()) {
+            fields.insert("email", &client.email);
+        }
+    }
+    if survivor.phone.is_none() {
+        if let Some(client) = first(|client| client.phone.is_some()) {
+            fields.insert("phone", client.phone.as_deref());
+        }
+    }
+    if survivor.company.is_none() {
+        if let Some(client) = first(|client| client.company.is_some()) {
+            fields.insert("company", client.company.as_deref());
+        }
+    }
+    if survivor.address.is_none() {
+        if let Some(client) = first(|client| client.address.is_some()) {
+            fields.insert("address", client.address.as_deref());
+        }
+    }
+    if survivor.billing_address.is_none() {
+        if let Some(client) = first(|client| client.billing_address.is_some()) {
+            fields.insert("billing_address", bson::to_bson(&client.billing_address)?);
+        }
+    }
+    if survivor.country.is_none() {
+        if let Some(client) = first(|client| client.country.is_some()) {
+            fields.insert("country", client.country.as_deref());
+        }
+    }
+    // Type and number only make sense together
+    if survivor.tax_id.is_none() {
+        if let Some(client) = first(|client| client.tax_id.is_some()) {
+            fields.insert("tax_id_type", bson::to_bson(&client.tax_id_type)?);
+            fields.insert("tax_id", client.tax_id.as_deref());
+        }
+    }
+    if survivor.time_rounding.is_none() {
+        if let Some(client) = first(|client| client.time_rounding.is_some()) {
+            fields.insert("time_rounding", bson::to_bson(&client.time_rounding)?);
+        }
+    }
+    if survivor.default_hourly_rate.is_none() {
+        if let Some(client) = first(|client| client.default_hourly_rate.is_some()) {
+            fields.insert("default_hourly_rate", client.default_hourly_rate);
+        }
+    }
+
+    let mut notes: Vec<&str> = survivor.notes.as_deref().into_iter().collect();
+    for note in merged.iter().filter_map(|client| client.notes.as_deref()) {
+        if !note.trim().is_empty() && !notes.contains(&note) {
+            notes.push(note);
+        }
+    }
+    if notes.len() > usize::from(survivor.notes.is_some()) {
+        fields.insert("notes", notes.join("\n\n"));
+    }
+
+    let filled = fields.keys().cloned().collect();
+    Ok((fields, filled))
+}
+
+pub async fn list_merges(db: &Database, user_id: ObjectId) -> Result<Vec<ClientMerge>> {
+    let options = FindOptions::builder()
+        .sort(doc! { "created_at": -1 })
+        .build();
+    let mut cursor = db.client_merges().find(doc! { "user_id": user_id }, options).await?;
+
+    let mut merges = Vec::new();
+    while cursor.advance().await? {
+        merges.push(cursor.deserialize_current()?);
+    }
+
+    Ok(merges)
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Duration;
+
+    use super::*;
+
+    fn profile(name: &str, company: Option<&str>, email: &str, contact_emails: &[&str]) -> Profile {
+        let client = Client {
+            id: Some(ObjectId::new()),
+            user_id: ObjectId::new(),
+            name: name.to_string(),
+            email: email.to_string(),
+            phone: None,
+            company: company.map(str::to_string),
+            address: None,
+            billing_address: None,
+            country: None,
+            tax_id_type: None,
+            tax_id: None,
+            notes: None,
+            retainer_balance: 0.0,
+            time_rounding: None,
+            default_hourly_rate: None,
+            currency: None,
+            archived_at: None,
+            created_at: Utc::now(),
+            updated_at: Utc::now(),
+        };
+        Profile::new(client, contact_emails.iter().filter_map(|email| company_domain(email)).collect())
+    }
+
+    fn names(group: &DuplicateGroup) -> Vec<&str> {
+        group.clients.iter().map(|client| client.name.as_str()).collect()
+    }
+
+    #[test]
+    fn strips_case_punctuation_and_legal_forms() {
+        assert_eq!(normalize_name("ACME, Inc."), "acme");
+        assert_eq!(normalize_name("The Acme Group GmbH"), "acme");
+        assert_eq!(normalize_name("acme corp"), "acme");
+        assert_eq!(normalize_name("Müller & Söhne KG"), "müller söhne");
+        assert_eq!(normalize_name("Northwind Traders Pty Ltd"), "northwind traders");
+        assert_eq!(normalize_name("Inc."), "");
+    }
+
+    #[test]
+    fn scores_by_edit_distance() {
+        assert_eq!(similarity("acme", "acme"), 1.0);
+        assert_eq!(similarity("acme", ""), 0.0);
+        assert_eq!(similarity("northwind traders", "northwind trader"), 1.0 - 1.0 / 17.0);
+        assert_eq!(similarity("kitten", "sitting"), 1.0 - 3.0 / 7.0);
+
+        // Two edits in twenty characters clear the default threshold,
+        // four don't
+        let name = "abcdefghijklmnopqrst";
+        assert!(similarity(name, "abcdefghijklmnopqrXY") >= DEFAULT_MIN_SIMILARITY);
+        assert!(similarity(name, "abcdefghijklmnopWXYZ") < DEFAULT_MIN_SIMILARITY);
+    }
+
+    #[test]
+    fn ignores_webmail_domains() {
+        assert_eq!(company_domain("Jane@Acme.COM"), Some("acme.com".to_string()));
+        assert_eq!(company_domain("jane.doe@gmail.com"), None);
+        assert_eq!(company_domain("jane@GMX.de"), None);
+        assert_eq!(company_domain("not an email"), None);
+    }
+
+    #[test]
+    fn groups_matches_transitively() {
+        let mut profiles = vec![
+            profile("Acme Inc", None, "", &[]),
+            profile("ACME Corporation", None, "", &["ap@acme.com"]),
+            // Matches only through the contact of the client above
+            profile("Road Runner Supplies", None, "orders@acme.com", &[]),
+            profile("Globex", None, "", &[]),
+            profile("Jane Doe", Some("Globex LLC"), "jane@gmail.com", &[]),
+            profile("Initech", None, "", &[]),
+        ];
+        for (index, profile) in profiles.iter_mut().enumerate() {
+            profile.client.created_at += Duration::seconds(index as i64);
+        }
+
+        let mut groups = group_profiles(&profiles, DEFAULT_MIN_SIMILARITY);
+        // Both groups score 1.0, so their order isn't fixed
+        groups.sort_by_key(|group| group.clients[0].name.clone());
+        assert_eq!(groups.len(), 2);
+        assert_eq!(names(&groups[0]), ["Acme Inc", "ACME Corporation", "Road Runner Supplies"]);
+        assert_eq!(groups[0].reasons, [DuplicateReason::SimilarName, DuplicateReason::SameEmailDomain]);
+        assert_eq!(groups[0].score, 1.0);
+        // The company name counts as well as the client name
+        assert_eq!(names(&groups[1]), ["Globex", "Jane Doe"]);
+        assert_eq!(groups[1].reasons, [DuplicateReason::SimilarName]);
+    }
+
+    #[test]
+    fn near_misses_stay_apart() {
+        let profiles = vec![
+            profile("Globex", None, "", &[]),
+            profile("Globe", None, "", &[]),
+            profile("Acme", None, "", &[]),
+            profile("Acme Labs", None, "", &[]),
+            profile("Initech", None, "jane@gmail.com", &[]),
+            profile("Initrode", None, "john@gmail.com", &[]),
+        ];
+        assert!(group_profiles(&profiles, DEFAULT_MIN_SIMILARITY).is_empty());
+
+        // A lower threshold lets the closest pair through
+        let groups = group_profiles(&profiles, 0.8);
+        assert_eq!(groups.len(), 1);
+        assert_eq!(names(&groups[0]), ["Globex", "Globe"]);
+    }
+}
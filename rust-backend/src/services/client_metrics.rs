@@ -0,0 +1,158 @@
+//! Lifetime figures per client, aggregated from its invoices and the time
+//! logged on its projects.
+
+use std::collections::HashMap;
+
+use chrono::{DateTime, Utc};
+use mongodb::bson::{doc, oid::ObjectId, Bson, Document};
+
+use crate::{
+    database::Database,
+    error::Result,
+    models::{ClientMetrics, CurrencyTotals, InvoiceStatus},
+};
+
+const MILLIS_PER_DAY: i64 = 86_400_000;
+
+fn number(row: &Document, key: &str) -> f64 {
+    match row.get(key) {
+        Some(Bson::Double(n)) => *n,
+        Some(Bson::Int32(n)) => f64::from(*n),
+        Some(Bson::Int64(n)) => *n as f64,
+        _ => 0.0,
+    }
+}
+
+fn date(row: &Document, key: &str) -> Option<DateTime<Utc>> {
+    row.get_datetime(key).ok().map(|time| time.to_chrono())
+}
+
+/// Metrics for each of `client_ids`; clients without invoices or time get
+/// zeroes.
+pub async fn client_metrics(
+    db: &Database,
+    user_id: ObjectId,
+    client_ids: &[ObjectId],
+) -> Result<Vec<ClientMetrics>> {
+    let mut metrics: HashMap<ObjectId, ClientMetrics> = client_ids
+        .iter()
+        .map(|&client_id| {
+            let empty = ClientMetrics {
+                client_id,
+                totals: Vec::new(),
+                hours: 0.0,
+                last_activity_at: None,
+                average_days_to_pay: None,
+            };
+            (client_id, empty)
+        })
+        .collect();
+    let mut days_to_pay: HashMap<ObjectId, (f64, f64)> = HashMap::new();
+
+    let paid = doc! { "$eq": ["$status", bson::to_bson(&InvoiceStatus::Paid)?] };
+    // Invoices paid before `paid_at` was recorded are left out of the average
+    let dated = doc! { "$and": [&paid, { "$eq": [{ "$type": "$paid_at" }, "date"] }] };
+    let mut cursor = db
+        .invoices()
+        .aggregate(
+            [
+                doc! {
+                    "$match": {
+                        "user_id": user_id,
+                        "client_id": { "$in": client_ids },
+                        "status": { "$ne": bson::to_bson(&InvoiceStatus::Draft)? },
+                    }
+                },
+                doc! {
+                    "$group": {
+                        "_id": { "client_id": "$client_id", "currency": "$currency" },
+                        "billed": { "$sum": "$total" },
+                        "paid": { "$sum": { "$cond": [&paid, "$total", 0] } },
+                        "paid_count": { "$sum": { "$cond": [&dated, 1, 0] } },
+                        "paid_days": {
+                            "$sum": {
+                                "$cond": [
+                                    &dated,
+                                    { "$divide": [{ "$subtract": ["$paid_at", "$date"] }, MILLIS_PER_DAY] },
+                                    0,
+                                ]
+                            }
+                        },
+                        "last_invoice": { "$max": "$date" },
+                    }
+                },
+                doc! { "$sort": { "_id.currency": 1 } },
+            ],
+            None,
+        )
+        .await?;
+    while cursor.advance().await? {
+        let row = cursor.deserialize_current()?;
+        let Ok(key) = row.get_document("_id") else { continue };
+        let Some(entry) = key.get_object_id("client_id").ok().and_then(|id| metrics.get_mut(&id)) else {
+            continue;
+        };
+
+        let (billed, paid) = (number(&row, "billed"), number(&row, "paid"));
+        entry.totals.push(CurrencyTotals {
+            currency: key.get_str("currency").unwrap_or_default().to_string(),
+            billed,
+            paid,
+            outstanding: billed - paid,
+        });
+        entry.last_activity_at = entry.last_activity_at.max(date(&row, "last_invoice"));
+
+        let days = days_to_pay.entry(entry.client_id).or_default();
+        days.0 += number(&row, "paid_days");
+        days.1 += number(&row, "paid_count");
+    }
+
+    let mut cursor = db
+        .time_entries()
+        .aggregate(
+            [
+                doc! {
+                    "$match": {
+                        "user_id": user_id,
+                        "project_id": { "$ne": null },
+                        "draft": { "$ne": true },
+                    }
+                },
+                doc! {
+                    "$lookup": {
+                        "from": "projects",
+                        "localField": "project_id",
+                        "foreignField": "_id",
+                        "as": "project",
+                    }
+                },
+                doc! { "$unwind": "$project" },
+                doc! { "$match": { "project.client_id": { "$in": client_ids } } },
+                doc! {
+                    "$group": {
+                        "_id": "$project.client_id",
+                        "seconds": { "$sum": { "$ifNull": ["$duration", 0] } },
+                        "last_entry": { "$max": { "$ifNull": ["$end_time", "$start_time"] } },
+                    }
+                },
+            ],
+            None,
+        )
+        .await?;
+    while cursor.advance().await? {
+        let row = cursor.deserialize_current()?;
+        let Some(entry) = row.get_object_id("_id").ok().and_then(|id| metrics.get_mut(&id)) else {
+            continue;
+        };
+        entry.hours = number(&row, "seconds") / 3600.0;
+        entry.last_activity_at = entry.last_activity_at.max(date(&row, "last_entry"));
+    }
+
+    for (client_id, (days, count)) in days_to_pay {
+        if let Some(entry) = metrics.get_mut(&client_id).filter(|_| count > 0.0) {
+            entry.average_days_to_pay = Some(days / count);
+        }
+    }
+
+    Ok(client_ids.iter().filter_map(|id| metrics.remove(id)).collect())
+}
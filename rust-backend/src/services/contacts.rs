@@ -0,0 +1,428 @@
+//! Client contacts: one primary contact per client, mirrored into the
+//! client's `email` and `phone`, and role-based routing of outgoing mail.
+
+use std::collections::{HashMap, HashSet};
+
+use chrono::Utc;
+use mongodb::{
+    bson::{doc, oid::ObjectId, Document},
+    options::{FindOneAndUpdateOptions, FindOneOptions, FindOptions, ReturnDocument},
+    ClientSession,
+};
+
+use crate::{
+    database::Database,
+    error::{AppError, Result},
+    models::{
+        Client, ClientContact, ContactRole, CreateContactRequest, MessagePurpose, Recipients,
+        UpdateContactRequest,
+    },
+};
+
+pub fn validate_email(email: &str) -> Result<()> {
+    let valid = email
+        .split_once('@')
+        .is_some_and(|(local, domain)| !local.is_empty() && domain.contains('.') && !domain.starts_with('.'))
+        && !email.contains(char::is_whitespace);
+    if !valid {
+        return Err(AppError::BadRequest(format!("Invalid email address '{}'", email)));
+    }
+
+    Ok(())
+}
+
+pub async fn list(db: &Database, user_id: ObjectId, client_id: ObjectId) -> Result<Vec<ClientContact>> {
+    let options = FindOptions::builder()
+        .sort(doc! { "is_primary": -1, "name": 1 })
+        .build();
+    let mut cursor = db
+        .client_contacts()
+        .find(doc! { "user_id": user_id, "client_id": client_id }, options)
+        .await?;
+
+    let mut contacts = Vec::new();
+    while cursor.advance().await? {
+        contacts.push(cursor.deserialize_current()?);
+    }
+
+    Ok(contacts)
+}
+
+/// Adds a contact. A client's first contact becomes its primary contact.
+pub async fn create(
+    db: &Database,
+    user_id: ObjectId,
+    client_id: ObjectId,
+    request: CreateContactRequest,
+) -> Result<ClientContact> {
+    db.clients()
+        .find_one(doc! { "_id": client_id, "user_id": user_id }, None)
+        .await?
+        .ok_or(AppError::NotFound("Client not found".to_string()))?;
+    validate_email(&request.email)?;
+
+    let existing = db
+        .client_contacts()
+        .count_documents(doc! { "user_id": user_id, "client_id": client_id }, None)
+        .await?;
+    let contact = ClientContact {
+        id: None,
+        user_id,
+        client_id,
+        name: request.name,
+        email: request.email,
+        phone: request.phone,
+        title: request.title,
+        roles: dedup_roles(request.roles),
+        is_primary: false,
+        created_at: Utc::now(),
+        updated_at: Utc::now(),
+    };
+    let result = db.client_contacts().insert_one(&contact, None).await?;
+    let contact = ClientContact { id: result.inserted_id.as_object_id(), ..contact };
+
+    if request.is_primary || existing == 0 {
+        return make_primary(db, &contact).await;
+    }
+
+    Ok(contact)
+}
+
+pub async fn update(
+    db: &Database,
+    user_id: ObjectId,
+    client_id: ObjectId,
+    contact_id: ObjectId,
+    request: UpdateContactRequest,
+) -> Result<ClientContact> {
+    let mut update_doc = doc! { "updated_at": Utc::now() };
+
+    if let Some(name) = request.name {
+        update_doc.insert("name", name);
+    }
+    if let Some(email) = request.email {
+        validate_email(&email)?;
+        update_doc.insert("email", email);
+    }
+    if let Some(phone) = request.phone {
+        update_doc.insert("phone", phone);
+    }
+    if let Some(title) = request.title {
+        update_doc.insert("title", title);
+    }
+    if let Some(roles) = request.roles {
+        update_doc.insert("roles", bson::to_bson(&dedup_roles(roles))?);
+    }
+    if request.is_primary == Some(false) {
+        return Err(AppError::BadRequest(
+            "Make another contact primary instead of unsetting this one".to_string(),
+        ));
+    }
+
+    let contact = db
+        .client_contacts()
+        .find_one_and_update(
+            doc! { "_id": contact_id, "client_id": client_id, "user_id": user_id },
+            doc! { "$set": update_doc },
+            FindOneAndUpdateOptions::builder()
+                .return_document(ReturnDocument::After)
+                .build(),
+        )
+        .await?
+        .ok_or(AppError::NotFound("Contact not found".to_string()))?;
+
+    if contact.is_primary || request.is_primary == Some(true) {
+        return make_primary(db, &contact).await;
+    }
+
+    Ok(contact)
+}
+
+/// Removes a contact. When it was the primary one, the oldest remaining
+/// contact takes over.
+pub async fn delete(
+    db: &Database,
+    user_id: ObjectId,
+    client_id: ObjectId,
+    contact_id: ObjectId,
+) -> Result<()> {
+    let contact = db
+        .client_contacts()
+        .find_one_and_delete(doc! { "_id": contact_id, "client_id": client_id, "user_id": user_id }, None)
+        .await?
+        .ok_or(AppError::NotFound("Contact not found".to_string()))?;
+
+    if contact.is_primary {
+        let options = FindOneOptions::builder()
+            .sort(doc! { "created_at": 1 })
+            .build();
+        let next = db
+            .client_contacts()
+            .find_one(doc! { "user_id": user_id, "client_id": client_id }, options)
+            .await?;
+        if let Some(next) = next {
+            make_primary(db, &next).await?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Makes `contact` its client's only primary contact and copies its email
+/// and phone onto the client.
+async fn make_primary(db: &Database, contact: &ClientContact) -> Result<ClientContact> {
+    db.client_contacts()
+        .update_many(
+            doc! { "client_id": contact.client_id, "_id": { "$ne": contact.id }, "is_primary": true },
+            doc! { "$set": { "is_primary": false, "updated_at": Utc::now() } },
+            None,
+        )
+        .await?;
+    db.client_contacts()
+        .update_one(doc! { "_id": contact.id }, doc! { "$set": { "is_primary": true } }, None)
+        .await?;
+    db.clients()
+        .update_one(
+            doc! { "_id": contact.client_id, "user_id": contact.user_id },
+            doc! {
+                "$set": {
+                    "email": &contact.email,
+                    "phone": contact.phone.as_deref(),
+                    "updated_at": Utc::now(),
+                }
+            },
+            None,
+        )
+        .await?;
+
+    Ok(ClientContact { is_primary: true, ..contact.clone() })
+}
+
+/// Gives a client without a primary contact one, preferring the contact
+/// with the client's own email.
+pub async fn ensure_primary(db: &Database, client: &Client) -> Result<()> {
+    let contacts = list(db, client.user_id, client.id.unwrap()).await?;
+    if contacts.iter().any(|contact| contact.is_primary) {
+        return Ok(());
+    }
+
+    let contact = contacts
+        .iter()
+        .find(|contact| contact.email.eq_ignore_ascii_case(&client.email))
+        .or(contacts.first());
+    if let Some(contact) = contact {
+        make_primary(db, contact).await?;
+    }
+
+    Ok(())
+}
+
+/// Moves the contacts of the clients in `ids` to `target_id` as secondary
+/// contacts, in `session`. A contact whose email the target already has is
+/// dropped.
+pub async fn move_to(
+    db: &Database,
+    session: &mut ClientSession,
+    user_id: ObjectId,
+    target_id: ObjectId,
+    ids: &[ObjectId],
+) -> Result<u64> {
+    let mut emails = HashSet::new();
+    let mut cursor = db
+        .client_contacts()
+        .find_with_session(doc! { "user_id": user_id, "client_id": target_id }, None, session)
+        .await?;
+    while let Some(contact) = cursor.next(session).await.transpose()? {
+        emails.insert(contact.email.to_lowercase());
+    }
+
+    let mut cursor = db
+        .client_contacts()
+        .find_with_session(doc! { "user_id": user_id, "client_id": { "$in": ids } }, None, session)
+        .await?;
+    let mut contacts = Vec::new();
+    while let Some(contact) = cursor.next(session).await.transpose()? {
+        contacts.push(contact);
+    }
+
+    let mut moved = 0;
+    for contact in contacts {
+        if !emails.insert(contact.email.to_lowercase()) {
+            db.client_contacts()
+                .delete_one_with_session(doc! { "_id": contact.id }, None, session)
+                .await?;
+            continue;
+        }
+        db.client_contacts()
+            .update_one_with_session(
+                doc! { "_id": contact.id },
+                doc! {
+                    "$set": { "client_id": target_id, "is_primary": false, "updated_at": Utc::now() }
+                },
+                None,
+                session,
+            )
+            .await?;
+        moved += 1;
+    }
+
+    Ok(moved)
+}
+
+/// Applies an edit of the client's own `email`/`phone` to its primary
+/// contact, creating one if the client has none yet.
+pub async fn sync_primary(
+    db: &Database,
+    client: &Client,
+    email: Option<&str>,
+    phone: Option<&str>,
+) -> Result<()> {
+    // An empty email means the client has none; the contact keeps its own
+    let email = email.filter(|email| !email.is_empty());
+    let mut update_doc = Document::new();
+    if let Some(email) = email {
+        update_doc.insert("email", email);
+    }
+    if let Some(phone) = phone {
+        update_doc.insert("phone", phone);
+    }
+    if update_doc.is_empty() {
+        return Ok(());
+    }
+    update_doc.insert("updated_at", Utc::now());
+
+    let result = db
+        .client_contacts()
+        .update_one(
+            doc! { "client_id": client.id, "user_id": client.user_id, "is_primary": true },
+            doc! { "$set": update_doc },
+            None,
+        )
+        .await?;
+    if result.matched_count == 0 {
+        let contact = ClientContact {
+            email: email.unwrap_or(&client.email).to_string(),
+            phone: phone.map(str::to_string).or_else(|| client.phone.clone()),
+            ..primary_from(client)
+        };
+        if !contact.email.is_empty() {
+            db.client_contacts().insert_one(contact, None).await?;
+        }
+    }
+
+    Ok(())
+}
+
+/// The contact a client created with a single email starts out with.
+pub fn primary_from(client: &Client) -> ClientContact {
+    ClientContact {
+        id: None,
+        user_id: client.user_id,
+        client_id: client.id.unwrap(),
+        name: client.name.clone(),
+        email: client.email.clone(),
+        phone: client.phone.clone(),
+        title: None,
+        roles: Vec::new(),
+        is_primary: true,
+        created_at: Utc::now(),
+        updated_at: Utc::now(),
+    }
+}
+
+pub async fn recipients(
+    db: &Database,
+    client: &Client,
+    purpose: MessagePurpose,
+) -> Result<Recipients> {
+    let contacts = list(db, client.user_id, client.id.unwrap()).await?;
+    let with_role = |role: ContactRole| -> Vec<String> {
+        contacts
+            .iter()
+            .filter(|contact| contact.roles.contains(&role))
+            .map(|contact| contact.email.clone())
+            .collect()
+    };
+    let primary: Vec<String> = contacts
+        .iter()
+        .filter(|contact| contact.is_primary)
+        .map(|contact| contact.email.clone())
+        .collect();
+
+    let (to, cc) = match purpose {
+        MessagePurpose::Invoice => (with_role(ContactRole::Billing), with_role(ContactRole::AccountsPayable)),
+        MessagePurpose::Contract => (with_role(ContactRole::Legal), primary.clone()),
+        MessagePurpose::Project => (with_role(ContactRole::ProjectLead), Vec::new()),
+    };
+
+    // Invoices with no billing contact go to accounts payable directly
+    let (to, cc) = match (to.is_empty(), purpose) {
+        (true, MessagePurpose::Invoice) if !cc.is_empty() => (cc, Vec::new()),
+        (true, _) if !primary.is_empty() => (primary, cc),
+        (true, _) if !client.email.is_empty() => (vec![client.email.clone()], cc),
+        _ => (to, cc),
+    };
+
+    // Each address once, in `to` if it's there
+    let mut seen = HashSet::new();
+    let to = to.into_iter().filter(|email| seen.insert(email.to_lowercase())).collect();
+    let cc = cc.into_iter().filter(|email| seen.insert(email.to_lowercase())).collect();
+
+    Ok(Recipients { to, cc })
+}
+
+/// Every email address the user's clients and contacts use, lowercased,
+/// with the client it belongs to.
+pub async fn emails_in_use(db: &Database, user_id: ObjectId) -> Result<HashMap<String, ObjectId>> {
+    let mut emails = HashMap::new();
+
+    let mut cursor = db.clients().find(doc! { "user_id": user_id }, None).await?;
+    while cursor.advance().await? {
+        let client: Client = cursor.deserialize_current()?;
+        if let (Some(id), false) = (client.id, client.email.is_empty()) {
+            emails.insert(client.email.to_lowercase(), id);
+        }
+    }
+    let mut cursor = db.client_contacts().find(doc! { "user_id": user_id }, None).await?;
+    while cursor.advance().await? {
+        let contact: ClientContact = cursor.deserialize_current()?;
+        emails.insert(contact.email.to_lowercase(), contact.client_id);
+    }
+
+    Ok(emails)
+}
+
+/// Gives every client that only has the old single `email`/`phone` a
+/// primary contact holding them. Safe to run on every start.
+pub async fn migrate_single_contacts(db: &Database) -> mongodb::error::Result<u64> {
+    let with_contacts: Vec<ObjectId> = db
+        .client_contacts()
+        .distinct("client_id", None, None)
+        .await?
+        .into_iter()
+        .filter_map(|id| id.as_object_id())
+        .collect();
+
+    let mut cursor = db
+        .clients()
+        .find(doc! { "_id": { "$nin": with_contacts }, "email": { "$nin": ["", null] } }, None)
+        .await?;
+    let mut migrated = 0;
+    while cursor.advance().await? {
+        let client: Client = cursor.deserialize_current()?;
+        db.client_contacts().insert_one(primary_from(&client), None).await?;
+        migrated += 1;
+    }
+
+    Ok(migrated)
+}
+
+fn dedup_roles(roles: Vec<ContactRole>) -> Vec<ContactRole> {
+    let mut unique = Vec::new();
+    for role in roles {
+        if !unique.contains(&role) {
+            unique.push(role);
+        }
+    }
+    unique
+}
@@ -0,0 +1,302 @@
+//! What still points at a client or project, and what happens to it when
+//! the client or project goes away.
+
+use chrono::Utc;
+use mongodb::{
+    bson::{doc, oid::ObjectId, Bson, Document},
+    options::UpdateOptions,
+    ClientSession,
+};
+use serde::de::DeserializeOwned;
+
+use crate::{
+    database::Database,
+    error::{AppError, Result},
+    models::{Client, DependentRef, Dependents, Project},
+    services::contacts,
+};
+
+pub async fn client_dependents(db: &Database, user_id: ObjectId, client_id: ObjectId) -> Result<Dependents> {
+    let filter = doc! { "user_id": user_id, "client_id": client_id };
+
+    Ok(Dependents {
+        invoices: refs(db.invoices(), filter.clone(), |invoice| (invoice.id, invoice.invoice_number)).await?,
+        contracts: refs(db.contracts(), filter.clone(), |contract| (contract.id, contract.title)).await?,
+        projects: refs(db.projects(), filter.clone(), |project| (project.id, project.name)).await?,
+        retainer_transactions: db.retainer_transactions().count_documents(filter, None).await?,
+        ..Default::default()
+    })
+}
+
+pub async fn project_dependents(db: &Database, user_id: ObjectId, project_id: ObjectId) -> Result<Dependents> {
+    let filter = doc! { "user_id": user_id, "project_id": project_id };
+
+    Ok(Dependents {
+        tasks: refs(db.tasks(), filter.clone(), |task| (task.id, task.name)).await?,
+        time_entries: db.time_entries().count_documents(filter, None).await?,
+        ..Default::default()
+    })
+}
+
+async fn refs<T: DeserializeOwned + Send + Sync>(
+    collection: mongodb::Collection<T>,
+    filter: Document,
+    label: impl Fn(T) -> (Option<ObjectId>, String),
+) -> Result<Vec<DependentRef>> {
+    let mut cursor = collection.find(filter, None).await?;
+    let mut refs = Vec::new();
+    while cursor.advance().await? {
+        if let (Some(id), label) = label(cursor.deserialize_current()?) {
+            refs.push(DependentRef { id, label });
+        }
+    }
+
+    Ok(refs)
+}
+
+/// Archives the client together with its projects.
+pub async fn archive_client(db: &Database, user_id: ObjectId, client_id: ObjectId) -> Result<()> {
+    let now = Utc::now();
+    let result = db
+        .clients()
+        .update_one(
+            doc! { "_id": client_id, "user_id": user_id },
+            doc! { "$set": { "archived_at": now, "updated_at": now } },
+            None,
+        )
+        .await?;
+    if result.matched_count == 0 {
+        return Err(AppError::NotFound("Client not found".to_string()));
+    }
+
+    db.projects()
+        .update_many(
+            doc! { "user_id": user_id, "client_id": client_id, "archived_at": null },
+            doc! { "$set": { "archived_at": now, "updated_at": now } },
+            None,
+        )
+        .await?;
+
+    Ok(())
+}
+
+/// Restores the client, and the projects that were archived along with it.
+pub async fn unarchive_client(db: &Database, user_id: ObjectId, client_id: ObjectId) -> Result<()> {
+    let client = find_owned::<Client>(db.clients(), user_id, client_id, "Client").await?;
+    let Some(archived_at) = client.archived_at else {
+        return Ok(());
+    };
+
+    let now = Utc::now();
+    db.clients()
+        .update_one(
+            doc! { "_id": client_id },
+            doc! { "$set": { "updated_at": now }, "$unset": { "archived_at": "" } },
+            None,
+        )
+        .await?;
+    db.projects()
+        .update_many(
+            doc! { "user_id": user_id, "client_id": client_id, "archived_at": archived_at },
+            doc! { "$set": { "updated_at": now }, "$unset": { "archived_at": "" } },
+            None,
+        )
+        .await?;
+
+    Ok(())
+}
+
+pub async fn archive_project(db: &Database, user_id: ObjectId, project_id: ObjectId) -> Result<()> {
+    let now = Utc::now();
+    let result = db
+        .projects()
+        .update_one(
+            doc! { "_id": project_id, "user_id": user_id },
+            doc! { "$set": { "archived_at": now, "updated_at": now } },
+            None,
+        )
+        .await?;
+    if result.matched_count == 0 {
+        return Err(AppError::NotFound("Project not found".to_string()));
+    }
+
+    Ok(())
+}
+
+/// Points the client's invoices, contracts and projects at `target` and
+/// moves its contacts there as secondary contacts. Projects keep billing at
+/// the client's rate and rounding. A retainer ledger can't be merged into
+/// another client's, so clients with one must be archived instead.
+pub async fn reassign_client(
+    db: &Database,
+    user_id: ObjectId,
+    client_id: ObjectId,
+    target: &str,
+) -> Result<()> {
+    let target_id = reassign_target(target, client_id)?;
+    let client = find_owned::<Client>(db.clients(), user_id, client_id, "Client").await?;
+    let target = find_owned::<Client>(db.clients(), user_id, target_id, "Client").await?;
+    if target.archived_at.is_some() {
+        return Err(AppError::BadRequest("Can't reassign to an archived client".to_string()));
+    }
+
+    let ledger = db
+        .retainer_transactions()
+        .count_documents(doc! { "user_id": user_id, "client_id": client_id }, None)
+        .await?;
+    if ledger > 0 || client.retainer_balance != 0.0 {
+        return Err(AppError::Conflict(
+            "Clients with a retainer can't be reassigned; archive them instead".to_string(),
+        ));
+    }
+
+    let mut session = db.client.start_session(None).await?;
+    session.start_transaction(None).await?;
+    match move_client_records(db, &mut session, user_id, &client, target_id).await {
+        Ok(()) => session.commit_transaction().await?,
+        Err(err) => {
+            session.abort_transaction().await?;
+            return Err(err);
+        }
+    }
+    contacts::ensure_primary(db, &target).await?;
+
+    Ok(())
+}
+
+async fn move_client_records(
+    db: &Database,
+    session: &mut ClientSession,
+    user_id: ObjectId,
+    client: &Client,
+    target_id: ObjectId,
+) -> Result<()> {
+    let client_id = client.id.unwrap();
+    pin_client_billing(db, session, user_id, client).await?;
+
+    let filter = doc! { "user_id": user_id, "client_id": client_id };
+    let update = doc! { "$set": { "client_id": target_id, "updated_at": Utc::now() } };
+    db.invoices().update_many_with_session(filter.clone(), update.clone(), None, session).await?;
+    db.contracts().update_many_with_session(filter.clone(), update.clone(), None, session).await?;
+    db.projects().update_many_with_session(filter, update, None, session).await?;
+    contacts::move_to(db, session, user_id, target_id, &[client_id]).await?;
+
+    Ok(())
+}
+
+/// Gives the client's projects that have no rate or rounding rule of their
+/// own the client's, so they keep billing the same under another client.
+pub async fn pin_client_billing(
+    db: &Database,
+    session: &mut ClientSession,
+    user_id: ObjectId,
+    client: &Client,
+) -> Result<()> {
+    let projects = doc! { "user_id": user_id, "client_id": client.id };
+    if let Some(rate) = client.default_hourly_rate {
+        let mut filter = projects.clone();
+        filter.insert("hourly_rate", Bson::Null);
+        db.projects()
+            .update_many_with_session(filter, doc! { "$set": { "hourly_rate": rate } }, None, session)
+            .await?;
+    }
+    if let Some(rule) = &client.time_rounding {
+        let mut filter = projects;
+        filter.insert("time_rounding", Bson::Null);
+        db.projects()
+            .update_many_with_session(
+                filter,
+                doc! { "$set": { "time_rounding": bson::to_bson(rule)? } },
+                None,
+                session,
+            )
+            .await?;
+    }
+
+    Ok(())
+}
+
+/// Moves the project's tasks and time entries to `target` and points
+/// calendar import rules at it. Locked entries stay where they were billed
+/// or approved, so their presence blocks the move. Everything happens in
+/// one transaction, so an entry locked meanwhile can't be moved half-way.
+pub async fn reassign_project(
+    db: &Database,
+    user_id: ObjectId,
+    project_id: ObjectId,
+    target: &str,
+) -> Result<()> {
+    let target_id = reassign_target(target, project_id)?;
+    find_owned::<Project>(db.projects(), user_id, project_id, "Project").await?;
+    let target = find_owned::<Project>(db.projects(), user_id, target_id, "Project").await?;
+    if target.archived_at.is_some() {
+        return Err(AppError::BadRequest("Can't reassign to an archived project".to_string()));
+    }
+
+    let mut session = db.client.start_session(None).await?;
+    session.start_transaction(None).await?;
+    match move_project_records(db, &mut session, user_id, project_id, target_id).await {
+        Ok(()) => session.commit_transaction().await?,
+        Err(err) => {
+            session.abort_transaction().await?;
+            return Err(err);
+        }
+    }
+
+    Ok(())
+}
+
+async fn move_project_records(
+    db: &Database,
+    session: &mut ClientSession,
+    user_id: ObjectId,
+    project_id: ObjectId,
+    target_id: ObjectId,
+) -> Result<()> {
+    let filter = doc! { "user_id": user_id, "project_id": project_id };
+    let mut locked = filter.clone();
+    locked.insert("locked", true);
+    if db.time_entries().count_documents_with_session(locked, None, session).await? > 0 {
+        return Err(AppError::Conflict(
+            "Project has locked time entries; archive it instead".to_string(),
+        ));
+    }
+
+    let update = doc! { "$set": { "project_id": target_id, "updated_at": Utc::now() } };
+    db.tasks().update_many_with_session(filter.clone(), update.clone(), None, session).await?;
+    db.time_entries().update_many_with_session(filter, update, None, session).await?;
+    db.users()
+        .update_one_with_session(
+            doc! { "_id": user_id },
+            doc! { "$set": { "settings.calendar_rules.$[rule].project_id": target_id } },
+            UpdateOptions::builder()
+                .array_filters(vec![doc! { "rule.project_id": project_id }])
+                .build(),
+            session,
+        )
+        .await?;
+
+    Ok(())
+}
+
+fn reassign_target(target: &str, id: ObjectId) -> Result<ObjectId> {
+    let target_id = ObjectId::parse_str(target)
+        .map_err(|_| AppError::BadRequest("Invalid reassign_to ID".to_string()))?;
+    if target_id == id {
+        return Err(AppError::BadRequest("Can't reassign to the record being deleted".to_string()));
+    }
+
+    Ok(target_id)
+}
+
+async fn find_owned<T: DeserializeOwned + Send + Sync + Unpin>(
+    collection: mongodb::Collection<T>,
+    user_id: ObjectId,
+    id: ObjectId,
+    what: &str,
+) -> Result<T> {
+    collection
+        .find_one(doc! { "_id": id, "user_id": user_id }, None)
+        .await?
+        .ok_or(AppError::NotFound(format!("{} not found", what)))
+}
@@ -0,0 +1,864 @@
+//! iCalendar (RFC 5545): rendering time entries as a feed, and reading
+//! events out of uploaded `.ics` files.
+//!
+//! The reader handles what calendar exports contain in practice: folded
+//! lines, UTC / TZID / floating times, DURATION, cancelled events, EXDATE,
+//! moved occurrences (RECURRENCE-ID) and daily, weekly and monthly RRULEs.
+//! Custom VTIMEZONE definitions are not interpreted; a TZID that isn't an
+//! IANA name falls back to the user's timezone.
+
+use std::collections::{HashMap, HashSet};
+
+use chrono::{
+    DateTime, Datelike, Duration, LocalResult, Months, NaiveDate, NaiveDateTime, Offset, TimeZone, Utc,
+    Weekday,
+};
+use chrono_tz::Tz;
+use mongodb::bson::{doc, oid::ObjectId};
+
+use crate::{
+    database::Database,
+    error::{AppError, Result},
+    models::{
+        CalendarEventStatus, CalendarImportEvent, ImportCalendarRequest, ImportCalendarResponse,
+        TimeEntry,
+    },
+    services::{
+        calendar::Calendar,
+        settings::user_settings,
+        time_entries::{check_overlaps, overlap_message},
+    },
+};
+
+const MAX_LINE_OCTETS: usize = 75;
+const MAX_OCCURRENCES: usize = 5000;
+/// Occurrences stepped through from DTSTART on, wanted or not, so rules that
+/// start centuries before the window give up instead of spinning.
+const MAX_GENERATED: usize = 100_000;
+/// Longest import window and longest event, in days.
+const MAX_SPAN_DAYS: i64 = 366;
+const YEARS: std::ops::RangeInclusive<i32> = 1900..=2200;
+
+// ---------------------------------------------------------------------------
+// Feed
+
+/// A VCALENDAR with one VEVENT per entry. `projects` maps project IDs to
+/// names for the event summaries.
+pub fn render_feed(entries: &[TimeEntry], projects: &HashMap<ObjectId, String>) -> String {
+    let mut lines = vec![
+        "BEGIN:VCALENDAR".to_string(),
+        "VERSION:2.0".to_string(),
+        "PRODID:-//Orbix//Time Tracking//EN".to_string(),
+        "CALSCALE:GREGORIAN".to_string(),
+        "METHOD:PUBLISH".to_string(),
+        "X-WR-CALNAME:Orbix time".to_string(),
+    ];
+
+    for entry in entries {
+        let Some(end_time) = entry.end_time else { continue };
+        let project = entry.project_id.and_then(|id| projects.get(&id));
+        let summary = match (project, entry.description.is_empty()) {
+            (Some(project), true) => project.clone(),
+            (Some(project), false) => format!("{}: {}", project, entry.description),
+            (None, _) => entry.description.clone(),
+        };
+        let seconds = entry.duration.unwrap_or(0);
+
+        lines.push("BEGIN:VEVENT".to_string());
+        lines.push(format!("UID:{}@orbix", entry.id.map(|id| id.to_hex()).unwrap_or_default()));
+        lines.push(format!("DTSTAMP:{}", utc_stamp(entry.updated_at)));
+        lines.push(format!("DTSTART:{}", utc_stamp(entry.start_time)));
+        lines.push(format!("DTEND:{}", utc_stamp(end_time)));
+        lines.push(format!("SUMMARY:{}", escape(&summary)));
+        lines.push(format!(
+            "DESCRIPTION:{}",
+            escape(&format!(
+                "{}:{:02} tracked, {}",
+                seconds / 3600,
+                seconds % 3600 / 60,
+                if entry.is_billable { "billable" } else { "non-billable" }
+            ))
+        ));
+        if let Some(project) = project {
+            lines.push(format!("CATEGORIES:{}", escape(project)));
+        }
+        lines.push("TRANSP:TRANSPARENT".to_string());
+        lines.push("END:VEVENT".to_string());
+    }
+    lines.push("END:VCALENDAR".to_string());
+
+    let mut out = String::new();
+    for line in lines {
+        fold(&line, &mut out);
+    }
+    out
+}
+
+fn utc_stamp(time: DateTime<Utc>) -> String {
+    time.format("%Y%m%dT%H%M%SZ").to_string()
+}
+
+pub fn escape(text: &str) -> String {
+    text.replace('\\', "\\\\")
+        .replace(';', "\\;")
+        .replace(',', "\\,")
+        .replace("\r\n", "\\n")
+        .replace('\n', "\\n")
+}
+
+/// Writes `line` with CRLF endings, folding it at 75 octets without
+/// splitting a UTF-8 character.
+pub fn fold(line: &str, out: &mut String) {
+    let mut width = 0;
+    for c in line.chars() {
+        if width + c.len_utf8() > MAX_LINE_OCTETS {
+            out.push_str("\r\n ");
+            width = 1;
+        }
+        out.push(c);
+        width += c.len_utf8();
+    }
+    out.push_str("\r\n");
+}
+
+// ---------------------------------------------------------------------------
+// Parsing
+
+pub struct Property {
+    pub name: String,
+    pub params: Vec<(String, String)>,
+    pub value: String,
+}
+
+impl Property {
+    pub fn param(&self, name: &str) -> Option<&str> {
+        self.params
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+/// A start or end as written in the file: local wall-clock time in `tz`.
+#[derive(Clone, Copy)]
+struct EventTime {
+    local: NaiveDateTime,
+    tz: Tz,
+    all_day: bool,
+}
+
+impl EventTime {
+    fn utc(&self) -> DateTime<Utc> {
+        to_utc(self.tz, self.local)
+    }
+}
+
+#[derive(Default)]
+struct Event {
+    uid: Option<String>,
+    summary: String,
+    description: String,
+    start: Option<EventTime>,
+    end: Option<EventTime>,
+    duration: Option<Duration>,
+    rrule: Option<String>,
+    exdates: Vec<DateTime<Utc>>,
+    recurrence_id: Option<DateTime<Utc>>,
+    cancelled: bool,
+}
+
+fn to_utc(tz: Tz, local: NaiveDateTime) -> DateTime<Utc> {
+    match tz.from_local_datetime(&local) {
+        LocalResult::Single(time) | LocalResult::Ambiguous(time, _) => time.with_timezone(&Utc),
+        // Inside a DST gap: read the time as if the clocks hadn't changed yet
+        LocalResult::None => (local - tz.offset_from_utc_datetime(&local).fix()).and_utc(),
+    }
+}
+
+pub fn unfold(text: &str) -> Vec<String> {
+    let mut lines: Vec<String> = Vec::new();
+    for raw in text.split('\n') {
+        let line = raw.strip_suffix('\r').unwrap_or(raw);
+        match (line.strip_prefix(' ').or_else(|| line.strip_prefix('\t')), lines.last_mut()) {
+            (Some(rest), Some(last)) => last.push_str(rest),
+            _ if line.is_empty() => {}
+            _ => lines.push(line.to_string()),
+        }
+    }
+    lines
+}
+
+pub fn parse_property(line: &str) -> Option<Property> {
+    // The value starts at the first colon outside a quoted parameter value
+    let mut in_quotes = false;
+    let split = line.char_indices().find(|&(_, c)| {
+        if c == '"' {
+            in_quotes = !in_quotes;
+        }
+        c == ':' && !in_quotes
+    })?;
+    let (head, value) = (&line[..split.0], &line[split.0 + 1..]);
+
+    let mut parts = head.split(';');
+    let name = parts.next()?.trim().to_ascii_uppercase();
+    let params = parts
+        .filter_map(|param| {
+            let (key, value) = param.split_once('=')?;
+            Some((key.trim().to_ascii_uppercase(), value.trim_matches('"').to_string()))
+        })
+        .collect();
+
+    Some(Property { name, params, value: value.to_string() })
+}
+
+pub fn unescape(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            out.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n') | Some('N') => out.push('\n'),
+            Some(other) => out.push(other),
+            None => {}
+        }
+    }
+    out
+}
+
+fn parse_time(value: &str, tzid: Option<&str>, default_tz: Tz) -> Option<EventTime> {
+    let value = value.trim();
+    if value.len() == 8 {
+        let date = NaiveDate::parse_from_str(value, "%Y%m%d").ok()?;
+        return Some(EventTime { local: date.and_hms_opt(0, 0, 0)?, tz: default_tz, all_day: true });
+    }
+
+    let (value, utc) = match value.strip_suffix('Z') {
+        Some(value) => (value, true),
+        None => (value, false),
+    };
+    let local = NaiveDateTime::parse_from_str(value, "%Y%m%dT%H%M%S").ok()?;
+    let tz = if utc {
+        Tz::UTC
+    } else {
+        tzid.and_then(|name| name.parse().ok()).unwrap_or(default_tz)
+    };
+    Some(EventTime { local, tz, all_day: false })
+}
+
+/// `[+-]P[nW][nD][T[nH][nM][nS]]`
+fn parse_duration(value: &str) -> Option<Duration> {
+    let (sign, rest) = match value.trim().strip_prefix('-') {
+        Some(rest) => (-1, rest),
+        None => (1, value.trim().trim_start_matches('+')),
+    };
+    let rest = rest.strip_prefix('P')?;
+
+    let mut seconds = 0i64;
+    let mut number = String::new();
+    let mut in_time = false;
+    for c in rest.chars() {
+        match c {
+            'T' => in_time = true,
+            '0'..='9' => number.push(c),
+            unit => {
+                let n: i64 = number.parse().ok()?;
+                number.clear();
+                let unit_seconds = match (unit, in_time) {
+                    ('W', false) => 7 * 86_400,
+                    ('D', false) => 86_400,
+                    ('H', true) => 3_600,
+                    ('M', true) => 60,
+                    ('S', true) => 1,
+                    _ => return None,
+                };
+                seconds = seconds.checked_add(n.checked_mul(unit_seconds)?)?;
+            }
+        }
+    }
+    if !number.is_empty() {
+        return None;
+    }
+
+    Duration::try_seconds(sign * seconds)
+}
+
+fn parse_events(text: &str, default_tz: Tz) -> Result<Vec<Event>> {
+    let lines = unfold(text);
+    if !lines.first().is_some_and(|line| line.eq_ignore_ascii_case("BEGIN:VCALENDAR")) {
+        return Err(AppError::BadRequest("Not an iCalendar file".to_string()));
+    }
+
+    let mut events = Vec::new();
+    let mut current: Option<Event> = None;
+    // Nested components (VALARM) inside an event are skipped
+    let mut nested = 0;
+
+    for line in &lines {
+        let Some(property) = parse_property(line) else { continue };
+        let value = property.value.as_str();
+        match property.name.as_str() {
+            "BEGIN" if value.eq_ignore_ascii_case("VEVENT") => current = Some(Event::default()),
+            "BEGIN" if current.is_some() => nested += 1,
+            "END" if nested > 0 => nested -= 1,
+            "END" if value.eq_ignore_ascii_case("VEVENT") => {
+                if let Some(event) = current.take() {
+                    events.push(event);
+                }
+            }
+            _ if nested > 0 => {}
+            name => {
+                let Some(event) = current.as_mut() else { continue };
+                let tzid = property.param("TZID");
+                match name {
+                    "UID" => event.uid = Some(value.to_string()),
+                    "SUMMARY" => event.summary = unescape(value),
+                    "DESCRIPTION" => event.description = unescape(value),
+                    "DTSTART" => event.start = parse_time(value, tzid, default_tz),
+                    "DTEND" => event.end = parse_time(value, tzid, default_tz),
+                    "DURATION" => event.duration = parse_duration(value),
+                    "RRULE" => event.rrule = Some(value.to_string()),
+                    "EXDATE" => event.exdates.extend(
+                        value
+                            .split(',')
+                            .filter_map(|v| parse_time(v, tzid, default_tz))
+                            .map(|time| time.utc()),
+                    ),
+                    "RECURRENCE-ID" => {
+                        event.recurrence_id = parse_time(value, tzid, default_tz).map(|time| time.utc())
+                    }
+                    "STATUS" => event.cancelled = value.eq_ignore_ascii_case("CANCELLED"),
+                    _ => {}
+                }
+            }
+        }
+    }
+
+    Ok(events)
+}
+
+// ---------------------------------------------------------------------------
+// Recurrence
+
+struct Rule {
+    freq: Freq,
+    interval: u32,
+    count: Option<usize>,
+    until: Option<DateTime<Utc>>,
+    by_day: Vec<Weekday>,
+}
+
+enum Freq {
+    Daily,
+    Weekly,
+    Monthly,
+}
+
+fn parse_rule(value: &str, default_tz: Tz) -> std::result::Result<Rule, String> {
+    let mut rule = Rule { freq: Freq::Daily, interval: 1, count: None, until: None, by_day: Vec::new() };
+    let mut freq = None;
+
+    for part in value.split(';') {
+        let Some((key, value)) = part.split_once('=') else { continue };
+        match key.to_ascii_uppercase().as_str() {
+            "FREQ" => {
+                freq = Some(match value.to_ascii_uppercase().as_str() {
+                    "DAILY" => Freq::Daily,
+                    "WEEKLY" => Freq::Weekly,
+                    "MONTHLY" => Freq::Monthly,
+                    other => return Err(format!("Unsupported recurrence frequency {}", other)),
+                })
+            }
+            "INTERVAL" => rule.interval = value.parse().map_err(|_| "Invalid INTERVAL".to_string())?,
+            "COUNT" => rule.count = Some(value.parse().map_err(|_| "Invalid COUNT".to_string())?),
+            "UNTIL" => rule.until = parse_time(value, None, default_tz).map(|time| match time.all_day {
+                // A date-only UNTIL includes that whole day
+                true => time.utc() + Duration::days(1) - Duration::seconds(1),
+                false => time.utc(),
+            }),
+            "BYDAY" => {
+                for day in value.split(',') {
+                    rule.by_day.push(match day.to_ascii_uppercase().as_str() {
+                        "MO" => Weekday::Mon,
+                        "TU" => Weekday::Tue,
+                        "WE" => Weekday::Wed,
+                        "TH" => Weekday::Thu,
+                        "FR" => Weekday::Fri,
+                        "SA" => Weekday::Sat,
+                        "SU" => Weekday::Sun,
+                        other => return Err(format!("Unsupported BYDAY value {}", other)),
+                    });
+                }
+            }
+            "WKST" => {}
+            other => return Err(format!("Unsupported recurrence part {}", other)),
+        }
+    }
+
+    rule.freq = freq.ok_or("Recurrence rule without FREQ".to_string())?;
+    if rule.interval == 0 {
+        return Err("Invalid INTERVAL".to_string());
+    }
+    if matches!(rule.freq, Freq::Monthly) && !rule.by_day.is_empty() {
+        return Err("Unsupported monthly BYDAY rule".to_string());
+    }
+
+    Ok(rule)
+}
+
+/// Local start times of the occurrences of a recurring event that begin in
+/// `[from, to)`, in order. COUNT counts every occurrence from DTSTART on,
+/// but only the returned ones count toward [`MAX_OCCURRENCES`], so events
+/// that started long ago still reach the window, up to [`MAX_GENERATED`].
+fn occurrences(
+    start: NaiveDateTime,
+    rule: &Rule,
+    tz: Tz,
+    from: DateTime<Utc>,
+    to: DateTime<Utc>,
+) -> Vec<NaiveDateTime> {
+    let mut starts = Vec::new();
+    let mut generated = 0;
+    // False once no later occurrence can be wanted
+    let mut accept = |local: NaiveDateTime| {
+        let utc = to_utc(tz, local);
+        if utc >= to
+            || rule.until.is_some_and(|until| utc > until)
+            || rule.count.is_some_and(|count| generated >= count)
+            || starts.len() >= MAX_OCCURRENCES
+            || generated >= MAX_GENERATED
+        {
+            return false;
+        }
+        generated += 1;
+        if utc >= from {
+            starts.push(local);
+        }
+        true
+    };
+
+    match rule.freq {
+        Freq::Daily => {
+            let mut local = start;
+            while accept(local) {
+                match local.checked_add_signed(Duration::days(rule.interval as i64)) {
+                    Some(next) => local = next,
+                    None => break,
+                }
+            }
+        }
+        Freq::Weekly => {
+            let days = if rule.by_day.is_empty() { vec![start.weekday()] } else { rule.by_day.clone() };
+            let mut week = start.date() - Duration::days(start.weekday().num_days_from_monday() as i64);
+            'weeks: loop {
+                let mut in_week: Vec<NaiveDateTime> = days
+                    .iter()
+                    .filter_map(|day| week.checked_add_signed(Duration::days(day.num_days_from_monday() as i64)))
+                    .map(|date| date.and_time(start.time()))
+                    .filter(|local| *local >= start)
+                    .collect();
+                in_week.sort();
+                for local in in_week {
+                    if !accept(local) {
+                        break 'weeks;
+                    }
+                }
+                week = match week.checked_add_signed(Duration::weeks(rule.interval as i64)) {
+                    Some(next) => next,
+                    None => break,
+                };
+                if to_utc(tz, week.and_time(start.time())) >= to {
+                    break;
+                }
+            }
+        }
+        Freq::Monthly => {
+            // Months without that day (the 31st in April) are skipped
+            let mut months = 0;
+            while let Some(local) = start.checked_add_months(Months::new(months)) {
+                months = match months.checked_add(rule.interval) {
+                    Some(next) => next,
+                    None => break,
+                };
+                if local.day() != start.day() {
+                    if to_utc(tz, local) >= to {
+                        break;
+                    }
+                    continue;
+                }
+                if !accept(local) {
+                    break;
+                }
+            }
+        }
+    }
+
+    starts
+}
+
+// ---------------------------------------------------------------------------
+// Import
+
+fn skip(
+    response: &mut ImportCalendarResponse,
+    event: &Event,
+    start_time: DateTime<Utc>,
+    end_time: DateTime<Utc>,
+    message: &str,
+) {
+    response.skipped += 1;
+    response.events.push(CalendarImportEvent {
+        uid: event.uid.clone(),
+        summary: event.summary.clone(),
+        start_time,
+        end_time,
+        project_id: None,
+        status: CalendarEventStatus::Skipped,
+        message: Some(message.to_string()),
+    });
+}
+
+struct Occurrence<'a> {
+    event: &'a Event,
+    start_time: DateTime<Utc>,
+    end_time: DateTime<Utc>,
+}
+
+fn validate_window(from: DateTime<Utc>, to: DateTime<Utc>) -> Result<()> {
+    if to <= from {
+        return Err(AppError::BadRequest("`to` must be after `from`".to_string()));
+    }
+    if to - from > Duration::days(MAX_SPAN_DAYS) {
+        return Err(AppError::BadRequest(format!(
+            "Import at most {} days at a time",
+            MAX_SPAN_DAYS
+        )));
+    }
+    if !YEARS.contains(&from.year()) || !YEARS.contains(&to.year()) {
+        return Err(AppError::BadRequest(format!(
+            "Dates must be between {} and {}",
+            YEARS.start(),
+            YEARS.end()
+        )));
+    }
+
+    Ok(())
+}
+
+/// Turns the events of an uploaded calendar that fall in `[from, to)` into
+/// draft time entries, assigning projects with the user's keyword rules.
+/// Occurrences already imported (same UID and start) are skipped.
+pub async fn import_calendar(
+    db: &Database,
+    user_id: ObjectId,
+    request: ImportCalendarRequest,
+) -> Result<ImportCalendarResponse> {
+    validate_window(request.from, request.to)?;
+
+    let settings = user_settings(db, user_id).await?;
+    let default_tz = Calendar::new(&settings).timezone;
+    let events = parse_events(&request.ics, default_tz)?;
+
+    let mut response = ImportCalendarResponse {
+        dry_run: request.dry_run,
+        imported: 0,
+        duplicates: 0,
+        skipped: 0,
+        events: Vec::new(),
+    };
+    // Occurrences moved or edited individually replace the generated ones
+    let overridden: HashSet<(String, DateTime<Utc>)> = events
+        .iter()
+        .filter_map(|event| Some((event.uid.clone()?, event.recurrence_id?)))
+        .collect();
+
+    let mut pending = Vec::new();
+    for event in &events {
+        let Some(start) = event.start else { continue };
+        let length = match (event.end, event.duration) {
+            (Some(end), _) => end.utc() - start.utc(),
+            (None, Some(duration)) => duration,
+            (None, None) => Duration::zero(),
+        };
+        // Also keeps the window arithmetic below from overflowing
+        if length.num_days().abs() > MAX_SPAN_DAYS {
+            if (request.from..request.to).contains(&start.utc()) {
+                skip(&mut response, event, start.utc(), start.utc(), "Longer than a year");
+            }
+            continue;
+        }
+        let in_range = |start_time: DateTime<Utc>| {
+            start_time < request.to && start_time + length > request.from
+        };
+
+        let starts = match (&event.rrule, event.recurrence_id) {
+            (Some(rrule), None) => match parse_rule(rrule, start.tz) {
+                Ok(rule) => occurrences(start.local, &rule, start.tz, request.from - length, request.to)
+                    .into_iter()
+                    .map(|local| to_utc(start.tz, local))
+                    .filter(|time| !event.exdates.contains(time))
+                    .filter(|time| {
+                        event.uid.as_ref().is_none_or(|uid| !overridden.contains(&(uid.clone(), *time)))
+                    })
+                    .collect(),
+                Err(message) => {
+                    if in_range(start.utc()) {
+                        skip(&mut response, event, start.utc(), start.utc() + length, &message);
+                    }
+                    continue;
+                }
+            },
+            _ => vec![start.utc()],
+        };
+
+        for start_time in starts.into_iter().filter(|time| in_range(*time)) {
+            let end_time = start_time + length;
+            if event.cancelled {
+                skip(&mut response, event, start_time, end_time, "Cancelled");
+            } else if start.all_day {
+                skip(&mut response, event, start_time, end_time, "All-day event");
+            } else if length <= Duration::zero() {
+                skip(&mut response, event, start_time, end_time, "No duration");
+            } else if settings.closed_until.is_some_and(|closed| start_time < closed) {
+                skip(&mut response, event, start_time, end_time, "Falls in a closed period");
+            } else {
+                pending.push(Occurrence { event, start_time, end_time });
+            }
+        }
+    }
+    pending.sort_by_key(|occurrence| occurrence.start_time);
+
+    let rules: Vec<(String, ObjectId)> = settings
+        .calendar_rules
+        .iter()
+        .map(|rule| (rule.keyword.to_lowercase(), rule.project_id))
+        .collect();
+    let mut seen = HashSet::new();
+
+    for Occurrence { event, start_time, end_time } in pending {
+        let text = format!("{}\n{}", event.summary, event.description).to_lowercase();
+        let project_id = rules
+            .iter()
+            .find(|(keyword, _)| text.contains(keyword.as_str()))
+            .map(|(_, project_id)| *project_id);
+        let external_id = event
+            .uid
+            .as_ref()
+            .map(|uid| format!("ical:{}@{}", uid, start_time.to_rfc3339()));
+
+        let duplicate = !seen.insert((start_time, end_time, event.summary.clone()))
+            || match &external_id {
+                Some(external_id) => {
+                    db.time_entries()
+                        .count_documents(doc! { "user_id": user_id, "external_id": external_id }, None)
+                        .await?
+                        > 0
+                }
+                None => {
+                    db.time_entries()
+                        .count_documents(
+                            doc! {
+                                "user_id": user_id,
+                                "start_time": start_time,
+                                "end_time": end_time,
+                                "description": &event.summary,
+                            },
+                            None,
+                        )
+                        .await?
+                        > 0
+                }
+            };
+
+        // Drafts follow the same overlap policy as time logged by hand
+        let mut message = None;
+        if !duplicate {
+            match check_overlaps(db, user_id, None, start_time, Some(end_time)).await {
+                Ok(overlaps) if overlaps.is_empty() => {}
+                Ok(overlaps) => message = Some(overlap_message(&overlaps)),
+                Err(AppError::Conflict(message)) => {
+                    skip(&mut response, event, start_time, end_time, &message);
+                    continue;
+                }
+                Err(err) => return Err(err),
+            }
+        }
+
+        let status = if duplicate {
+            response.duplicates += 1;
+            CalendarEventStatus::Duplicate
+        } else {
+            if !request.dry_run {
+                let entry = TimeEntry {
+                    id: None,
+                    user_id,
+                    project_id,
+                    task_id: None,
+                    tags: Vec::new(),
+                    description: event.summary.clone(),
+                    start_time,
+                    end_time: Some(end_time),
+                    duration: Some((end_time - start_time).num_seconds()),
+                    is_billable: true,
+                    hourly_rate: None,
+                    invoice_id: None,
+                    locked: false,
+                    lock_reason: None,
+                    unlocks: Vec::new(),
+                    draft: true,
+                    external_id,
+                    last_activity_at: None,
+                    auto_stopped: false,
+                    approval_status: None,
+                    created_at: Utc::now(),
+                    updated_at: Utc::now(),
+                };
+                db.time_entries().insert_one(&entry, None).await?;
+            }
+            response.imported += 1;
+            CalendarEventStatus::Import
+        };
+
+        response.events.push(CalendarImportEvent {
+            uid: event.uid.clone(),
+            summary: event.summary.clone(),
+            start_time,
+            end_time,
+            project_id,
+            status,
+            message,
+        });
+    }
+
+    Ok(response)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn local(value: &str) -> NaiveDateTime {
+        NaiveDateTime::parse_from_str(value, "%Y-%m-%d %H:%M").unwrap()
+    }
+
+    fn utc(value: &str) -> DateTime<Utc> {
+        local(value).and_utc()
+    }
+
+    #[test]
+    fn durations() {
+        assert_eq!(parse_duration("PT1H30M"), Some(Duration::minutes(90)));
+        assert_eq!(parse_duration("P1W"), Some(Duration::days(7)));
+        assert_eq!(parse_duration("P1DT2H"), Some(Duration::hours(26)));
+        assert_eq!(parse_duration("-PT15M"), Some(Duration::minutes(-15)));
+        assert_eq!(parse_duration("+PT45S"), Some(Duration::seconds(45)));
+        // Minutes only exist in the time part, days only before it
+        assert_eq!(parse_duration("P1M"), None);
+        assert_eq!(parse_duration("PT1D"), None);
+        // Too long to represent
+        assert_eq!(parse_duration("P99999999999999999W"), None);
+        assert_eq!(parse_duration("P9999999999999D"), None);
+        assert_eq!(parse_duration("PT5"), None);
+        assert_eq!(parse_duration("1H"), None);
+    }
+
+    #[test]
+    fn rules() {
+        let rule = parse_rule("FREQ=WEEKLY;INTERVAL=2;BYDAY=MO,WE;COUNT=4", Tz::UTC).unwrap();
+        assert!(matches!(rule.freq, Freq::Weekly));
+        assert_eq!(rule.interval, 2);
+        assert_eq!(rule.count, Some(4));
+        assert_eq!(rule.by_day, vec![Weekday::Mon, Weekday::Wed]);
+
+        // A date-only UNTIL includes the whole day
+        let rule = parse_rule("FREQ=DAILY;UNTIL=20240105", Tz::UTC).unwrap();
+        assert_eq!(rule.until, Some(utc("2024-01-05 23:59") + Duration::seconds(59)));
+
+        assert!(parse_rule("INTERVAL=2", Tz::UTC).is_err());
+        assert!(parse_rule("FREQ=YEARLY", Tz::UTC).is_err());
+        assert!(parse_rule("FREQ=DAILY;INTERVAL=0", Tz::UTC).is_err());
+        assert!(parse_rule("FREQ=MONTHLY;BYDAY=MO", Tz::UTC).is_err());
+        assert!(parse_rule("FREQ=DAILY;BYMONTH=1", Tz::UTC).is_err());
+    }
+
+    #[test]
+    fn weekly_occurrences_in_window() {
+        // Monday 1 January 2024
+        let rule = parse_rule("FREQ=WEEKLY;BYDAY=MO,FR", Tz::UTC).unwrap();
+        let (from, to) = (utc("2024-01-03 00:00"), utc("2024-01-13 00:00"));
+        let starts = occurrences(local("2024-01-01 09:00"), &rule, Tz::UTC, from, to);
+        assert_eq!(
+            starts,
+            vec![local("2024-01-05 09:00"), local("2024-01-08 09:00"), local("2024-01-12 09:00")]
+        );
+    }
+
+    #[test]
+    fn count_includes_occurrences_before_the_window() {
+        let rule = parse_rule("FREQ=DAILY;COUNT=5", Tz::UTC).unwrap();
+        let (from, to) = (utc("2024-01-04 00:00"), utc("2024-02-01 00:00"));
+        let starts = occurrences(local("2024-01-01 09:00"), &rule, Tz::UTC, from, to);
+        assert_eq!(starts, vec![local("2024-01-04 09:00"), local("2024-01-05 09:00")]);
+    }
+
+    #[test]
+    fn old_events_still_reach_the_window() {
+        let rule = parse_rule("FREQ=DAILY", Tz::UTC).unwrap();
+        let (from, to) = (utc("2024-01-01 00:00"), utc("2024-01-03 00:00"));
+        let starts = occurrences(local("2000-01-01 09:00"), &rule, Tz::UTC, from, to);
+        assert_eq!(starts, vec![local("2024-01-01 09:00"), local("2024-01-02 09:00")]);
+    }
+
+    #[test]
+    fn monthly_skips_short_months() {
+        let rule = parse_rule("FREQ=MONTHLY;COUNT=3", Tz::UTC).unwrap();
+        let (from, to) = (utc("2024-01-01 00:00"), utc("2025-01-01 00:00"));
+        let starts = occurrences(local("2024-01-31 09:00"), &rule, Tz::UTC, from, to);
+        assert_eq!(
+            starts,
+            vec![local("2024-01-31 09:00"), local("2024-03-31 09:00"), local("2024-05-31 09:00")]
+        );
+    }
+
+    #[test]
+    fn occurrences_follow_local_time_across_dst() {
+        let berlin: Tz = "Europe/Berlin".parse().unwrap();
+        let rule = parse_rule("FREQ=DAILY;UNTIL=20240401T070000Z", berlin).unwrap();
+        let (from, to) = (utc("2024-03-01 00:00"), utc("2024-05-01 00:00"));
+        let starts = occurrences(local("2024-03-30 09:00"), &rule, berlin, from, to);
+        // 09:00 CEST on 1 April is 07:00 UTC, still within UNTIL
+        assert_eq!(
+            starts,
+            vec![local("2024-03-30 09:00"), local("2024-03-31 09:00"), local("2024-04-01 09:00")]
+        );
+    }
+
+    #[test]
+    fn import_windows_are_limited() {
+        assert!(validate_window(utc("2024-01-01 00:00"), utc("2024-12-31 00:00")).is_ok());
+        assert!(validate_window(utc("2024-01-01 00:00"), utc("2024-01-01 00:00")).is_err());
+        assert!(validate_window(utc("2024-01-01 00:00"), utc("2025-01-02 00:00")).is_err());
+        assert!(validate_window(utc("1800-01-01 00:00"), utc("1800-02-01 00:00")).is_err());
+        assert!(validate_window(utc("9000-01-01 00:00"), utc("9000-02-01 00:00")).is_err());
+    }
+
+    #[test]
+    fn ancient_rules_give_up() {
+        // Over 370,000 days from DTSTART to the window
+        let rule = parse_rule("FREQ=DAILY", Tz::UTC).unwrap();
+        let (from, to) = (utc("2024-01-01 00:00"), utc("2024-01-03 00:00"));
+        assert!(occurrences(local("1000-01-01 09:00"), &rule, Tz::UTC, from, to).is_empty());
+    }
+
+    #[test]
+    fn huge_intervals_dont_overflow() {
+        let (from, to) = (utc("2024-01-01 00:00"), utc("2024-12-31 00:00"));
+        for freq in ["DAILY", "WEEKLY", "MONTHLY"] {
+            let rule = parse_rule(&format!("FREQ={};INTERVAL=4294967295", freq), Tz::UTC).unwrap();
+            let starts = occurrences(local("2024-01-31 09:00"), &rule, Tz::UTC, from, to);
+            assert_eq!(starts, vec![local("2024-01-31 09:00")], "{}", freq);
+        }
+    }
+}
@@ -0,0 +1,105 @@
+//! Idle detection for running timers and the maximum timer duration.
+//!
+//! Clients send heartbeats while the user is active. When they stop for
+//! longer than the user's idle threshold, the gap is reported on the timer
+//! until the user decides what to do with it. Timers running past the
+//! maximum duration are stopped by a background sweep.
+
+use chrono::{DateTime, Duration, Utc};
+use mongodb::bson::doc;
+
+use crate::{
+    database::Database,
+    error::Result,
+    models::{IdlePeriod, TimeEntry, TimerEventKind, User, UserSettings},
+    services::timer_events::TimerEvents,
+};
+
+const SWEEP_INTERVAL_SECS: u64 = 60;
+
+/// The idle period of a running timer at `now`, if any. Timers whose client
+/// has never sent a heartbeat are never considered idle.
+pub fn idle_period(settings: &UserSettings, entry: &TimeEntry, now: DateTime<Utc>) -> Option<IdlePeriod> {
+    let threshold = Duration::minutes(settings.idle_threshold_minutes? as i64);
+    let since = entry.last_activity_at?;
+    if entry.end_time.is_some() || now - since <= threshold {
+        return None;
+    }
+
+    Some(IdlePeriod { since, until: now, seconds: (now - since).num_seconds() })
+}
+
+/// Stops every timer that has run longer than its owner's maximum, ending it
+/// exactly at the limit. Returns how many were stopped.
+pub async fn stop_overdue_timers(db: &Database, events: &TimerEvents) -> Result<u64> {
+    let now = Utc::now();
+    let mut stopped = 0;
+
+    let mut users = db
+        .users()
+        .find(doc! { "settings.max_timer_hours": { "$gt": 0 } }, None)
+        .await?;
+    while users.advance().await? {
+        let user: User = users.deserialize_current()?;
+        let Some(hours) = user.settings.max_timer_hours else { continue };
+        let limit = Duration::hours(hours as i64);
+
+        let mut cursor = db
+            .time_entries()
+            .find(
+                doc! {
+                    "user_id": user.id,
+                    "end_time": null,
+                    "start_time": { "$lt": now - limit },
+                },
+                None,
+            )
+            .await?;
+        while cursor.advance().await? {
+            let mut entry: TimeEntry = cursor.deserialize_current()?;
+            let end_time = entry.start_time + limit;
+
+            // Filtering on `end_time` again leaves timers stopped meanwhile alone
+            let result = db
+                .time_entries()
+                .update_one(
+                    doc! { "_id": entry.id, "end_time": null },
+                    doc! {
+                        "$set": {
+                            "end_time": end_time,
+                            "duration": limit.num_seconds(),
+                            "auto_stopped": true,
+                            "updated_at": now,
+                        }
+                    },
+                    None,
+                )
+                .await?;
+            if result.modified_count == 0 {
+                continue;
+            }
+
+            entry.end_time = Some(end_time);
+            entry.duration = Some(limit.num_seconds());
+            entry.auto_stopped = true;
+            entry.updated_at = now;
+            events.publish(TimerEventKind::Stopped, &entry);
+            stopped += 1;
+        }
+    }
+
+    Ok(stopped)
+}
+
+/// Runs [`stop_overdue_timers`] every minute for the life of the server.
+pub async fn run_auto_stop(db: Database, events: TimerEvents) {
+    let mut interval = tokio::time::interval(std::time::Duration::from_secs(SWEEP_INTERVAL_SECS));
+    loop {
+        interval.tick().await;
+        match stop_overdue_timers(&db, &events).await {
+            Ok(0) => {}
+            Ok(count) => println!("⏱️  Stopped {} timers at their maximum duration", count),
+            Err(err) => println!("⚠️  Stopping overdue timers failed: {:?}", err),
+        }
+    }
+}
@@ -0,0 +1,269 @@
+use chrono::{DateTime, Utc};
+use mongodb::{
+    bson::{doc, oid::ObjectId},
+    options::{FindOneAndUpdateOptions, ReturnDocument},
+};
+
+use crate::{
+    database::Database,
+    error::{AppError, Result},
+    models::{
+        ClosePeriodResponse, InvoiceItem, LockReason, TimeEntry, TimesheetStatus, UserSettings,
+    },
+    services::{approvals, billing::BillingContext, calendar::Calendar, settings::user_settings},
+};
+
+pub fn ensure_unlocked(entry: &TimeEntry) -> Result<()> {
+    if entry.locked {
+        return Err(AppError::Conflict("Time entry is locked".to_string()));
+    }
+
+    Ok(())
+}
+
+/// Refuses to log time starting before the user's closed period ends.
+pub fn ensure_open(settings: &UserSettings, start_time: DateTime<Utc>) -> Result<()> {
+    match settings.closed_until {
+        Some(closed_until) if start_time < closed_until => Err(AppError::Conflict(format!(
+            "Time up to {} is closed",
+            closed_until.to_rfc3339()
+        ))),
+        _ => Ok(()),
+    }
+}
+
+/// Locks every stopped entry of `user_id` that ended by `until` and moves
+/// the closed period forward. A period is never reopened this way; single
+/// entries are released with `unlock`.
+pub async fn close_period(
+    db: &Database,
+    user_id: ObjectId,
+    until: DateTime<Utc>,
+) -> Result<ClosePeriodResponse> {
+    let result = db
+        .time_entries()
+        .update_many(
+            doc! {
+                "user_id": user_id,
+                "end_time": { "$ne": null, "$lte": until },
+                "locked": { "$ne": true },
+            },
+            doc! {
+                "$set": {
+                    "locked": true,
+                    "lock_reason": bson::to_bson(&LockReason::PeriodClosed)?,
+                    "updated_at": Utc::now(),
+                }
+            },
+            None,
+        )
+        .await?;
+
+    let user = db
+        .users()
+        .find_one_and_update(
+            doc! { "_id": user_id },
+            doc! { "$max": { "settings.closed_until": until } },
+            FindOneAndUpdateOptions::builder()
+                .return_document(ReturnDocument::After)
+                .build(),
+        )
+        .await?
+        .ok_or(AppError::NotFound("User not found".to_string()))?;
+
+    Ok(ClosePeriodResponse {
+        closed_until: user.settings.closed_until.unwrap_or(until),
+        locked: result.modified_count,
+    })
+}
+
+/// Lifts the lock on one entry, keeping a record of who did it and why. An
+/// invoiced entry stays attached to its invoice.
+pub async fn unlock(
+    db: &Database,
+    user_id: ObjectId,
+    entry_id: ObjectId,
+    reason: &str,
+) -> Result<TimeEntry> {
+    let reason = reason.trim();
+    if reason.is_empty() {
+        return Err(AppError::BadRequest("A reason is required to unlock a time entry".to_string()));
+    }
+
+    let entry = db
+        .time_entries()
+        .find_one(doc! { "_id": entry_id, "user_id": user_id }, None)
+        .await?
+        .ok_or(AppError::NotFound("Time entry not found".to_string()))?;
+    if !entry.locked {
+        return Err(AppError::BadRequest("Time entry is not locked".to_string()));
+    }
+
+    // Editing a submitted or approved entry voids its sign-off
+    let mut unset = doc! { "lock_reason": "" };
+    if matches!(entry.lock_reason, Some(LockReason::Submitted | LockReason::Approved)) {
+        unset.insert("approval_status", "");
+    }
+
+    db.time_entries()
+        .find_one_and_update(
+            doc! { "_id": entry_id, "user_id": user_id, "locked": true },
+            doc! {
+                "$set": { "locked": false, "updated_at": Utc::now() },
+                "$unset": unset,
+                "$push": {
+                    "unlocks": {
+                        "user_id": user_id,
+                        "reason": reason,
+                        "previous_reason": bson::to_bson(&entry.lock_reason)?,
+                        "unlocked_at": Utc::now(),
+                    }
+                },
+            },
+            FindOneAndUpdateOptions::builder()
+                .return_document(ReturnDocument::After)
+                .build(),
+        )
+        .await?
+        .ok_or(AppError::Conflict("Time entry was unlocked concurrently".to_string()))
+}
+
+/// Loads the entries to bill on an invoice for `client_id` and turns each
+/// into an invoice line priced with the usual rate and rounding rules.
+pub async fn invoice_items(
+    db: &Database,
+    user_id: ObjectId,
+    client_id: ObjectId,
+    entry_ids: &[String],
+) -> Result<(Vec<ObjectId>, Vec<InvoiceItem>)> {
+    let mut ids = Vec::new();
+    for id in entry_ids {
+        let id = ObjectId::parse_str(id)
+            .map_err(|_| AppError::BadRequest("Invalid time entry ID".to_string()))?;
+        if !ids.contains(&id) {
+            ids.push(id);
+        }
+    }
+
+    let mut cursor = db
+        .time_entries()
+        .find(doc! { "_id": { "$in": &ids }, "user_id": user_id }, None)
+        .await?;
+    let mut entries: Vec<TimeEntry> = Vec::new();
+    while cursor.advance().await? {
+        entries.push(cursor.deserialize_current()?);
+    }
+    if entries.len() != ids.len() {
+        return Err(AppError::NotFound("Time entry not found".to_string()));
+    }
+    entries.sort_by_key(|entry| entry.start_time);
+
+    let billing = BillingContext::load(db, user_id).await?;
+    let settings = user_settings(db, user_id).await?;
+    let calendar = Calendar::new(&settings);
+    let mut items = Vec::new();
+    for entry in &entries {
+        if entry.draft {
+            return Err(AppError::BadRequest("Draft time entries can't be invoiced".to_string()));
+        }
+        if approvals::approval_required(&settings, entry.start_time)
+            && entry.approval_status != Some(TimesheetStatus::Approved)
+        {
+            return Err(AppError::BadRequest(
+                "Only time entries on approved timesheets can be invoiced".to_string(),
+            ));
+        }
+        if entry.invoice_id.is_some() {
+            return Err(AppError::Conflict("Time entry has already been invoiced".to_string()));
+        }
+        if billing.client(entry).and_then(|client| client.id) != Some(client_id) {
+            return Err(AppError::BadRequest(
+                "Time entry does not belong to this client".to_string(),
+            ));
+        }
+        let seconds = billing.billable_duration(entry).ok_or(AppError::BadRequest(
+            "Only stopped, billable time entries can be invoiced".to_string(),
+        ))?;
+        let (rate, _) = billing.hourly_rate(entry).ok_or(AppError::BadRequest(
+            "Time entry has no hourly rate".to_string(),
+        ))?;
+
+        let label = match billing.project(entry) {
+            Some(project) if entry.description.is_empty() => project.name.clone(),
+            Some(project) => format!("{}: {}", project.name, entry.description),
+            None => entry.description.clone(),
+        };
+        let quantity = seconds as f64 / 3600.0;
+        items.push(InvoiceItem {
+            description: format!("{} {}", calendar.format_time(entry.start_time), label),
+            quantity,
+            rate,
+            amount: quantity * rate,
+        });
+    }
+
+    Ok((ids, items))
+}
+
+/// Attaches entries to a freshly created invoice and locks them. Fails if
+/// any of them was invoiced in the meantime, leaving none attached.
+pub async fn lock_invoiced(
+    db: &Database,
+    user_id: ObjectId,
+    entry_ids: &[ObjectId],
+    invoice_id: ObjectId,
+) -> Result<()> {
+    let result = db
+        .time_entries()
+        .update_many(
+            doc! { "_id": { "$in": entry_ids }, "user_id": user_id, "invoice_id": null },
+            doc! {
+                "$set": {
+                    "invoice_id": invoice_id,
+                    "locked": true,
+                    "lock_reason": bson::to_bson(&LockReason::Invoiced)?,
+                    "updated_at": Utc::now(),
+                }
+            },
+            None,
+        )
+        .await?;
+
+    if result.modified_count != entry_ids.len() as u64 {
+        release_invoiced(db, invoice_id).await?;
+        return Err(AppError::Conflict("Time entry has already been invoiced".to_string()));
+    }
+
+    Ok(())
+}
+
+/// Undoes `lock_invoiced` for an invoice that is being discarded. Entries
+/// on an approved timesheet go back to being locked as approved.
+pub async fn release_invoiced(db: &Database, invoice_id: ObjectId) -> Result<()> {
+    let approved = bson::to_bson(&TimesheetStatus::Approved)?;
+    db.time_entries()
+        .update_many(
+            doc! { "invoice_id": invoice_id, "approval_status": &approved },
+            doc! {
+                "$set": {
+                    "lock_reason": bson::to_bson(&LockReason::Approved)?,
+                    "updated_at": Utc::now(),
+                },
+                "$unset": { "invoice_id": "" },
+            },
+            None,
+        )
+        .await?;
+    db.time_entries()
+        .update_many(
+            doc! { "invoice_id": invoice_id },
+            doc! {
+                "$set": { "locked": false, "updated_at": Utc::now() },
+                "$unset": { "invoice_id": "", "lock_reason": "" },
+            },
+            None,
+        )
+        .await?;
+
+    Ok(())
+}
@@ -1 +1,23 @@
-// Future services like PDF generation, email, etc.
+// Future services like email, etc.
+pub mod approvals;
+pub mod billing;
+pub mod calendar;
+pub mod client_csv;
+pub mod client_merge;
+pub mod client_metrics;
+pub mod contacts;
+pub mod dependents;
+pub mod ical;
+pub mod idle;
+pub mod locks;
+pub mod pdf;
+pub mod references;
+pub mod reports;
+pub mod retainer;
+pub mod settings;
+pub mod tags;
+pub mod tax;
+pub mod time_import;
+pub mod timer_events;
+pub mod time_entries;
+pub mod vcard;
@@ -0,0 +1,104 @@
+//! Minimal PDF writer for plain-text documents such as reports.
+//!
+//! Everything is set in the built-in Courier font, so no font files need to
+//! be embedded and columns line up without any layout engine.
+
+const PAGE_WIDTH: f64 = 595.0; // A4 in points
+const PAGE_HEIGHT: f64 = 842.0;
+const MARGIN: f64 = 40.0;
+const FONT_SIZE: f64 = 9.0;
+const LINE_HEIGHT: f64 = 12.0;
+
+/// Characters that fit on one line at `FONT_SIZE` (Courier glyphs are 0.6em wide).
+pub const LINE_WIDTH: usize = ((PAGE_WIDTH - 2.0 * MARGIN) / (FONT_SIZE * 0.6)) as usize;
+
+/// Renders `lines` onto as many pages as needed, with `title` at the top of
+/// each page. Lines longer than `LINE_WIDTH` are cut off.
+pub fn render_text(title: &str, lines: &[String]) -> Vec<u8> {
+    let lines_per_page = ((PAGE_HEIGHT - 2.0 * MARGIN) / LINE_HEIGHT) as usize - 2;
+    let pages: Vec<&[String]> = if lines.is_empty() {
+        vec![&[]]
+    } else {
+        lines.chunks(lines_per_page).collect()
+    };
+
+    // Object layout: 1 catalog, 2 page tree, 3 font, then a page and its
+    // content stream for every page.
+    let mut objects: Vec<String> = vec![
+        "<< /Type /Catalog /Pages 2 0 R >>".to_string(),
+        String::new(), // page tree, filled in once page numbers are known
+        "<< /Type /Font /Subtype /Type1 /BaseFont /Courier /Encoding /WinAnsiEncoding >>"
+            .to_string(),
+    ];
+
+    let mut page_refs = Vec::new();
+    for (index, page_lines) in pages.iter().enumerate() {
+        let page_id = objects.len() + 1;
+        let content_id = page_id + 1;
+        page_refs.push(format!("{} 0 R", page_id));
+
+        let mut content = format!(
+            "BT\n/F1 {} Tf\n{} TL\n{} {} Td\n",
+            FONT_SIZE,
+            LINE_HEIGHT,
+            MARGIN,
+            PAGE_HEIGHT - MARGIN
+        );
+        let heading = format!("{} (page {} of {})", title, index + 1, pages.len());
+        content.push_str(&format!("({}) Tj T* T*\n", escape(&heading)));
+        for line in page_lines.iter() {
+            content.push_str(&format!("({}) Tj T*\n", escape(line)));
+        }
+        content.push_str("ET\n");
+
+        objects.push(format!(
+            "<< /Type /Page /Parent 2 0 R /MediaBox [0 0 {} {}] \
+             /Resources << /Font << /F1 3 0 R >> >> /Contents {} 0 R >>",
+            PAGE_WIDTH, PAGE_HEIGHT, content_id
+        ));
+        objects.push(format!(
+            "<< /Length {} >>\nstream\n{}endstream",
+            content.len(),
+            content
+        ));
+    }
+    objects[1] = format!(
+        "<< /Type /Pages /Kids [{}] /Count {} >>",
+        page_refs.join(" "),
+        pages.len()
+    );
+
+    let mut pdf = b"%PDF-1.4\n".to_vec();
+    let mut offsets = Vec::with_capacity(objects.len());
+    for (index, object) in objects.iter().enumerate() {
+        offsets.push(pdf.len());
+        pdf.extend_from_slice(format!("{} 0 obj\n{}\nendobj\n", index + 1, object).as_bytes());
+    }
+
+    let xref_offset = pdf.len();
+    let mut trailer = format!("xref\n0 {}\n0000000000 65535 f \n", objects.len() + 1);
+    for offset in offsets {
+        trailer.push_str(&format!("{:010} 00000 n \n", offset));
+    }
+    trailer.push_str(&format!(
+        "trailer\n<< /Size {} /Root 1 0 R >>\nstartxref\n{}\n%%EOF\n",
+        objects.len() + 1,
+        xref_offset
+    ));
+    pdf.extend_from_slice(trailer.as_bytes());
+
+    pdf
+}
+
+/// Escapes a line for use in a PDF string literal. Only ASCII is kept since
+/// the standard fonts have no glyphs for anything else.
+fn escape(line: &str) -> String {
+    line.chars()
+        .take(LINE_WIDTH)
+        .map(|c| match c {
+            '(' | ')' | '\\' => format!("\\{}", c),
+            c if c.is_ascii() && !c.is_ascii_control() => c.to_string(),
+            _ => "?".to_string(),
+        })
+        .collect()
+}
@@ -0,0 +1,43 @@
+//! Lookups for the IDs a request refers to. Each checks the document exists
+//! and belongs to the caller, so one user can't attach records to another
+//! user's clients or projects.
+
+use mongodb::bson::{doc, oid::ObjectId};
+
+use crate::{
+    database::Database,
+    error::{AppError, Result},
+    models::{Client, Project},
+};
+
+pub fn parse_id(id: &str, what: &str) -> Result<ObjectId> {
+    ObjectId::parse_str(id).map_err(|_| AppError::BadRequest(format!("Invalid {} ID", what)))
+}
+
+/// The caller's client `id`. Archived clients can't take new records.
+pub async fn resolve_client(db: &Database, user_id: ObjectId, id: &str) -> Result<Client> {
+    let client = db
+        .clients()
+        .find_one(doc! { "_id": parse_id(id, "client")?, "user_id": user_id }, None)
+        .await?
+        .ok_or(AppError::NotFound("Client not found".to_string()))?;
+    if client.archived_at.is_some() {
+        return Err(AppError::BadRequest("Client is archived".to_string()));
+    }
+
+    Ok(client)
+}
+
+/// The caller's project `id`. Archived projects can't take new records.
+pub async fn resolve_project(db: &Database, user_id: ObjectId, id: &str) -> Result<Project> {
+    let project = db
+        .projects()
+        .find_one(doc! { "_id": parse_id(id, "project")?, "user_id": user_id }, None)
+        .await?
+        .ok_or(AppError::NotFound("Project not found".to_string()))?;
+    if project.archived_at.is_some() {
+        return Err(AppError::BadRequest("Project is archived".to_string()));
+    }
+
+    Ok(project)
+}
@@ -0,0 +1,563 @@
+use std::collections::BTreeMap;
+
+use chrono::{DateTime, Utc};
+use mongodb::{
+    bson::{doc, oid::ObjectId, Document},
+    options::FindOptions,
+};
+
+use crate::{
+    database::Database,
+    error::{AppError, Result},
+    models::{
+        CurrencyAmount, DetailedReport, DetailedReportGroup, DetailedReportLine, ReportTotals,
+        SummaryReport, SummaryReportRow, TimeEntry, TimeEntryGrouping, TimeEntryQuery,
+    },
+    services::{billing::BillingContext, calendar::Calendar, pdf, time_entries::build_filter},
+};
+
+/// The part of an entry that falls on one local day. Entries crossing
+/// midnight are split, with billable time and amounts shared out in
+/// proportion to the tracked time so rounding isn't applied per piece.
+struct Slice {
+    entry: TimeEntry,
+    start_time: DateTime<Utc>,
+    seconds: i64,
+    /// Seconds from the start of the entry to the start and end of this
+    /// piece, out of `span`
+    range: (i64, i64),
+    span: i64,
+}
+
+impl Slice {
+    /// This piece's part of `total`. Rounding the running total rather than
+    /// each piece keeps the pieces of an entry adding up to `total`.
+    fn part(&self, total: i64) -> i64 {
+        let upto = |at: i64| (total * at + self.span / 2) / self.span;
+        upto(self.range.1) - upto(self.range.0)
+    }
+
+    fn billable_seconds(&self, billing: &BillingContext) -> Option<i64> {
+        billing.billable_duration(&self.entry).map(|seconds| self.part(seconds))
+    }
+
+    fn amount(&self, billing: &BillingContext) -> Option<f64> {
+        let share = (self.range.1 - self.range.0) as f64 / self.span as f64;
+        billing.billable_amount(&self.entry).map(|amount| amount * share)
+    }
+}
+
+fn slices(calendar: &Calendar, entries: Vec<TimeEntry>) -> Vec<Slice> {
+    let mut slices = Vec::new();
+    for entry in entries {
+        let end_time = entry.end_time.unwrap_or(entry.start_time);
+        let total = entry.duration.unwrap_or(0);
+        let pieces = calendar.split_days(entry.start_time, end_time);
+        if pieces.len() == 1 {
+            slices.push(Slice {
+                start_time: entry.start_time,
+                seconds: total,
+                range: (0, 1),
+                span: 1,
+                entry,
+            });
+            continue;
+        }
+
+        let span = (end_time - entry.start_time).num_seconds();
+        for (start, end) in pieces {
+            let mut slice = Slice {
+                entry: entry.clone(),
+                start_time: start,
+                seconds: 0,
+                range: (
+                    (start - entry.start_time).num_seconds(),
+                    (end - entry.start_time).num_seconds(),
+                ),
+                span,
+            };
+            slice.seconds = slice.part(total);
+            slices.push(slice);
+        }
+    }
+
+    slices
+}
+
+#[derive(Default)]
+struct Accumulator {
+    seconds: i64,
+    billable_seconds: i64,
+    non_billable_seconds: i64,
+    amounts: BTreeMap<String, f64>,
+}
+
+impl Accumulator {
+    fn add(&mut self, billing: &BillingContext, slice: &Slice) {
+        self.seconds += slice.seconds;
+        match slice.billable_seconds(billing) {
+            Some(billable) => self.billable_seconds += billable,
+            None => self.non_billable_seconds += slice.seconds,
+        }
+        if let Some(amount) = slice.amount(billing) {
+            *self.amounts.entry(billing.currency(&slice.entry).to_string()).or_default() += amount;
+        }
+    }
+
+    fn totals(&self) -> ReportTotals {
+        ReportTotals {
+            hours: hours(self.seconds),
+            billable_hours: hours(self.billable_seconds),
+            billable_amounts: self
+                .amounts
+                .iter()
+                .map(|(currency, &amount)| CurrencyAmount { currency: currency.clone(), amount })
+                .collect(),
+            non_billable_share: if self.seconds > 0 {
+                self.non_billable_seconds as f64 / self.seconds as f64
+            } else {
+                0.0
+            },
+        }
+    }
+}
+
+fn hours(seconds: i64) -> f64 {
+    seconds as f64 / 3600.0
+}
+
+/// Stopped entries matching `query` split per local day, oldest first, with
+/// the billing context needed to price them.
+async fn load_slices(
+    db: &Database,
+    calendar: &Calendar,
+    user_id: ObjectId,
+    query: &TimeEntryQuery,
+) -> Result<(Vec<Slice>, BillingContext)> {
+    let mut filter: Document = build_filter(db, user_id, query).await?;
+    filter.insert("end_time", doc! { "$ne": null });
+
+    let options = FindOptions::builder().sort(doc! { "start_time": 1 }).build();
+    let mut cursor = db.time_entries().find(filter, options).await?;
+    let mut entries = Vec::new();
+    while cursor.advance().await? {
+        entries.push(cursor.deserialize_current()?);
+    }
+
+    Ok((slices(calendar, entries), BillingContext::load(db, user_id).await?))
+}
+
+/// The group a slice falls into as `(key, label)`. Periods are keyed by the
+/// local date they start on; entries without a project or client share a
+/// `None` key.
+fn group_of(
+    billing: &BillingContext,
+    calendar: &Calendar,
+    slice: &Slice,
+    grouping: TimeEntryGrouping,
+) -> (Option<String>, String) {
+    let entry = &slice.entry;
+    let date = calendar.local_date(slice.start_time);
+
+    match grouping {
+        TimeEntryGrouping::Day => {
+            (Some(date.format("%Y-%m-%d").to_string()), calendar.format_date(date))
+        }
+        TimeEntryGrouping::Week => {
+            let week_start = calendar.start_of_week(date);
+            (
+                Some(week_start.format("%Y-%m-%d").to_string()),
+                format!("Week of {}", calendar.format_date(week_start)),
+            )
+        }
+        TimeEntryGrouping::Month => {
+            let key = date.format("%Y-%m").to_string();
+            (Some(key.clone()), key)
+        }
+        TimeEntryGrouping::Project => match billing.project(entry) {
+            Some(project) => (project.id.map(|id| id.to_hex()), project.name.clone()),
+            None => (None, "No project".to_string()),
+        },
+        TimeEntryGrouping::Client => match billing.client(entry) {
+            Some(client) => (client.id.map(|id| id.to_hex()), client.name.clone()),
+            None => (None, "No client".to_string()),
+        },
+    }
+}
+
+/// Splits slices into groups, keeping periods in date order and projects
+/// and clients in alphabetical order.
+fn group_slices(
+    billing: &BillingContext,
+    calendar: &Calendar,
+    slices: Vec<Slice>,
+    grouping: TimeEntryGrouping,
+) -> Vec<(Option<String>, String, Vec<Slice>)> {
+    let mut groups: Vec<(Option<String>, String, Vec<Slice>)> = Vec::new();
+    for slice in slices {
+        let (key, label) = group_of(billing, calendar, &slice, grouping);
+        match groups.iter_mut().find(|(k, _, _)| *k == key) {
+            Some((_, _, group)) => group.push(slice),
+            None => groups.push((key, label, vec![slice])),
+        }
+    }
+
+    if matches!(grouping, TimeEntryGrouping::Project | TimeEntryGrouping::Client) {
+        groups.sort_by_key(|(_, label, _)| label.to_lowercase());
+    } else {
+        groups.sort_by(|(a, _, _), (b, _, _)| a.cmp(b));
+    }
+
+    groups
+}
+
+pub async fn summary_report(
+    db: &Database,
+    user_id: ObjectId,
+    query: &TimeEntryQuery,
+) -> Result<SummaryReport> {
+    let grouping = query.group_by.unwrap_or(TimeEntryGrouping::Project);
+    let calendar = Calendar::load(db, user_id).await?;
+    let (slices, billing) = load_slices(db, &calendar, user_id, query).await?;
+
+    let mut overall = Accumulator::default();
+    let rows = group_slices(&billing, &calendar, slices, grouping)
+        .into_iter()
+        .map(|(key, label, slices)| {
+            let mut group = Accumulator::default();
+            for slice in &slices {
+                group.add(&billing, slice);
+                overall.add(&billing, slice);
+            }
+            SummaryReportRow { key, label, totals: group.totals() }
+        })
+        .collect();
+
+    Ok(SummaryReport {
+        from: query.from,
+        to: query.to,
+        group_by: grouping,
+        timezone: calendar.timezone.name().to_string(),
+        period: period(&calendar, query.from, query.to),
+        rows,
+        totals: overall.totals(),
+    })
+}
+
+pub async fn detailed_report(
+    db: &Database,
+    user_id: ObjectId,
+    query: &TimeEntryQuery,
+) -> Result<DetailedReport> {
+    let grouping = query.group_by.unwrap_or(TimeEntryGrouping::Project);
+    let calendar = Calendar::load(db, user_id).await?;
+    let (slices, billing) = load_slices(db, &calendar, user_id, query).await?;
+
+    let mut overall = Accumulator::default();
+    let groups = group_slices(&billing, &calendar, slices, grouping)
+        .into_iter()
+        .map(|(key, label, slices)| {
+            let mut group = Accumulator::default();
+            let lines = slices
+                .iter()
+                .map(|slice| {
+                    group.add(&billing, slice);
+                    overall.add(&billing, slice);
+                    let entry = &slice.entry;
+                    DetailedReportLine {
+                        entry_id: entry.id.unwrap(),
+                        start_time: slice.start_time,
+                        date: calendar.format_time(slice.start_time),
+                        project: billing.project(entry).map(|p| p.name.clone()),
+                        client: billing.client(entry).map(|c| c.name.clone()),
+                        description: entry.description.clone(),
+                        hours: hours(slice.seconds),
+                        billable_hours: hours(slice.billable_seconds(&billing).unwrap_or(0)),
+                        hourly_rate: billing.hourly_rate(entry).map(|(rate, _)| rate),
+                        amount: slice.amount(&billing).unwrap_or(0.0),
+                        currency: billing.currency(entry).to_string(),
+                    }
+                })
+                .collect();
+            DetailedReportGroup { key, label, lines, totals: group.totals() }
+        })
+        .collect();
+
+    Ok(DetailedReport {
+        from: query.from,
+        to: query.to,
+        group_by: grouping,
+        timezone: calendar.timezone.name().to_string(),
+        period: period(&calendar, query.from, query.to),
+        groups,
+        totals: overall.totals(),
+    })
+}
+
+fn csv_error(err: impl std::fmt::Display) -> AppError {
+    AppError::InternalError(format!("Failed to write CSV: {}", err))
+}
+
+/// One `billable_amount_<currency>` column for every currency in the report.
+pub fn summary_csv(report: &SummaryReport) -> Result<Vec<u8>> {
+    let currencies: Vec<&str> =
+        report.totals.billable_amounts.iter().map(|amount| amount.currency.as_str()).collect();
+
+    let mut writer = csv::Writer::from_writer(Vec::new());
+    let mut header = vec!["group".to_string(), "hours".to_string(), "billable_hours".to_string()];
+    header.extend(currencies.iter().map(|currency| format!("billable_amount_{}", currency)));
+    header.push("non_billable_share".to_string());
+    writer.write_record(&header).map_err(csv_error)?;
+
+    let rows = report.rows.iter().map(|row| (row.label.as_str(), &row.totals));
+    for (label, totals) in rows.chain(std::iter::once(("Total", &report.totals))) {
+        let mut record = vec![
+            label.to_string(),
+            format!("{:.2}", totals.hours),
+            format!("{:.2}", totals.billable_hours),
+        ];
+        record.extend(currencies.iter().map(|&currency| format!("{:.2}", amount_in(totals, currency))));
+        record.push(format!("{:.4}", totals.non_billable_share));
+        writer.write_record(&record).map_err(csv_error)?;
+    }
+
+    writer.into_inner().map_err(csv_error)
+}
+
+pub fn detailed_csv(report: &DetailedReport) -> Result<Vec<u8>> {
+    let mut writer = csv::Writer::from_writer(Vec::new());
+    writer
+        .write_record([
+            "group", "date", "project", "client", "description", "hours", "billable_hours",
+            "hourly_rate", "amount", "currency",
+        ])
+        .map_err(csv_error)?;
+
+    for group in &report.groups {
+        for line in &group.lines {
+            writer
+                .write_record([
+                    group.label.clone(),
+                    line.date.clone(),
+                    line.project.clone().unwrap_or_default(),
+                    line.client.clone().unwrap_or_default(),
+                    line.description.clone(),
+                    format!("{:.2}", line.hours),
+                    format!("{:.2}", line.billable_hours),
+                    line.hourly_rate.map(|rate| format!("{:.2}", rate)).unwrap_or_default(),
+                    format!("{:.2}", line.amount),
+                    line.currency.clone(),
+                ])
+                .map_err(csv_error)?;
+        }
+    }
+
+    writer.into_inner().map_err(csv_error)
+}
+
+fn amount_in(totals: &ReportTotals, currency: &str) -> f64 {
+    totals
+        .billable_amounts
+        .iter()
+        .find(|amount| amount.currency == currency)
+        .map_or(0.0, |amount| amount.amount)
+}
+
+fn period(calendar: &Calendar, from: Option<DateTime<Utc>>, to: Option<DateTime<Utc>>) -> String {
+    let format = |date: Option<DateTime<Utc>>| {
+        date.map(|d| calendar.format_time(d)).unwrap_or_else(|| "...".to_string())
+    };
+    format!("{} - {}", format(from), format(to))
+}
+
+fn period_line(period: &str, timezone: &str) -> String {
+    format!("Period: {} ({})", period, timezone)
+}
+
+/// Totals of a row, with amounts in further currencies on lines of their own.
+fn totals_lines(label: &str, totals: &ReportTotals) -> Vec<String> {
+    let mut amounts = totals
+        .billable_amounts
+        .iter()
+        .map(|amount| format!("{:.2} {}", amount.amount, amount.currency));
+    let mut lines = vec![format!(
+        "{:<40} {:>9.2} {:>9.2} {:>16} {:>6.1}%",
+        truncate(label, 40),
+        totals.hours,
+        totals.billable_hours,
+        amounts.next().unwrap_or_default(),
+        totals.non_billable_share * 100.0
+    )];
+    lines.extend(amounts.map(|amount| format!("{:<60} {:>16}", "", amount)));
+
+    lines
+}
+
+fn truncate(text: &str, width: usize) -> String {
+    text.chars().take(width).collect()
+}
+
+pub fn summary_pdf(report: &SummaryReport) -> Vec<u8> {
+    let mut lines = vec![
+        period_line(&report.period, &report.timezone),
+        String::new(),
+        format!("{:<40} {:>9} {:>9} {:>16} {:>7}", "Group", "Hours", "Billable", "Amount", "Non-b."),
+        "-".repeat(pdf::LINE_WIDTH),
+    ];
+    for row in &report.rows {
+        lines.extend(totals_lines(&row.label, &row.totals));
+    }
+    lines.push("-".repeat(pdf::LINE_WIDTH));
+    lines.extend(totals_lines("Total", &report.totals));
+
+    pdf::render_text("Time report - summary", &lines)
+}
+
+pub fn detailed_pdf(report: &DetailedReport) -> Vec<u8> {
+    let mut lines = vec![period_line(&report.period, &report.timezone), String::new()];
+    for group in &report.groups {
+        lines.push(group.label.clone());
+        lines.push("-".repeat(pdf::LINE_WIDTH));
+        for line in &group.lines {
+            lines.push(format!(
+                "{:<10}  {:<46} {:>7.2} {:>7.2} {:>10.2} {}",
+                line.date,
+                truncate(&line.description, 46),
+                line.hours,
+                line.billable_hours,
+                line.amount,
+                line.currency
+            ));
+        }
+        lines.extend(totals_lines("Subtotal", &group.totals));
+        lines.push(String::new());
+    }
+    lines.extend(totals_lines("Total", &report.totals));
+
+    pdf::render_text("Time report - detailed", &lines)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::models::UserSettings;
+
+    use super::*;
+
+    fn at(time: &str) -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339(time).unwrap().with_timezone(&Utc)
+    }
+
+    fn entry(start_time: &str, end_time: &str, duration: i64) -> TimeEntry {
+        TimeEntry {
+            id: Some(ObjectId::new()),
+            user_id: ObjectId::new(),
+            project_id: None,
+            task_id: None,
+            tags: Vec::new(),
+            description: String::new(),
+            start_time: at(start_time),
+            end_time: Some(at(end_time)),
+            duration: Some(duration),
+            is_billable: true,
+            hourly_rate: None,
+            invoice_id: None,
+            locked: false,
+            lock_reason: None,
+            unlocks: Vec::new(),
+            draft: false,
+            external_id: None,
+            last_activity_at: None,
+            auto_stopped: false,
+            approval_status: None,
+            created_at: at(start_time),
+            updated_at: at(start_time),
+        }
+    }
+
+    fn calendar(timezone: &str) -> Calendar {
+        Calendar::new(&UserSettings { timezone: Some(timezone.to_string()), ..Default::default() })
+    }
+
+    #[test]
+    fn slices_add_up_to_the_entry() {
+        // 21:00 on the 1st to 01:20 on the 3rd in Berlin, with a break taken
+        // out of the duration so the shares don't come out even
+        let entries = vec![entry("2024-03-01T20:00:00Z", "2024-03-03T00:20:00Z", 100_001)];
+        let slices = slices(&calendar("Europe/Berlin"), entries);
+
+        assert_eq!(slices.len(), 3);
+        assert_eq!(slices[1].start_time, at("2024-03-01T23:00:00Z"));
+        assert_eq!(slices.iter().map(|slice| slice.seconds).sum::<i64>(), 100_001);
+        for total in [1, 7, 899, 3601, 86_399] {
+            assert_eq!(slices.iter().map(|slice| slice.part(total)).sum::<i64>(), total);
+        }
+    }
+
+    #[test]
+    fn single_day_entries_stay_whole() {
+        let entries = vec![entry("2024-03-01T09:00:00Z", "2024-03-01T10:00:00Z", 3000)];
+        let slices = slices(&calendar("Europe/Berlin"), entries);
+
+        assert_eq!(slices.len(), 1);
+        assert_eq!(slices[0].seconds, 3000);
+        assert_eq!(slices[0].part(1234), 1234);
+    }
+
+    /// Checks that every object is where the xref table says and that
+    /// `startxref` points at the table.
+    fn assert_valid_xref(pdf: &[u8]) {
+        let text = String::from_utf8(pdf.to_vec()).unwrap();
+        assert!(text.starts_with("%PDF-1.4\n"));
+        assert!(text.ends_with("%%EOF\n"));
+
+        let tail = &text[text.rfind("startxref\n").unwrap() + "startxref\n".len()..];
+        let xref_offset: usize = tail.lines().next().unwrap().parse().unwrap();
+        let mut lines = text[xref_offset..].lines();
+        assert_eq!(lines.next(), Some("xref"));
+        let size: usize = lines.next().unwrap().strip_prefix("0 ").unwrap().parse().unwrap();
+        assert_eq!(lines.next(), Some("0000000000 65535 f "));
+        for id in 1..size {
+            let line = lines.next().unwrap();
+            assert_eq!(line.len(), 19);
+            let offset: usize = line[..10].parse().unwrap();
+            assert!(text[offset..].starts_with(&format!("{} 0 obj\n", id)));
+        }
+        assert_eq!(lines.next(), Some("trailer"));
+        assert_eq!(lines.next(), Some(format!("<< /Size {} /Root 1 0 R >>", size).as_str()));
+    }
+
+    #[test]
+    fn pdf_cross_references_are_valid() {
+        let totals = ReportTotals {
+            hours: 12.5,
+            billable_hours: 10.0,
+            billable_amounts: vec![
+                CurrencyAmount { currency: "EUR".to_string(), amount: 800.0 },
+                CurrencyAmount { currency: "USD".to_string(), amount: 120.0 },
+            ],
+            non_billable_share: 0.2,
+        };
+        // Enough rows for several pages, with characters that need escaping
+        let rows = (0..150)
+            .map(|n| SummaryReportRow {
+                key: Some(n.to_string()),
+                label: format!("Project (n\\{})", n),
+                totals: totals.clone(),
+            })
+            .collect();
+        let report = SummaryReport {
+            from: None,
+            to: None,
+            group_by: TimeEntryGrouping::Project,
+            timezone: "UTC".to_string(),
+            period: "... - ...".to_string(),
+            rows,
+            totals,
+        };
+
+        let pdf = summary_pdf(&report);
+        assert_valid_xref(&pdf);
+        assert!(String::from_utf8(pdf).unwrap().contains("/Count 6 "));
+        assert_valid_xref(&pdf::render_text("Empty", &[]));
+    }
+}
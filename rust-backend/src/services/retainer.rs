@@ -0,0 +1,210 @@
+use chrono::{DateTime, Utc};
+use mongodb::{
+    bson::{doc, oid::ObjectId},
+    options::{FindOneAndUpdateOptions, ReturnDocument},
+};
+
+use crate::{
+    database::Database,
+    error::{AppError, Result},
+    models::{Client, RetainerTransaction, RetainerTransactionKind},
+};
+
+/// Credits `amount` to the client's retainer balance and records the deposit
+/// in the ledger.
+pub async fn deposit(
+    db: &Database,
+    user_id: ObjectId,
+    client_id: ObjectId,
+    amount: f64,
+    date: DateTime<Utc>,
+    reference: Option<String>,
+    notes: Option<String>,
+) -> Result<RetainerTransaction> {
+    if amount <= 0.0 {
+        return Err(AppError::BadRequest("Deposit amount must be positive".to_string()));
+    }
+
+    let client = adjust_balance(db, doc! { "_id": client_id, "user_id": user_id }, amount)
+        .await?
+        .ok_or(AppError::NotFound("Client not found".to_string()))?;
+
+    record(db, &client, RetainerTransactionKind::Deposit, amount, None, date, reference, notes).await
+}
+
+/// Debits `amount` from the client's retainer balance for the given invoice.
+/// Fails without touching the balance when it does not cover `amount`.
+pub async fn draw_down(
+    db: &Database,
+    user_id: ObjectId,
+    client_id: ObjectId,
+    amount: f64,
+    invoice_id: ObjectId,
+) -> Result<RetainerTransaction> {
+    let client = adjust_balance(
+        db,
+        doc! { "_id": client_id, "user_id": user_id, "retainer_balance": { "$gte": amount } },
+        -amount,
+    )
+    .await?
+    .ok_or(AppError::BadRequest("Insufficient retainer balance".to_string()))?;
+
+    record(
+        db,
+        &client,
+        RetainerTransactionKind::Drawdown,
+        amount,
+        Some(invoice_id),
+        Utc::now(),
+        None,
+        Some("Applied to invoice".to_string()),
+    )
+    .await
+}
+
+/// Draws `amount` for the invoice unless the ledger already has an
+/// outstanding draw-down for it, as invoices issued before draw-downs waited
+/// for the invoice to leave draft do.
+pub async fn draw_down_once(
+    db: &Database,
+    user_id: ObjectId,
+    client_id: ObjectId,
+    amount: f64,
+    invoice_id: ObjectId,
+) -> Result<()> {
+    let drawn = drawn_for(db, user_id, invoice_id).await?.map_or(0.0, |(_, amount)| amount);
+    if drawn <= 0.0 {
+        draw_down(db, user_id, client_id, amount, invoice_id).await?;
+    }
+
+    Ok(())
+}
+
+/// Credits back what was drawn for the invoice, e.g. when it returns to
+/// draft. Does nothing when no draw-down is outstanding.
+pub async fn reverse(db: &Database, user_id: ObjectId, invoice_id: ObjectId) -> Result<()> {
+    let drawn = drawn_for(db, user_id, invoice_id).await?;
+    let Some((client_id, amount)) = drawn.filter(|(_, amount)| *amount > 0.0) else {
+        return Ok(());
+    };
+
+    let client = adjust_balance(db, doc! { "_id": client_id, "user_id": user_id }, amount)
+        .await?
+        .ok_or(AppError::NotFound("Client not found".to_string()))?;
+
+    record(
+        db,
+        &client,
+        RetainerTransactionKind::Reversal,
+        amount,
+        Some(invoice_id),
+        Utc::now(),
+        None,
+        Some("Invoice returned to draft".to_string()),
+    )
+    .await?;
+
+    Ok(())
+}
+
+/// The client and net amount currently drawn for the invoice: draw-downs
+/// less reversals. `None` when the ledger has nothing for it.
+pub async fn drawn_for(
+    db: &Database,
+    user_id: ObjectId,
+    invoice_id: ObjectId,
+) -> Result<Option<(ObjectId, f64)>> {
+    let mut cursor = db
+        .retainer_transactions()
+        .find(doc! { "user_id": user_id, "invoice_id": invoice_id }, None)
+        .await?;
+
+    let mut drawn = None;
+    while cursor.advance().await? {
+        let transaction = cursor.deserialize_current()?;
+        let amount = match transaction.kind {
+            RetainerTransactionKind::Drawdown => transaction.amount,
+            RetainerTransactionKind::Reversal => -transaction.amount,
+            RetainerTransactionKind::Deposit => continue,
+        };
+        let (_, total) = drawn.get_or_insert((transaction.client_id, 0.0));
+        *total += amount;
+    }
+
+    Ok(drawn)
+}
+
+/// Checks up front that the client exists and its balance covers `amount`,
+/// so callers can validate before creating the invoice it will be drawn for.
+pub async fn ensure_available(
+    db: &Database,
+    user_id: ObjectId,
+    client_id: ObjectId,
+    amount: f64,
+) -> Result<()> {
+    let client = db
+        .clients()
+        .find_one(doc! { "_id": client_id, "user_id": user_id }, None)
+        .await?
+        .ok_or(AppError::NotFound("Client not found".to_string()))?;
+
+    if client.retainer_balance < amount {
+        return Err(AppError::BadRequest(format!(
+            "Insufficient retainer balance ({:.2} available)",
+            client.retainer_balance
+        )));
+    }
+
+    Ok(())
+}
+
+async fn adjust_balance(
+    db: &Database,
+    filter: bson::Document,
+    delta: f64,
+) -> Result<Option<Client>> {
+    let options = FindOneAndUpdateOptions::builder()
+        .return_document(ReturnDocument::After)
+        .build();
+
+    Ok(db
+        .clients()
+        .find_one_and_update(
+            filter,
+            doc! { "$inc": { "retainer_balance": delta }, "$set": { "updated_at": Utc::now() } },
+            options,
+        )
+        .await?)
+}
+
+#[allow(clippy::too_many_arguments)]
+async fn record(
+    db: &Database,
+    client: &Client,
+    kind: RetainerTransactionKind,
+    amount: f64,
+    invoice_id: Option<ObjectId>,
+    date: DateTime<Utc>,
+    reference: Option<String>,
+    notes: Option<String>,
+) -> Result<RetainerTransaction> {
+    let transaction = RetainerTransaction {
+        id: None,
+        user_id: client.user_id,
+        client_id: client.id.unwrap(),
+        kind,
+        amount,
+        balance_after: client.retainer_balance,
+        invoice_id,
+        reference,
+        notes,
+        date,
+        created_at: Utc::now(),
+    };
+
+    let result = db.retainer_transactions().insert_one(&transaction, None).await?;
+    let mut transaction_with_id = transaction;
+    transaction_with_id.id = Some(result.inserted_id.as_object_id().unwrap());
+
+    Ok(transaction_with_id)
+}
@@ -0,0 +1,17 @@
+use mongodb::bson::{doc, oid::ObjectId};
+
+use crate::{
+    database::Database,
+    error::{AppError, Result},
+    models::UserSettings,
+};
+
+pub async fn user_settings(db: &Database, user_id: ObjectId) -> Result<UserSettings> {
+    let user = db
+        .users()
+        .find_one(doc! { "_id": user_id }, None)
+        .await?
+        .ok_or(AppError::NotFound("User not found".to_string()))?;
+
+    Ok(user.settings)
+}
@@ -0,0 +1,60 @@
+//! Tag housekeeping that isn't tied to a single request.
+
+use mongodb::{
+    bson::{doc, oid::ObjectId},
+    options::FindOptions,
+};
+
+use crate::{database::Database, models::Tag};
+
+/// Merges tags that share a user and name into the oldest of them, as older
+/// versions could create the same tag twice. Entries are moved onto the
+/// kept tag before the others are deleted. Returns how many were merged.
+pub async fn merge_duplicate_tags(db: &Database) -> mongodb::error::Result<u64> {
+    let options = FindOptions::builder().sort(doc! { "user_id": 1, "name": 1, "created_at": 1 }).build();
+    let mut cursor = db.tags().find(None, options).await?;
+
+    let mut merged = 0;
+    let mut kept: Option<Tag> = None;
+    while cursor.advance().await? {
+        let tag: Tag = cursor.deserialize_current()?;
+        let survivor = match &kept {
+            Some(kept) if kept.user_id == tag.user_id && kept.name == tag.name => kept.id.unwrap(),
+            _ => {
+                kept = Some(tag);
+                continue;
+            }
+        };
+
+        merge_into(db, tag.user_id, tag.id.unwrap(), survivor).await?;
+        merged += 1;
+    }
+
+    Ok(merged)
+}
+
+async fn merge_into(
+    db: &Database,
+    user_id: ObjectId,
+    duplicate: ObjectId,
+    survivor: ObjectId,
+) -> mongodb::error::Result<()> {
+    // Adding and pulling the same array can't happen in one update
+    db.time_entries()
+        .update_many(
+            doc! { "user_id": user_id, "tags": duplicate },
+            doc! { "$addToSet": { "tags": survivor } },
+            None,
+        )
+        .await?;
+    db.time_entries()
+        .update_many(
+            doc! { "user_id": user_id, "tags": duplicate },
+            doc! { "$pull": { "tags": duplicate } },
+            None,
+        )
+        .await?;
+    db.tags().delete_one(doc! { "_id": duplicate }, None).await?;
+
+    Ok(())
+}
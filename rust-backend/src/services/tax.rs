@@ -0,0 +1,542 @@
+//! Tax identity: offline format and check-digit validation of VAT and other
+//! tax IDs, and when an invoice falls under the EU reverse charge.
+//!
+//! Validation only proves a number is well formed. Whether it is actually
+//! registered can only be confirmed online (VIES, HMRC).
+
+use crate::{
+    error::{AppError, Result},
+    models::{Client, PostalAddress, TaxIdType, UserSettings},
+};
+
+/// ISO codes of the EU member states
+pub const EU_COUNTRIES: [&str; 27] = [
+    "AT", "BE", "BG", "CY", "CZ", "DE", "DK", "EE", "ES", "FI", "FR", "GR", "HR", "HU", "IE",
+    "IT", "LT", "LU", "LV", "MT", "NL", "PL", "PT", "RO", "SE", "SI", "SK",
+];
+
+pub const REVERSE_CHARGE_NOTE: &str =
+    "Reverse charge: VAT to be accounted for by the recipient (Article 196, Council Directive 2006/112/EC)";
+
+/// A country together with a tax ID valid for it, as stored on a client.
+#[derive(Debug, Default)]
+pub struct TaxIdentity {
+    pub country: Option<String>,
+    pub tax_id_type: Option<TaxIdType>,
+    pub tax_id: Option<String>,
+}
+
+/// Uppercased ISO 3166-1 alpha-2 code. `UK` and `EL` are accepted for the
+/// United Kingdom and Greece.
+pub fn normalize_country(country: &str) -> Result<String> {
+    let code = country.trim().to_uppercase();
+    if code.len() != 2 || !code.chars().all(|c| c.is_ascii_uppercase()) {
+        return Err(AppError::BadRequest(format!(
+            "Invalid country '{}'; use a two-letter ISO code",
+            country
+        )));
+    }
+
+    Ok(match code.as_str() {
+        "UK" => "GB".to_string(),
+        "EL" => "GR".to_string(),
+        _ => code,
+    })
+}
+
+pub fn is_eu(country: &str) -> bool {
+    EU_COUNTRIES.contains(&country)
+}
+
+/// The tax ID scheme businesses in `country` use.
+pub fn type_for_country(country: &str) -> TaxIdType {
+    match country {
+        "GB" => TaxIdType::GbVat,
+        "CH" | "LI" => TaxIdType::ChVat,
+        "NO" => TaxIdType::NoVat,
+        "AU" => TaxIdType::AuAbn,
+        "US" => TaxIdType::UsEin,
+        country if is_eu(country) => TaxIdType::EuVat,
+        _ => TaxIdType::Other,
+    }
+}
+
+pub fn validate_address(address: PostalAddress) -> Result<PostalAddress> {
+    if address.line1.trim().is_empty() || address.city.trim().is_empty() {
+        return Err(AppError::BadRequest("Billing address needs a street and a city".to_string()));
+    }
+
+    Ok(PostalAddress { country: normalize_country(&address.country)?, ..address })
+}
+
+/// Validates a country and tax ID together; empty strings clear them. The
+/// scheme defaults to the country's, or to the one the ID's prefix names.
+pub fn tax_identity(
+    country: Option<&str>,
+    tax_id_type: Option<TaxIdType>,
+    tax_id: Option<&str>,
+) -> Result<TaxIdentity> {
+    let country = match country.map(str::trim) {
+        None | Some("") => None,
+        Some(country) => Some(normalize_country(country)?),
+    };
+    let tax_id = match tax_id.map(str::trim) {
+        None | Some("") => return Ok(TaxIdentity { country, ..Default::default() }),
+        Some(tax_id) => tax_id,
+    };
+
+    let kind = tax_id_type
+        .or_else(|| country.as_deref().map(type_for_country))
+        .or_else(|| type_from_prefix(&compact(tax_id)))
+        .ok_or(AppError::BadRequest(
+            "Set tax_id_type or country so the tax ID can be checked".to_string(),
+        ))?;
+    let tax_id = validate_tax_id(kind, tax_id, country.as_deref())?;
+    // An EU VAT ID names its member state
+    let country = country.or_else(|| (kind == TaxIdType::EuVat).then(|| vat_country(&tax_id)).flatten());
+
+    Ok(TaxIdentity { country, tax_id_type: Some(kind), tax_id: Some(tax_id) })
+}
+
+/// Checks `tax_id` against `kind` and returns it in its normalized form:
+/// EU and UK VAT IDs carry their country prefix, US EINs are `XX-XXXXXXX`.
+pub fn validate_tax_id(kind: TaxIdType, tax_id: &str, country: Option<&str>) -> Result<String> {
+    let invalid = |reason: &str| AppError::BadRequest(format!("Invalid tax ID '{}': {}", tax_id, reason));
+    let number = compact(tax_id);
+
+    match kind {
+        TaxIdType::EuVat => {
+            let own_prefix = country.filter(|country| is_eu(country)).map(vat_prefix);
+            let (prefix, body) = match own_prefix {
+                Some(prefix) => (prefix, number.strip_prefix(prefix).unwrap_or(&number)),
+                None => {
+                    let prefix = number.get(..2).filter(|prefix| vat_prefix_country(prefix).is_some());
+                    let prefix = prefix.ok_or_else(|| invalid("missing EU country prefix"))?;
+                    (prefix, &number[2..])
+                }
+            };
+            if !eu_vat_valid(prefix, body) {
+                return Err(invalid(&format!("not a valid {} VAT number", prefix)));
+            }
+            // Old nine-digit Belgian numbers gained a leading 0
+            let padding = if prefix == "BE" && body.len() == 9 { "0" } else { "" };
+            Ok(format!("{}{}{}", prefix, padding, body))
+        }
+        TaxIdType::GbVat => {
+            let body = number.strip_prefix("GB").unwrap_or(&number);
+            if !gb_vat_valid(body) {
+                return Err(invalid("not a valid UK VAT number"));
+            }
+            Ok(format!("GB{}", body))
+        }
+        TaxIdType::ChVat => {
+            let body = number.strip_prefix("CHE").unwrap_or(&number);
+            let body = ["MWST", "TVA", "IVA"]
+                .iter()
+                .find_map(|suffix| body.strip_suffix(suffix))
+                .unwrap_or(body);
+            if !(digits(body, 9) && mod11_check(body, &[5, 4, 3, 2, 7, 6, 5, 4])) {
+                return Err(invalid("not a valid Swiss UID"));
+            }
+            Ok(format!("CHE{}", body))
+        }
+        TaxIdType::NoVat => {
+            let body = number.strip_prefix("NO").unwrap_or(&number);
+            let body = body.strip_suffix("MVA").unwrap_or(body);
+            if !(digits(body, 9) && mod11_check(body, &[3, 2, 7, 6, 5, 4, 3, 2])) {
+                return Err(invalid("not a valid Norwegian organisation number"));
+            }
+            Ok(format!("NO{}MVA", body))
+        }
+        TaxIdType::AuAbn => {
+            if !(digits(&number, 11) && abn_valid(&number)) {
+                return Err(invalid("not a valid ABN"));
+            }
+            Ok(number)
+        }
+        TaxIdType::UsEin => {
+            const UNASSIGNED: [&str; 17] = [
+                "00", "07", "08", "09", "17", "18", "19", "28", "29", "49", "69", "70", "78", "79",
+                "89", "96", "97",
+            ];
+            if !digits(&number, 9) || UNASSIGNED.contains(&&number[..2]) {
+                return Err(invalid("not a valid EIN"));
+            }
+            Ok(format!("{}-{}", &number[..2], &number[2..]))
+        }
+        TaxIdType::Other => Ok(tax_id.trim().to_string()),
+    }
+}
+
+/// Whether an invoice from `seller` to `client` is an intra-EU business
+/// supply, on which the client rather than the seller accounts for VAT:
+/// both hold valid VAT IDs of different member states.
+pub fn reverse_charge_applies(seller: &UserSettings, client: &Client) -> bool {
+    let seller_country = seller
+        .business_country
+        .as_deref()
+        .filter(|country| is_eu(country));
+    let seller_vat = seller.business_tax_id.as_deref().and_then(vat_country);
+    let client_vat = match (client.tax_id_type, client.tax_id.as_deref()) {
+        (Some(TaxIdType::EuVat), Some(tax_id)) => vat_country(tax_id),
+        _ => None,
+    };
+
+    match (seller_country, seller_vat, client_vat) {
+        (Some(_), Some(seller_vat), Some(client_vat)) => seller_vat != client_vat,
+        _ => false,
+    }
+}
+
+/// The member state of a normalized EU VAT ID.
+pub fn vat_country(tax_id: &str) -> Option<String> {
+    let country = vat_prefix_country(tax_id.get(..2)?)?;
+    eu_vat_valid(&tax_id[..2], &tax_id[2..]).then(|| country.to_string())
+}
+
+fn vat_prefix(country: &str) -> &str {
+    if country == "GR" { "EL" } else { country }
+}
+
+fn vat_prefix_country(prefix: &str) -> Option<&str> {
+    match prefix {
+        "EL" => Some("GR"),
+        "GR" => None,
+        prefix => EU_COUNTRIES.iter().find(|country| **country == prefix).copied(),
+    }
+}
+
+fn type_from_prefix(number: &str) -> Option<TaxIdType> {
+    if number.starts_with("CHE") {
+        Some(TaxIdType::ChVat)
+    } else if number.starts_with("GB") {
+        Some(TaxIdType::GbVat)
+    } else {
+        number.get(..2).and_then(vat_prefix_country).map(|_| TaxIdType::EuVat)
+    }
+}
+
+/// Uppercase with spaces and punctuation removed; Irish numbers may contain
+/// `+` and `*`.
+fn compact(tax_id: &str) -> String {
+    tax_id
+        .chars()
+        .filter(|c| c.is_ascii_alphanumeric() || *c == '+' || *c == '*')
+        .map(|c| c.to_ascii_uppercase())
+        .collect()
+}
+
+fn eu_vat_valid(prefix: &str, body: &str) -> bool {
+    let len = body.len();
+    let all_digits = body.bytes().all(|b| b.is_ascii_digit());
+    let digit = |i: usize| (body.as_bytes()[i] - b'0') as u32;
+
+    match prefix {
+        "AT" => {
+            let Some(rest) = body.strip_prefix('U') else { return false };
+            if !digits(rest, 8) {
+                return false;
+            }
+            let d = |i: usize| (rest.as_bytes()[i] - b'0') as u32;
+            let sum: u32 = (0..7)
+                .map(|i| if i % 2 == 0 { d(i) } else { let x = d(i) * 2; x / 10 + x % 10 })
+                .sum();
+            (10 - (sum + 4) % 10) % 10 == d(7)
+        }
+        "BE" => {
+            let body = if len == 9 { format!("0{}", body) } else { body.to_string() };
+            digits(&body, 10)
+                && matches!(&body[..1], "0" | "1")
+                && 97 - body[..8].parse::<u64>().unwrap() % 97 == body[8..].parse::<u64>().unwrap()
+        }
+        "BG" => all_digits && (len == 9 || len == 10),
+        "CY" => {
+            len == 9
+                && digits(&body[..8], 8)
+                && body.as_bytes()[8].is_ascii_uppercase()
+        }
+        "CZ" => all_digits && (8..=10).contains(&len),
+        "DE" => digits(body, 9) && body.as_bytes()[0] != b'0' && mod11_10_check(body),
+        "DK" => {
+            digits(body, 8)
+                && weighted_sum(body, &[2, 7, 6, 5, 4, 3, 2, 1]).is_multiple_of(11)
+        }
+        "EE" => digits(body, 9) && weighted_mod10_check(body, &[3, 7, 1, 3, 7, 1, 3, 7]),
+        "EL" => {
+            digits(body, 9)
+                && (0..8).map(|i| digit(i) << (8 - i)).sum::<u32>() % 11 % 10 == digit(8)
+        }
+        "ES" => {
+            len == 9
+                && digits(&body[1..8], 7)
+                && body.bytes().all(|b| b.is_ascii_alphanumeric())
+                && !all_digits
+        }
+        "FI" => digits(body, 8) && mod11_check(body, &[7, 9, 10, 5, 8, 4, 2]),
+        "FR" => {
+            if len != 11 || !digits(&body[2..], 9) {
+                return false;
+            }
+            let key = &body[..2];
+            match key.parse::<u64>() {
+                Ok(key) => key == (12 + 3 * (body[2..].parse::<u64>().unwrap() % 97)) % 97,
+                // Newer keys use letters (but never I or O) and have no offline check
+                Err(_) => key.bytes().all(|b| b.is_ascii_alphanumeric() && b != b'I' && b != b'O'),
+            }
+        }
+        "HR" => digits(body, 11) && mod11_10_check(body),
+        "HU" => digits(body, 8) && weighted_mod10_check(body, &[9, 7, 3, 1, 9, 7, 3]),
+        "IE" => {
+            let b = body.as_bytes();
+            let letter = |c: u8| c.is_ascii_uppercase();
+            match len {
+                8 | 9 if digits(&body[..7], 7) => {
+                    letter(b[7])
+                        && b.get(8).is_none_or(|&c| letter(c))
+                        && ie_check_letter(&body[..7], b.get(8).copied()) == Some(b[7])
+                }
+                // Old style: a letter, + or * in second place, and the
+                // first digit moved behind the other five
+                8 => {
+                    b[0].is_ascii_digit()
+                        && (letter(b[1]) || b[1] == b'+' || b[1] == b'*')
+                        && digits(&body[2..7], 5)
+                        && ie_check_letter(&format!("0{}{}", &body[2..7], &body[..1]), None) == Some(b[7])
+                }
+                _ => false,
+            }
+        }
+        "IT" => digits(body, 11) && luhn(body),
+        "LT" => all_digits && (len == 9 || len == 12),
+        "LU" => digits(body, 8) && body[..6].parse::<u64>().unwrap() % 89 == body[6..].parse::<u64>().unwrap(),
+        "LV" => digits(body, 11),
+        "MT" => {
+            digits(body, 8)
+                && 37 - weighted_sum(body, &[3, 4, 6, 7, 8, 9]) % 37
+                    == body[6..].parse::<u32>().unwrap()
+        }
+        "NL" => {
+            if len != 12 || !digits(&body[..9], 9) || &body[9..10] != "B" || !digits(&body[10..], 2) {
+                return false;
+            }
+            // Sole traders' numbers use a mod-97 check over the whole ID instead
+            mod11_check_direct(&body[..9], &[9, 8, 7, 6, 5, 4, 3, 2]) || mod97(&format!("NL{}", body)) == 1
+        }
+        "PL" => digits(body, 10) && mod11_check_direct(body, &[6, 5, 7, 2, 3, 4, 5, 6, 7]),
+        "PT" => {
+            // 10 and 11 both become 0
+            digits(body, 9)
+                && match 11 - weighted_sum(body, &[9, 8, 7, 6, 5, 4, 3, 2]) % 11 {
+                    10 | 11 => 0,
+                    check => check,
+                } == digit(8)
+        }
+        "RO" => all_digits && (2..=10).contains(&len),
+        "SE" => digits(body, 12) && body.ends_with("01") && luhn(&body[..10]),
+        "SI" => {
+            // 10 becomes 0 and 11 is never issued
+            digits(body, 8)
+                && body.as_bytes()[0] != b'0'
+                && match 11 - weighted_sum(body, &[8, 7, 6, 5, 4, 3, 2]) % 11 {
+                    11 => false,
+                    check => check % 10 == digit(7),
+                }
+        }
+        "SK" => digits(body, 10) && body.parse::<u64>().unwrap() % 11 == 0,
+        _ => false,
+    }
+}
+
+/// Nine digits, or twelve with a branch suffix, weighted 8..2 plus the last
+/// two digits as a number; old numbers sum to 0 mod 97, newer ones to 42.
+/// Government departments and health authorities use `GD`/`HA` and three
+/// digits.
+fn gb_vat_valid(body: &str) -> bool {
+    if let Some(rest) = body.strip_prefix("GD").or_else(|| body.strip_prefix("HA")) {
+        return digits(rest, 3);
+    }
+    if !(digits(body, 9) || digits(body, 12)) {
+        return false;
+    }
+
+    let d = |i: usize| (body.as_bytes()[i] - b'0') as u32;
+    let sum: u32 = (0..7).map(|i| d(i) * (8 - i as u32)).sum::<u32>() + d(7) * 10 + d(8);
+    sum.is_multiple_of(97) || (sum + 55).is_multiple_of(97)
+}
+
+/// Weighted sum of seven digits, plus nine times the value of the optional
+/// ninth character, mod 23 as a letter with 0 as `W`.
+fn ie_check_letter(number: &str, ninth: Option<u8>) -> Option<u8> {
+    const LETTERS: &[u8] = b"WABCDEFGHIJKLMNOPQRSTUV";
+    let ninth = match ninth {
+        Some(c) => LETTERS.iter().position(|&l| l == c)? as u32,
+        None => 0,
+    };
+    Some(LETTERS[((weighted_sum(number, &[8, 7, 6, 5, 4, 3, 2]) + 9 * ninth) % 23) as usize])
+}
+
+fn abn_valid(number: &str) -> bool {
+    const WEIGHTS: [u32; 11] = [10, 1, 3, 5, 7, 9, 11, 13, 15, 17, 19];
+    let d = |i: usize| (number.as_bytes()[i] - b'0') as u32;
+    if d(0) == 0 {
+        return false;
+    }
+
+    let sum: u32 = (d(0) - 1) * WEIGHTS[0] + (1..11).map(|i| d(i) * WEIGHTS[i]).sum::<u32>();
+    sum.is_multiple_of(89)
+}
+
+fn digits(s: &str, len: usize) -> bool {
+    s.len() == len && s.bytes().all(|b| b.is_ascii_digit())
+}
+
+fn digit_values(s: &str) -> Vec<u32> {
+    s.bytes().map(|b| (b - b'0') as u32).collect()
+}
+
+fn weighted_sum(s: &str, weights: &[u32]) -> u32 {
+    weights.iter().zip(digit_values(s)).map(|(w, d)| w * d).sum()
+}
+
+/// Check digit 11 - (weighted sum mod 11), where 11 becomes 0 and 10 is
+/// never issued.
+fn mod11_check(s: &str, weights: &[u32]) -> bool {
+    let d = digit_values(s);
+    match 11 - weighted_sum(s, weights) % 11 {
+        10 => false,
+        11 => d[weights.len()] == 0,
+        check => d[weights.len()] == check,
+    }
+}
+
+/// Check digit (weighted sum mod 11), where 10 is never issued.
+fn mod11_check_direct(s: &str, weights: &[u32]) -> bool {
+    let d = digit_values(s);
+    let check = weighted_sum(s, weights) % 11;
+    check != 10 && d[weights.len()] == check
+}
+
+/// Check digit (10 - weighted sum mod 10) mod 10.
+fn weighted_mod10_check(s: &str, weights: &[u32]) -> bool {
+    (10 - weighted_sum(s, weights) % 10) % 10 == digit_values(s)[weights.len()]
+}
+
+/// ISO 7064 MOD 11,10 over all digits but the last
+fn mod11_10_check(s: &str) -> bool {
+    let d = digit_values(s);
+    let (body, check) = d.split_at(d.len() - 1);
+    let mut product = 10;
+    for digit in body {
+        let sum = match (digit + product) % 10 {
+            0 => 10,
+            sum => sum,
+        };
+        product = sum * 2 % 11;
+    }
+    (11 - product) % 10 == check[0]
+}
+
+fn luhn(s: &str) -> bool {
+    let sum: u32 = digit_values(s)
+        .iter()
+        .rev()
+        .enumerate()
+        .map(|(i, d)| if i % 2 == 1 { let x = d * 2; if x > 9 { x - 9 } else { x } } else { *d })
+        .sum();
+    sum.is_multiple_of(10)
+}
+
+/// Remainder mod 97 with letters counted as 10 (A) to 35 (Z)
+fn mod97(s: &str) -> u32 {
+    s.chars().fold(0, |acc, c| {
+        let value = c.to_digit(36).unwrap_or(0);
+        if value >= 10 {
+            (acc * 100 + value) % 97
+        } else {
+            (acc * 10 + value) % 97
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn eu(vat: &str) -> bool {
+        validate_tax_id(TaxIdType::EuVat, vat, None).is_ok()
+    }
+
+    #[test]
+    fn eu_vat_checksums() {
+        let cases = [
+            ("ATU13585627", "ATU13585626"),
+            ("BE0403019261", "BE0403019262"),
+            ("DE136695976", "DE136695977"),
+            ("EL094259216", "EL094259217"),
+            ("FR40303265045", "FR41303265045"),
+            ("IE6433435F", "IE6433435E"),
+            ("IE3628739UA", "IE3628739UB"),
+            ("IE8D79739I", "IE8D79739J"),
+            ("NL004495445B01", "NL004495446B01"),
+            ("PL8567346215", "PL8567346216"),
+            ("PT501964843", "PT501964842"),
+            ("SI50223054", "SI50223055"),
+            ("HR33392005961", "HR33392005962"),
+            ("IT00743110157", "IT00743110158"),
+        ];
+        for (valid, invalid) in cases {
+            assert!(eu(valid), "{} should be valid", valid);
+            assert!(!eu(invalid), "{} should be invalid", invalid);
+        }
+    }
+
+    #[test]
+    fn eu_vat_normalizes() {
+        assert_eq!(validate_tax_id(TaxIdType::EuVat, "be 403.019.261", None).unwrap(), "BE0403019261");
+        assert_eq!(validate_tax_id(TaxIdType::EuVat, "094259216", Some("GR")).unwrap(), "EL094259216");
+        assert!(!eu("GR094259216"));
+    }
+
+    #[test]
+    fn french_letter_keys_skip_the_checksum() {
+        assert!(eu("FRK7399859412"));
+        assert!(!eu("FRI7399859412"));
+    }
+
+    #[test]
+    fn dutch_sole_trader_numbers_use_mod97() {
+        assert!(eu("NL000099998B57"));
+        assert!(!eu("NL000099998B58"));
+    }
+
+    #[test]
+    fn gb_vat() {
+        assert_eq!(validate_tax_id(TaxIdType::GbVat, "GB 980 7806 84", None).unwrap(), "GB980780684");
+        assert!(validate_tax_id(TaxIdType::GbVat, "GB980780685", None).is_err());
+        assert!(validate_tax_id(TaxIdType::GbVat, "GBGD001", None).is_ok());
+    }
+
+    #[test]
+    fn abn() {
+        assert!(validate_tax_id(TaxIdType::AuAbn, "83 914 571 673", None).is_ok());
+        assert!(validate_tax_id(TaxIdType::AuAbn, "83 914 571 674", None).is_err());
+    }
+
+    #[test]
+    fn swiss_and_norwegian_numbers() {
+        assert_eq!(validate_tax_id(TaxIdType::ChVat, "CHE-107.787.577 IVA", None).unwrap(), "CHE107787577");
+        assert!(validate_tax_id(TaxIdType::ChVat, "CHE-107.787.578", None).is_err());
+        assert_eq!(validate_tax_id(TaxIdType::NoVat, "NO 995 525 828 MVA", None).unwrap(), "NO995525828MVA");
+        assert!(validate_tax_id(TaxIdType::NoVat, "995525829", None).is_err());
+    }
+
+    #[test]
+    fn check_digit_helpers() {
+        assert!(mod11_10_check("136695976"));
+        assert!(!mod11_10_check("136695977"));
+        assert!(luhn("79927398713"));
+        assert!(!luhn("79927398710"));
+        assert_eq!(mod97("WEST12345698765432GB82"), 1);
+        assert_ne!(mod97("WEST12345698765433GB82"), 1);
+    }
+}
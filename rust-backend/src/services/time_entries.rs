@@ -0,0 +1,584 @@
+use std::collections::HashMap;
+
+use chrono::{DateTime, Utc, Weekday};
+use mongodb::{
+    bson::{doc, oid::ObjectId, Bson, Document},
+    options::FindOptions,
+};
+
+use crate::{
+    database::Database,
+    error::{AppError, Result},
+    models::{
+        OverlappingPair, Task, TimeEntry, TimeEntryGroup, TimeEntryGrouping, TimeEntryOverlap,
+        TimeEntryQuery, TimesheetStatus,
+    },
+    services::{calendar::Calendar, references::parse_id, settings::user_settings},
+};
+
+/// Length of a time entry in seconds. Every place that stores `duration`
+/// goes through here so an end before the start is never persisted.
+pub fn duration_between(start_time: DateTime<Utc>, end_time: DateTime<Utc>) -> Result<i64> {
+    if end_time < start_time {
+        return Err(AppError::BadRequest("End time cannot be before start time".to_string()));
+    }
+
+    Ok((end_time - start_time).num_seconds())
+}
+
+/// Looks up a task referenced by a time entry. When the entry already has a
+/// project the task must belong to it.
+pub async fn resolve_task(
+    db: &Database,
+    user_id: ObjectId,
+    task_id: &str,
+    project_id: Option<ObjectId>,
+) -> Result<Task> {
+    let task = db
+        .tasks()
+        .find_one(doc! { "_id": parse_id(task_id, "task")?, "user_id": user_id }, None)
+        .await?
+        .ok_or(AppError::NotFound("Task not found".to_string()))?;
+
+    if project_id.is_some_and(|project_id| project_id != task.project_id) {
+        return Err(AppError::BadRequest("Task belongs to a different project".to_string()));
+    }
+
+    Ok(task)
+}
+
+pub async fn resolve_tags(db: &Database, user_id: ObjectId, tags: &[String]) -> Result<Vec<ObjectId>> {
+    let mut ids = Vec::new();
+    for tag in tags {
+        let id = parse_id(tag, "tag")?;
+        if !ids.contains(&id) {
+            ids.push(id);
+        }
+    }
+
+    let found = db
+        .tags()
+        .count_documents(doc! { "_id": { "$in": &ids }, "user_id": user_id }, None)
+        .await?;
+    if found != ids.len() as u64 {
+        return Err(AppError::NotFound("Tag not found".to_string()));
+    }
+
+    Ok(ids)
+}
+
+/// Translates the filter part of `query` into a `time_entries` filter for
+/// `user_id`. Filtering by client goes through the client's projects.
+pub async fn build_filter(db: &Database, user_id: ObjectId, query: &TimeEntryQuery) -> Result<Document> {
+    let mut filter = doc! { "user_id": user_id };
+
+    let mut start_time = Document::new();
+    if let Some(from) = query.from {
+        start_time.insert("$gte", from);
+    }
+    if let Some(to) = query.to {
+        start_time.insert("$lt", to);
+    }
+    if !start_time.is_empty() {
+        filter.insert("start_time", start_time);
+    }
+
+    let mut project_ids = None;
+    if let Some(project_id) = &query.project_id {
+        project_ids = Some(vec![parse_id(project_id, "project")?]);
+    }
+    if let Some(client_id) = &query.client_id {
+        let client_id = parse_id(client_id, "client")?;
+        let mut cursor = db
+            .projects()
+            .find(doc! { "user_id": user_id, "client_id": client_id }, None)
+            .await?;
+        let mut client_projects = Vec::new();
+        while cursor.advance().await? {
+            client_projects.push(cursor.deserialize_current()?.id.unwrap());
+        }
+        project_ids = Some(match project_ids {
+            Some(ids) => ids.into_iter().filter(|id| client_projects.contains(id)).collect(),
+            None => client_projects,
+        });
+    }
+    if let Some(project_ids) = project_ids {
+        filter.insert("project_id", doc! { "$in": project_ids });
+    }
+
+    if let Some(task_id) = &query.task_id {
+        filter.insert("task_id", parse_id(task_id, "task")?);
+    }
+    if let Some(tag) = &query.tag {
+        filter.insert("tags", parse_id(tag, "tag")?);
+    }
+    if let Some(billable) = query.billable {
+        filter.insert("is_billable", billable);
+    }
+    if let Some(billed) = query.billed {
+        filter.insert("invoice_id", if billed { doc! { "$ne": null } } else { doc! { "$eq": null } });
+    }
+    filter.insert("draft", if query.draft == Some(true) { doc! { "$eq": true } } else { doc! { "$ne": true } });
+    if let Some(locked) = query.locked {
+        filter.insert("locked", if locked { doc! { "$eq": true } } else { doc! { "$ne": true } });
+    }
+    if let Some(approved) = query.approved {
+        let status = bson::to_bson(&TimesheetStatus::Approved)?;
+        filter.insert("approval_status", if approved { doc! { "$eq": status } } else { doc! { "$ne": status } });
+    }
+
+    Ok(filter)
+}
+
+/// Totals of the entries matching `filter`, grouped per `grouping`, using a
+/// server-side aggregation pipeline. Periods are counted in the user's
+/// timezone; weeks are keyed by the date they start on.
+pub async fn summarize(
+    db: &Database,
+    user_id: ObjectId,
+    filter: Document,
+    grouping: TimeEntryGrouping,
+) -> Result<Vec<TimeEntryGroup>> {
+    let calendar = Calendar::load(db, user_id).await?;
+    let timezone = calendar.timezone.name();
+    let mut pipeline = vec![doc! { "$match": filter }];
+
+    let key = match grouping {
+        TimeEntryGrouping::Day => date_bucket("%Y-%m-%d", "$start_time", timezone),
+        TimeEntryGrouping::Week => week_bucket(timezone, calendar.week_start),
+        TimeEntryGrouping::Month => date_bucket("%Y-%m", "$start_time", timezone),
+        TimeEntryGrouping::Project => Bson::String("$project_id".to_string()),
+        TimeEntryGrouping::Client => {
+            pipeline.push(doc! {
+                "$lookup": {
+                    "from": "projects",
+                    "localField": "project_id",
+                    "foreignField": "_id",
+                    "as": "project",
+                }
+            });
+            pipeline.push(doc! {
+                "$unwind": { "path": "$project", "preserveNullAndEmptyArrays": true }
+            });
+            Bson::String("$project.client_id".to_string())
+        }
+    };
+
+    pipeline.push(doc! {
+        "$group": {
+            "_id": key,
+            "total_seconds": { "$sum": { "$ifNull": ["$duration", 0] } },
+            "billable_seconds": {
+                "$sum": { "$cond": ["$is_billable", { "$ifNull": ["$duration", 0] }, 0] }
+            },
+            "entry_count": { "$sum": 1 },
+        }
+    });
+    pipeline.push(doc! { "$sort": { "_id": 1 } });
+
+    let labels = group_labels(db, user_id, grouping).await?;
+
+    let mut cursor = db.time_entries().aggregate(pipeline, None).await?;
+    let mut groups = Vec::new();
+    while cursor.advance().await? {
+        let row = cursor.deserialize_current()?;
+        let key = match row.get("_id") {
+            Some(Bson::ObjectId(id)) => Some(id.to_hex()),
+            Some(Bson::String(bucket)) => Some(bucket.clone()),
+            _ => None,
+        };
+
+        groups.push(TimeEntryGroup {
+            label: key.as_ref().and_then(|key| labels.get(key).cloned()),
+            key,
+            total_seconds: as_i64(row.get("total_seconds")),
+            billable_seconds: as_i64(row.get("billable_seconds")),
+            entry_count: as_i64(row.get("entry_count")),
+        });
+    }
+
+    Ok(groups)
+}
+
+fn date_bucket(format: &str, date: impl Into<Bson>, timezone: &str) -> Bson {
+    Bson::Document(doc! {
+        "$dateToString": { "format": format, "date": date.into(), "timezone": timezone }
+    })
+}
+
+/// The local date the entry's week starts on. Built from date parts rather
+/// than `$dateTrunc`, which needs MongoDB 5.0. The local date is rebuilt as
+/// UTC midnight first, so stepping back whole days can't trip over DST.
+fn week_bucket(timezone: &str, week_start: Weekday) -> Bson {
+    let local = |operator: &str| doc! { operator: { "date": "$start_time", "timezone": timezone } };
+    let day = doc! {
+        "$dateFromParts": {
+            "year": local("$year"),
+            "month": local("$month"),
+            "day": local("$dayOfMonth"),
+        }
+    };
+    // $dayOfWeek counts from 1 for Sunday
+    let days_into_week = doc! {
+        "$mod": [
+            { "$add": [{ "$subtract": [local("$dayOfWeek"), week_start.number_from_sunday() as i32] }, 7] },
+            7,
+        ]
+    };
+
+    date_bucket(
+        "%Y-%m-%d",
+        doc! { "$subtract": [day, { "$multiply": [days_into_week, 24 * 60 * 60 * 1000] }] },
+        "UTC",
+    )
+}
+
+fn as_i64(value: Option<&Bson>) -> i64 {
+    match value {
+        Some(Bson::Int32(n)) => i64::from(*n),
+        Some(Bson::Int64(n)) => *n,
+        Some(Bson::Double(n)) => *n as i64,
+        _ => 0,
+    }
+}
+
+/// Project or client names keyed by hex ID, for labelling summary groups.
+async fn group_labels(
+    db: &Database,
+    user_id: ObjectId,
+    grouping: TimeEntryGrouping,
+) -> Result<HashMap<String, String>> {
+    let mut labels = HashMap::new();
+    match grouping {
+        TimeEntryGrouping::Project => {
+            let mut cursor = db.projects().find(doc! { "user_id": user_id }, None).await?;
+            while cursor.advance().await? {
+                let project = cursor.deserialize_current()?;
+                labels.insert(project.id.unwrap().to_hex(), project.name);
+            }
+        }
+        TimeEntryGrouping::Client => {
+            let mut cursor = db.clients().find(doc! { "user_id": user_id }, None).await?;
+            while cursor.advance().await? {
+                let client = cursor.deserialize_current()?;
+                labels.insert(client.id.unwrap().to_hex(), client.name);
+            }
+        }
+        _ => {}
+    }
+
+    Ok(labels)
+}
+
+/// Running entries count as extending up to now.
+fn effective_end(entry_end: Option<DateTime<Utc>>, now: DateTime<Utc>) -> DateTime<Utc> {
+    entry_end.unwrap_or(now)
+}
+
+/// Entries of `user_id` overlapping `[start_time, end_time)`, other than
+/// `exclude_id`. An open range (`end_time` of `None`) runs until now.
+pub async fn find_overlaps(
+    db: &Database,
+    user_id: ObjectId,
+    exclude_id: Option<ObjectId>,
+    start_time: DateTime<Utc>,
+    end_time: Option<DateTime<Utc>>,
+) -> Result<Vec<TimeEntryOverlap>> {
+    let now = Utc::now();
+    let end_time = effective_end(end_time, now);
+
+    let mut filter = doc! {
+        "user_id": user_id,
+        "draft": { "$ne": true },
+        "start_time": { "$lt": end_time },
+        "$or": [
+            { "end_time": { "$gt": start_time } },
+            { "end_time": null },
+        ],
+    };
+    if let Some(exclude_id) = exclude_id {
+        filter.insert("_id", doc! { "$ne": exclude_id });
+    }
+
+    let options = FindOptions::builder().sort(doc! { "start_time": 1 }).build();
+    let mut cursor = db.time_entries().find(filter, options).await?;
+
+    let mut overlaps = Vec::new();
+    while cursor.advance().await? {
+        let other: TimeEntry = cursor.deserialize_current()?;
+        let overlap_start = other.start_time.max(start_time);
+        let overlap_end = effective_end(other.end_time, now).min(end_time);
+        if overlap_end <= overlap_start {
+            continue;
+        }
+
+        overlaps.push(TimeEntryOverlap {
+            entry_id: other.id.unwrap(),
+            project_id: other.project_id,
+            description: other.description,
+            start_time: other.start_time,
+            end_time: other.end_time,
+            overlap_seconds: (overlap_end - overlap_start).num_seconds(),
+        });
+    }
+
+    Ok(overlaps)
+}
+
+/// Looks up overlaps for a range about to be written and applies the user's
+/// overlap policy: either the overlaps come back as warnings, or the write
+/// is refused with a conflict.
+pub async fn check_overlaps(
+    db: &Database,
+    user_id: ObjectId,
+    exclude_id: Option<ObjectId>,
+    start_time: DateTime<Utc>,
+    end_time: Option<DateTime<Utc>>,
+) -> Result<Vec<TimeEntryOverlap>> {
+    let overlaps = find_overlaps(db, user_id, exclude_id, start_time, end_time).await?;
+
+    if !overlaps.is_empty() && user_settings(db, user_id).await?.reject_overlapping_time_entries {
+        return Err(AppError::Conflict(overlap_message(&overlaps)));
+    }
+
+    Ok(overlaps)
+}
+
+/// Describes overlaps found by [`check_overlaps`], for imports that report
+/// them per row.
+pub fn overlap_message(overlaps: &[TimeEntryOverlap]) -> String {
+    format!(
+        "Time entry overlaps {} other entr{}",
+        overlaps.len(),
+        if overlaps.len() == 1 { "y" } else { "ies" }
+    )
+}
+
+/// Every pair of entries in `entries` whose ranges overlap. `entries` must
+/// be sorted by `start_time`.
+pub fn overlapping_pairs(entries: &[TimeEntry]) -> Vec<OverlappingPair> {
+    let now = Utc::now();
+    let mut pairs = Vec::new();
+
+    for (i, first) in entries.iter().enumerate() {
+        let first_end = effective_end(first.end_time, now);
+        for second in entries[i + 1..].iter().take_while(|e| e.start_time < first_end) {
+            let overlap_end = effective_end(second.end_time, now).min(first_end);
+            if overlap_end <= second.start_time {
+                continue;
+            }
+
+            pairs.push(OverlappingPair {
+                first: first.clone(),
+                second: second.clone(),
+                overlap_start: second.start_time,
+                overlap_end,
+                overlap_seconds: (overlap_end - second.start_time).num_seconds(),
+            });
+        }
+    }
+
+    pairs
+}
+
+#[derive(Debug, Default)]
+pub struct RepairReport {
+    pub checked: u64,
+    pub recomputed: u64,
+    pub swapped: u64,
+    pub cleared: u64,
+}
+
+/// Fixes stored durations that disagree with `start_time`/`end_time`.
+/// Entries whose end precedes the start have the two swapped, and running
+/// entries lose any stale duration. With `dry_run` nothing is written.
+pub async fn repair_durations(db: &Database, dry_run: bool) -> mongodb::error::Result<RepairReport> {
+    let mut report = RepairReport::default();
+    let mut cursor = db.time_entries().find(doc! {}, None).await?;
+
+    while cursor.advance().await? {
+        let entry = cursor.deserialize_current()?;
+        report.checked += 1;
+
+        let update = match entry.end_time {
+            None if entry.duration.is_some() => {
+                report.cleared += 1;
+                doc! { "duration": Bson::Null }
+            }
+            None => continue,
+            Some(end_time) if end_time < entry.start_time => {
+                report.swapped += 1;
+                doc! {
+                    "start_time": end_time,
+                    "end_time": entry.start_time,
+                    "duration": (entry.start_time - end_time).num_seconds(),
+                }
+            }
+            Some(end_time) => {
+                let duration = (end_time - entry.start_time).num_seconds();
+                if entry.duration == Some(duration) {
+                    continue;
+                }
+                report.recomputed += 1;
+                doc! { "duration": duration }
+            }
+        };
+
+        if !dry_run {
+            let mut update = update;
+            update.insert("updated_at", Utc::now());
+            db.time_entries()
+                .update_one(doc! { "_id": entry.id }, doc! { "$set": update }, None)
+                .await?;
+        }
+    }
+
+    Ok(report)
+}
+
+/// Stops all but the newest running timer of each user, as older versions
+/// allowed several at once. A stopped timer ends at its last heartbeat, or
+/// where it started when it never had one, and no later than the start of
+/// the next timer. They are flagged as auto-stopped so the user can review
+/// them. Returns how many were stopped.
+pub async fn stop_duplicate_timers(db: &Database) -> mongodb::error::Result<u64> {
+    let options = FindOptions::builder().sort(doc! { "user_id": 1, "start_time": -1 }).build();
+    let mut cursor = db
+        .time_entries()
+        .find(doc! { "end_time": { "$type": "null" } }, options)
+        .await?;
+
+    let mut stopped = 0;
+    let mut newer: Option<(ObjectId, DateTime<Utc>)> = None;
+    while cursor.advance().await? {
+        let entry: TimeEntry = cursor.deserialize_current()?;
+        let next_start = match newer {
+            Some((user_id, start_time)) if user_id == entry.user_id => start_time,
+            _ => {
+                newer = Some((entry.user_id, entry.start_time));
+                continue;
+            }
+        };
+        newer = Some((entry.user_id, entry.start_time));
+
+        let end_time = entry
+            .last_activity_at
+            .unwrap_or(entry.start_time)
+            .clamp(entry.start_time, next_start.max(entry.start_time));
+        db.time_entries()
+            .update_one(
+                doc! { "_id": entry.id },
+                doc! {
+                    "$set": {
+                        "end_time": end_time,
+                        "duration": (end_time - entry.start_time).num_seconds(),
+                        "auto_stopped": true,
+                        "updated_at": Utc::now(),
+                    }
+                },
+                None,
+            )
+            .await?;
+        stopped += 1;
+    }
+
+    Ok(stopped)
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Duration;
+
+    use super::*;
+
+    fn at(time: &str) -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339(time).unwrap().with_timezone(&Utc)
+    }
+
+    fn entry(start_time: DateTime<Utc>, end_time: Option<DateTime<Utc>>) -> TimeEntry {
+        TimeEntry {
+            id: Some(ObjectId::new()),
+            user_id: ObjectId::new(),
+            project_id: None,
+            task_id: None,
+            tags: Vec::new(),
+            description: String::new(),
+            start_time,
+            end_time,
+            duration: None,
+            is_billable: true,
+            hourly_rate: None,
+            invoice_id: None,
+            locked: false,
+            lock_reason: None,
+            unlocks: Vec::new(),
+            draft: false,
+            external_id: None,
+            last_activity_at: None,
+            auto_stopped: false,
+            approval_status: None,
+            created_at: start_time,
+            updated_at: start_time,
+        }
+    }
+
+    #[test]
+    fn durations() {
+        let start = at("2024-03-01T09:00:00Z");
+        assert_eq!(duration_between(start, at("2024-03-01T10:30:15Z")).unwrap(), 5415);
+        assert_eq!(duration_between(start, start).unwrap(), 0);
+        assert!(duration_between(start, at("2024-03-01T08:59:59Z")).is_err());
+    }
+
+    #[test]
+    fn overlapping_entries_pair_up() {
+        let entries = [
+            entry(at("2024-03-01T09:00:00Z"), Some(at("2024-03-01T11:00:00Z"))),
+            entry(at("2024-03-01T10:00:00Z"), Some(at("2024-03-01T10:30:00Z"))),
+            entry(at("2024-03-01T10:15:00Z"), Some(at("2024-03-01T12:00:00Z"))),
+        ];
+        let pairs = overlapping_pairs(&entries);
+        let seconds: Vec<i64> = pairs.iter().map(|pair| pair.overlap_seconds).collect();
+        assert_eq!(seconds, [30 * 60, 45 * 60, 15 * 60]);
+        assert_eq!(pairs[1].overlap_start, at("2024-03-01T10:15:00Z"));
+        assert_eq!(pairs[1].overlap_end, at("2024-03-01T11:00:00Z"));
+    }
+
+    #[test]
+    fn touching_entries_dont_overlap() {
+        let entries = [
+            entry(at("2024-03-01T09:00:00Z"), Some(at("2024-03-01T10:00:00Z"))),
+            entry(at("2024-03-01T10:00:00Z"), Some(at("2024-03-01T11:00:00Z"))),
+        ];
+        assert!(overlapping_pairs(&entries).is_empty());
+    }
+
+    #[test]
+    fn running_timers_run_until_now() {
+        let now = Utc::now();
+        let entries = [
+            entry(now - Duration::hours(2), None),
+            entry(now - Duration::hours(1), Some(now - Duration::minutes(30))),
+        ];
+        let pairs = overlapping_pairs(&entries);
+        assert_eq!(pairs.len(), 1);
+        assert_eq!(pairs[0].overlap_seconds, 30 * 60);
+
+        // A timer started after the other entry ended overlaps nothing
+        let entries = [
+            entry(now - Duration::hours(2), Some(now - Duration::hours(1))),
+            entry(now - Duration::minutes(10), None),
+        ];
+        assert!(overlapping_pairs(&entries).is_empty());
+    }
+
+    #[test]
+    fn negative_spans_overlap_nothing() {
+        let entries = [
+            entry(at("2024-03-01T09:00:00Z"), Some(at("2024-03-01T12:00:00Z"))),
+            entry(at("2024-03-01T10:00:00Z"), Some(at("2024-03-01T09:30:00Z"))),
+            entry(at("2024-03-01T13:00:00Z"), Some(at("2024-03-01T12:30:00Z"))),
+        ];
+        assert!(overlapping_pairs(&entries).is_empty());
+    }
+}
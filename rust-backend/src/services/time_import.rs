@@ -0,0 +1,590 @@
+use std::collections::{HashMap, HashSet};
+
+use chrono::{DateTime, Duration, NaiveDate, NaiveDateTime, NaiveTime, TimeZone, Utc};
+use chrono_tz::Tz;
+use mongodb::bson::{doc, oid::ObjectId};
+
+use crate::{
+    database::Database,
+    error::{AppError, Result},
+    models::{
+        Client, ImportRow, ImportRowStatus, ImportTimeEntriesRequest, ImportTimeEntriesResponse,
+        Project, ProjectStatus, TimeEntry, TimeImportSource,
+    },
+    services::{
+        calendar::Calendar,
+        client_csv,
+        settings::user_settings,
+        time_entries::{check_overlaps, duration_between, overlap_message},
+    },
+};
+
+/// Column names of the detailed CSV exports, which differ per tool.
+struct Columns {
+    description: &'static str,
+    project: &'static str,
+    client: &'static str,
+    billable: &'static str,
+    start_date: &'static str,
+    start_time: &'static str,
+    end_date: &'static str,
+    end_time: &'static str,
+    duration: &'static str,
+}
+
+const TOGGL: Columns = Columns {
+    description: "description",
+    project: "project",
+    client: "client",
+    billable: "billable",
+    start_date: "start date",
+    start_time: "start time",
+    end_date: "end date",
+    end_time: "end time",
+    duration: "duration",
+};
+
+const CLOCKIFY: Columns = Columns {
+    description: "description",
+    project: "project",
+    client: "client",
+    billable: "billable",
+    start_date: "start date",
+    start_time: "start time",
+    end_date: "end date",
+    end_time: "end time",
+    duration: "duration (h)",
+};
+
+const DATE_FORMATS: [&str; 3] = ["%Y-%m-%d", "%m/%d/%Y", "%d.%m.%Y"];
+const TIME_FORMATS: [&str; 4] = ["%H:%M:%S", "%I:%M:%S %p", "%H:%M", "%I:%M %p"];
+
+struct ParsedRow {
+    description: String,
+    project: Option<String>,
+    client: Option<String>,
+    start_time: DateTime<Utc>,
+    end_time: DateTime<Utc>,
+    is_billable: bool,
+}
+
+/// Looks a record's fields up by (case-insensitive) header name.
+struct Record<'a> {
+    headers: &'a HashMap<String, usize>,
+    record: &'a csv::StringRecord,
+}
+
+impl Record<'_> {
+    fn get(&self, column: &str) -> Option<&str> {
+        self.headers
+            .get(column)
+            .and_then(|&index| self.record.get(index))
+            .map(str::trim)
+            .filter(|value| !value.is_empty())
+    }
+}
+
+fn parse_date(value: &str) -> std::result::Result<NaiveDate, String> {
+    DATE_FORMATS
+        .iter()
+        .find_map(|format| NaiveDate::parse_from_str(value, format).ok())
+        .ok_or_else(|| format!("Unrecognized date '{}'", value))
+}
+
+fn parse_time(value: &str) -> std::result::Result<NaiveTime, String> {
+    TIME_FORMATS
+        .iter()
+        .find_map(|format| NaiveTime::parse_from_str(value, format).ok())
+        .ok_or_else(|| format!("Unrecognized time '{}'", value))
+}
+
+/// Parses `HH:MM:SS` durations; hours may exceed 24.
+fn parse_duration(value: &str) -> std::result::Result<Duration, String> {
+    let parts: Vec<i64> = value
+        .split(':')
+        .map(|part| part.parse::<i64>())
+        .collect::<std::result::Result<_, _>>()
+        .map_err(|_| format!("Unrecognized duration '{}'", value))?;
+
+    match parts.as_slice() {
+        [h, m, s] => Ok(Duration::seconds(h * 3600 + m * 60 + s)),
+        [h, m] => Ok(Duration::seconds(h * 3600 + m * 60)),
+        _ => Err(format!("Unrecognized duration '{}'", value)),
+    }
+}
+
+fn to_utc(date: NaiveDate, time: NaiveTime, tz: Tz) -> std::result::Result<DateTime<Utc>, String> {
+    let local = NaiveDateTime::new(date, time);
+    tz.from_local_datetime(&local)
+        .earliest()
+        .map(|time| time.with_timezone(&Utc))
+        .ok_or_else(|| format!("{} does not exist in {}", local, tz))
+}
+
+fn parse_row(record: &Record, columns: &Columns, tz: Tz) -> std::result::Result<ParsedRow, String> {
+    let start_date = parse_date(record.get(columns.start_date).ok_or("Missing start date")?)?;
+    let start_time = parse_time(record.get(columns.start_time).ok_or("Missing start time")?)?;
+    let start_time = to_utc(start_date, start_time, tz)?;
+
+    let end_time = match (record.get(columns.end_date), record.get(columns.end_time)) {
+        (Some(date), Some(time)) => to_utc(parse_date(date)?, parse_time(time)?, tz)?,
+        (None, Some(time)) => {
+            // Without an end date the entry ends on the start day, or the
+            // next one when it runs past midnight
+            let end_time = to_utc(start_date, parse_time(time)?, tz)?;
+            if end_time < start_time { end_time + Duration::days(1) } else { end_time }
+        }
+        _ => {
+            let duration = record.get(columns.duration).ok_or("Missing end time and duration")?;
+            start_time + parse_duration(duration)?
+        }
+    };
+    duration_between(start_time, end_time).map_err(|_| "End time is before start time".to_string())?;
+
+    let is_billable = record
+        .get(columns.billable)
+        .map(|value| matches!(value.to_lowercase().as_str(), "yes" | "true" | "1"))
+        .unwrap_or(true);
+
+    Ok(ParsedRow {
+        description: record.get(columns.description).unwrap_or_default().to_string(),
+        project: record.get(columns.project).map(str::to_string),
+        client: record.get(columns.client).map(str::to_string),
+        start_time,
+        end_time,
+        is_billable,
+    })
+}
+
+/// Column index by lowercased header name, ignoring a byte order mark.
+fn header_index(headers: &csv::StringRecord, columns: &Columns) -> Result<HashMap<String, usize>> {
+    let headers: HashMap<String, usize> = headers
+        .iter()
+        .enumerate()
+        .map(|(index, name)| (name.trim_start_matches('\u{feff}').trim().to_lowercase(), index))
+        .collect();
+    if !headers.contains_key(columns.start_date) {
+        return Err(AppError::BadRequest(format!(
+            "CSV has no '{}' column; is this a detailed export?",
+            columns.start_date
+        )));
+    }
+
+    Ok(headers)
+}
+
+fn lookup(names: &HashMap<String, Option<ObjectId>>, name: &str) -> Option<Option<ObjectId>> {
+    names.get(&name.to_lowercase()).copied()
+}
+
+type ProjectKey = (Option<ObjectId>, String);
+
+/// The project named `name` under the row's client. Rows without a client
+/// also match a project of some client when no other project has the name.
+fn find_project(
+    projects: &HashMap<ProjectKey, Option<ObjectId>>,
+    client_id: Option<ObjectId>,
+    has_client: bool,
+    name: &str,
+) -> Option<Option<ObjectId>> {
+    let name = name.to_lowercase();
+    if let Some(&id) = projects.get(&(client_id, name.clone())) {
+        return Some(id);
+    }
+    if has_client {
+        return None;
+    }
+
+    let mut matching = projects.iter().filter(|((_, project), _)| *project == name);
+    match (matching.next(), matching.next()) {
+        (Some((_, &id)), None) => Some(id),
+        _ => None,
+    }
+}
+
+/// Imports the rows of a Toggl or Clockify detailed CSV export. Rows whose
+/// start, end and description match an entry that already exists (or an
+/// earlier row of the same file) are skipped as duplicates. With `dry_run` nothing is
+/// written and the response is a preview of what would happen.
+pub async fn import_time_entries(
+    db: &Database,
+    user_id: ObjectId,
+    request: ImportTimeEntriesRequest,
+) -> Result<ImportTimeEntriesResponse> {
+    let settings = user_settings(db, user_id).await?;
+    let tz: Tz = match &request.timezone {
+        Some(name) => name
+            .parse()
+            .map_err(|_| AppError::BadRequest(format!("Unknown timezone '{}'", name)))?,
+        None => Calendar::new(&settings).timezone,
+    };
+    let columns = match request.source {
+        TimeImportSource::Toggl => &TOGGL,
+        TimeImportSource::Clockify => &CLOCKIFY,
+    };
+
+    let mut reader = csv::ReaderBuilder::new()
+        .flexible(true)
+        .from_reader(request.csv.as_bytes());
+    let headers = header_index(
+        reader
+            .headers()
+            .map_err(|err| AppError::BadRequest(format!("Invalid CSV: {}", err)))?,
+        columns,
+    )?;
+
+    // Different clients may each have a project of the same name
+    let mut projects: HashMap<ProjectKey, Option<ObjectId>> = HashMap::new();
+    let mut cursor = db.projects().find(doc! { "user_id": user_id }, None).await?;
+    while cursor.advance().await? {
+        let project: Project = cursor.deserialize_current()?;
+        projects.insert((project.client_id, project.name.to_lowercase()), project.id);
+    }
+    let mut clients: HashMap<String, Option<ObjectId>> = HashMap::new();
+    let mut cursor = db.clients().find(doc! { "user_id": user_id }, None).await?;
+    while cursor.advance().await? {
+        let client: Client = cursor.deserialize_current()?;
+        clients.insert(client.name.to_lowercase(), client.id);
+    }
+
+    let mut response = ImportTimeEntriesResponse {
+        dry_run: request.dry_run,
+        imported: 0,
+        duplicates: 0,
+        errors: 0,
+        created_projects: Vec::new(),
+        created_clients: Vec::new(),
+        rows: Vec::new(),
+    };
+    let mut seen = HashSet::new();
+
+    for (index, record) in reader.records().enumerate() {
+        let line = index + 2; // 1-based, after the header
+        let record = match record {
+            Ok(record) => record,
+            Err(err) => {
+                response.errors += 1;
+                response.rows.push(error_row(line, None, err.to_string()));
+                continue;
+            }
+        };
+        let parsed = match parse_row(&Record { headers: &headers, record: &record }, columns, tz) {
+            Ok(parsed) => parsed,
+            Err(message) => {
+                response.errors += 1;
+                response.rows.push(error_row(line, None, message));
+                continue;
+            }
+        };
+        if settings.closed_until.is_some_and(|closed_until| parsed.start_time < closed_until) {
+            response.errors += 1;
+            let message = "Falls in a closed period".to_string();
+            response.rows.push(error_row(line, Some(&parsed), message));
+            continue;
+        }
+
+        // Names not known yet are created with `create_missing`; in a dry
+        // run they get a placeholder ID and are only reported
+        let client_id = match &parsed.client {
+            Some(name) => match lookup(&clients, name) {
+                Some(id) => id,
+                None if request.create_missing => {
+                    let id = if request.dry_run {
+                        Some(ObjectId::new())
+                    } else {
+                        Some(create_client(db, user_id, name).await?)
+                    };
+                    clients.insert(name.to_lowercase(), id);
+                    response.created_clients.push(name.clone());
+                    id
+                }
+                None => {
+                    response.errors += 1;
+                    let message = format!("Unknown client '{}'", name);
+                    response.rows.push(error_row(line, Some(&parsed), message));
+                    continue;
+                }
+            },
+            None => None,
+        };
+
+        let project_id = match &parsed.project {
+            Some(name) => match find_project(&projects, client_id, parsed.client.is_some(), name) {
+                Some(id) => id,
+                None if request.create_missing => {
+                    let id = if request.dry_run {
+                        Some(ObjectId::new())
+                    } else {
+                        Some(create_project(db, user_id, name, client_id).await?)
+                    };
+                    projects.insert((client_id, name.to_lowercase()), id);
+                    response.created_projects.push(name.clone());
+                    id
+                }
+                None => {
+                    response.errors += 1;
+                    let message = format!("Unknown project '{}'", name);
+                    response.rows.push(error_row(line, Some(&parsed), message));
+                    continue;
+                }
+            },
+            None => None,
+        };
+
+        let duplicate = !seen.insert((parsed.start_time, parsed.end_time, parsed.description.clone()))
+            || db
+                .time_entries()
+                .count_documents(
+                    doc! {
+                        "user_id": user_id,
+                        "start_time": parsed.start_time,
+                        "end_time": parsed.end_time,
+                        "description": &parsed.description,
+                    },
+                    None,
+                )
+                .await?
+                > 0;
+
+        // Imported time follows the same overlap policy as time logged by hand
+        let mut message = None;
+        if !duplicate {
+            match check_overlaps(db, user_id, None, parsed.start_time, Some(parsed.end_time)).await {
+                Ok(overlaps) if overlaps.is_empty() => {}
+                Ok(overlaps) => message = Some(overlap_message(&overlaps)),
+                Err(AppError::Conflict(message)) => {
+                    response.errors += 1;
+                    response.rows.push(error_row(line, Some(&parsed), message));
+                    continue;
+                }
+                Err(err) => return Err(err),
+            }
+        }
+
+        let status = if duplicate {
+            response.duplicates += 1;
+            ImportRowStatus::Duplicate
+        } else {
+            if !request.dry_run {
+                let entry = TimeEntry {
+                    id: None,
+                    user_id,
+                    project_id,
+                    task_id: None,
+                    tags: Vec::new(),
+                    description: parsed.description.clone(),
+                    start_time: parsed.start_time,
+                    end_time: Some(parsed.end_time),
+                    duration: Some((parsed.end_time - parsed.start_time).num_seconds()),
+                    is_billable: parsed.is_billable,
+                    hourly_rate: None,
+                    invoice_id: None,
+                    locked: false,
+                    lock_reason: None,
+                    unlocks: Vec::new(),
+                    draft: false,
+                    external_id: None,
+                    last_activity_at: None,
+                    auto_stopped: false,
+                    approval_status: None,
+                    created_at: Utc::now(),
+                    updated_at: Utc::now(),
+                };
+                db.time_entries().insert_one(&entry, None).await?;
+            }
+            response.imported += 1;
+            ImportRowStatus::Import
+        };
+
+        response.rows.push(ImportRow {
+            line,
+            status,
+            description: parsed.description,
+            project: parsed.project,
+            client: parsed.client,
+            start_time: Some(parsed.start_time),
+            end_time: Some(parsed.end_time),
+            duration: Some((parsed.end_time - parsed.start_time).num_seconds()),
+            message,
+        });
+    }
+
+    Ok(response)
+}
+
+fn error_row(line: usize, parsed: Option<&ParsedRow>, message: String) -> ImportRow {
+    ImportRow {
+        line,
+        status: ImportRowStatus::Error,
+        description: parsed.map(|p| p.description.clone()).unwrap_or_default(),
+        project: parsed.and_then(|p| p.project.clone()),
+        client: parsed.and_then(|p| p.client.clone()),
+        start_time: parsed.map(|p| p.start_time),
+        end_time: parsed.map(|p| p.end_time),
+        duration: parsed.map(|p| (p.end_time - p.start_time).num_seconds()),
+        message: Some(message),
+    }
+}
+
+/// The export has no email for the client. An empty one means none, as for
+/// clients imported from a CSV without emails, so no contact is created.
+async fn create_client(db: &Database, user_id: ObjectId, name: &str) -> Result<ObjectId> {
+    let client = Client {
+        id: None,
+        user_id,
+        name: name.to_string(),
+        email: String::new(),
+        phone: None,
+        company: None,
+        address: None,
+        billing_address: None,
+        country: None,
+        tax_id_type: None,
+        tax_id: None,
+        notes: Some("Created by time entry import".to_string()),
+        retainer_balance: 0.0,
+        time_rounding: None,
+        default_hourly_rate: None,
+        currency: None,
+        archived_at: None,
+        created_at: Utc::now(),
+        updated_at: Utc::now(),
+    };
+
+    client_csv::insert(db, client).await
+}
+
+async fn create_project(
+    db: &Database,
+    user_id: ObjectId,
+    name: &str,
+    client_id: Option<ObjectId>,
+) -> Result<ObjectId> {
+    let project = Project {
+        id: None,
+        user_id,
+        client_id,
+        name: name.to_string(),
+        description: None,
+        status: ProjectStatus::Active,
+        hourly_rate: None,
+        budget: None,
+        start_date: None,
+        end_date: None,
+        time_rounding: None,
+        archived_at: None,
+        created_at: Utc::now(),
+        updated_at: Utc::now(),
+    };
+
+    let result = db.projects().insert_one(&project, None).await?;
+    Ok(result.inserted_id.as_object_id().unwrap())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TOGGL_CSV: &str = "\u{feff}User,Email,Client,Project,Task,Description,Billable,Start date,Start time,End date,End time,Duration,Tags,Amount ()
+Jane Doe,jane@example.com,Acme,Website,,Homepage layout,Yes,2024-03-04,09:15:00,2024-03-04,11:45:30,02:30:30,design,
+Jane Doe,jane@example.com,,Internal,,\"Planning, Q2\",No,2024-03-04,23:30:00,2024-03-05,00:45:00,01:15:00,,
+Jane Doe,jane@example.com,Acme,Website,,Bad date,Yes,2024-13-04,09:00:00,2024-13-04,10:00:00,01:00:00,,
+Jane Doe,jane@example.com,Acme,Website,,Backwards,Yes,2024-03-04,10:00:00,2024-03-04,09:00:00,01:00:00,,
+";
+
+    const CLOCKIFY_CSV: &str = "Project,Client,Description,Task,User,Group,Email,Tags,Billable,Start Date,Start Time,End Date,End Time,Duration (h),Duration (decimal),Billable Rate (USD),Billable Amount (USD)
+Website,Acme,Homepage layout,,Jane Doe,,jane@example.com,,Yes,03/04/2024,09:15:00 AM,03/04/2024,11:45:30 AM,02:30:30,2.51,100.00,250.83
+Website,Acme,Late fix,,Jane Doe,,jane@example.com,,No,03/04/2024,11:30 PM,,,00:45:00,0.75,100.00,0.00
+Website,Acme,No end,,Jane Doe,,jane@example.com,,Yes,03/05/2024,08:00:00 AM,,,26:05:00,26.08,100.00,2608.33
+Website,Acme,Missing start,,Jane Doe,,jane@example.com,,Yes,03/05/2024,,,,01:00:00,1.00,100.00,100.00
+";
+
+    fn at(time: &str) -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339(time).unwrap().with_timezone(&Utc)
+    }
+
+    fn parse(csv: &str, columns: &Columns, tz: Tz) -> Vec<std::result::Result<ParsedRow, String>> {
+        let mut reader = csv::ReaderBuilder::new().flexible(true).from_reader(csv.as_bytes());
+        let headers = header_index(reader.headers().unwrap(), columns).unwrap();
+        reader
+            .records()
+            .map(|record| parse_row(&Record { headers: &headers, record: &record.unwrap() }, columns, tz))
+            .collect()
+    }
+
+    #[test]
+    fn finds_the_columns_of_detailed_exports() {
+        let headers = csv::StringRecord::from(vec!["\u{feff}Start Date", " End Time ", "Duration (h)"]);
+        let index = header_index(&headers, &CLOCKIFY).unwrap();
+        assert_eq!(index.get("start date"), Some(&0));
+        assert_eq!(index.get("end time"), Some(&1));
+        assert_eq!(index.get("duration (h)"), Some(&2));
+
+        // Summary exports only have totals per project
+        let summary = csv::StringRecord::from(vec!["Project", "Client", "Duration"]);
+        assert!(header_index(&summary, &TOGGL).is_err());
+    }
+
+    #[test]
+    fn parses_toggl_rows() {
+        let rows = parse(TOGGL_CSV, &TOGGL, chrono_tz::Europe::Berlin);
+
+        let row = rows[0].as_ref().unwrap();
+        assert_eq!(row.description, "Homepage layout");
+        assert_eq!(row.project.as_deref(), Some("Website"));
+        assert_eq!(row.client.as_deref(), Some("Acme"));
+        assert!(row.is_billable);
+        assert_eq!(row.start_time, at("2024-03-04T08:15:00Z"));
+        assert_eq!(row.end_time, at("2024-03-04T10:45:30Z"));
+
+        let row = rows[1].as_ref().unwrap();
+        assert_eq!(row.description, "Planning, Q2");
+        assert_eq!(row.client, None);
+        assert!(!row.is_billable);
+        assert_eq!(row.end_time - row.start_time, Duration::minutes(75));
+
+        assert_eq!(rows[2].as_ref().err().map(String::as_str), Some("Unrecognized date '2024-13-04'"));
+        assert_eq!(rows[3].as_ref().err().map(String::as_str), Some("End time is before start time"));
+    }
+
+    #[test]
+    fn parses_clockify_rows() {
+        let rows = parse(CLOCKIFY_CSV, &CLOCKIFY, chrono_tz::America::New_York);
+
+        let row = rows[0].as_ref().unwrap();
+        assert_eq!(row.start_time, at("2024-03-04T14:15:00Z"));
+        assert_eq!(row.end_time, at("2024-03-04T16:45:30Z"));
+
+        // Without an end date the end falls on the start day, or the next
+        // one when it would be before the start
+        let row = rows[1].as_ref().unwrap();
+        assert!(!row.is_billable);
+        assert_eq!(row.start_time, at("2024-03-05T04:30:00Z"));
+
+        // Without any end time the duration is used, and it can exceed a day
+        let row = rows[2].as_ref().unwrap();
+        assert_eq!(row.end_time - row.start_time, Duration::seconds(26 * 3600 + 5 * 60));
+
+        assert_eq!(rows[3].as_ref().err().map(String::as_str), Some("Missing start time"));
+    }
+
+    #[test]
+    fn parses_durations_and_times() {
+        assert_eq!(parse_duration("02:30:30"), Ok(Duration::seconds(9030)));
+        assert_eq!(parse_duration("1:30"), Ok(Duration::minutes(90)));
+        assert!(parse_duration("2.51").is_err());
+        assert!(parse_duration("1:2:3:4").is_err());
+
+        assert_eq!(parse_date("04.03.2024").unwrap(), NaiveDate::from_ymd_opt(2024, 3, 4).unwrap());
+        assert_eq!(parse_time("11:45:30 PM").unwrap(), NaiveTime::from_hms_opt(23, 45, 30).unwrap());
+        assert_eq!(parse_time("07:05").unwrap(), NaiveTime::from_hms_opt(7, 5, 0).unwrap());
+        assert!(parse_time("25:00").is_err());
+    }
+
+    #[test]
+    fn skipped_local_times_are_errors() {
+        let date = NaiveDate::from_ymd_opt(2024, 3, 31).unwrap();
+        let time = NaiveTime::from_hms_opt(2, 30, 0).unwrap();
+        assert!(to_utc(date, time, chrono_tz::Europe::Berlin).is_err());
+    }
+}
@@ -0,0 +1,45 @@
+use bson::oid::ObjectId;
+use tokio::sync::broadcast;
+
+use crate::models::{TimeEntry, TimerEvent, TimerEventKind};
+
+/// Fan-out of time entry changes to open timer sockets. Events only reach
+/// sockets connected to the same server process.
+#[derive(Clone)]
+pub struct TimerEvents {
+    sender: broadcast::Sender<TimerEvent>,
+}
+
+impl Default for TimerEvents {
+    fn default() -> Self {
+        let (sender, _) = broadcast::channel(256);
+        TimerEvents { sender }
+    }
+}
+
+impl TimerEvents {
+    pub fn subscribe(&self) -> broadcast::Receiver<TimerEvent> {
+        self.sender.subscribe()
+    }
+
+    pub fn publish(&self, kind: TimerEventKind, entry: &TimeEntry) {
+        // Sending only fails when nobody is listening
+        let _ = self.sender.send(TimerEvent {
+            user_id: entry.user_id,
+            kind,
+            entry_id: entry.id,
+            entry: (kind != TimerEventKind::Deleted).then(|| entry.clone()),
+        });
+    }
+
+    /// Tells the user's sockets to reload their entries after a change to
+    /// more of them than is worth sending one by one.
+    pub fn publish_resync(&self, user_id: ObjectId) {
+        let _ = self.sender.send(TimerEvent {
+            user_id,
+            kind: TimerEventKind::Resync,
+            entry_id: None,
+            entry: None,
+        });
+    }
+}
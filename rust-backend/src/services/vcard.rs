@@ -0,0 +1,426 @@
+//! vCard 4.0 (RFC 6350): exporting client contacts as cards and importing
+//! cards as contacts. vCard shares its line syntax with iCalendar, so the
+//! folding and escaping helpers come from [`ical`].
+//!
+//! On import a card's ORG (or, without one, its FN) names the client,
+//! matched against client names and companies. Cards for a client that
+//! exists are added to it as contacts, unless it is archived; otherwise the
+//! client is created with the card as its primary contact. Version 3.0
+//! cards are read as well.
+
+use std::collections::{HashMap, HashSet};
+
+use chrono::Utc;
+use mongodb::bson::{doc, oid::ObjectId};
+
+use crate::{
+    database::Database,
+    error::{AppError, Result},
+    models::{
+        Client, ClientContact, ClientImportRow, ClientImportStatus, ContactRole, CreateContactRequest,
+        ImportClientsResponse, ImportVcardRequest, PostalAddress,
+    },
+    services::{contacts, ical, tax},
+};
+
+/// Extension property holding a contact's roles, e.g. `billing,legal`
+const ROLES_PROPERTY: &str = "X-ORBIX-ROLES";
+
+// ---------------------------------------------------------------------------
+// Export
+
+/// One card per contact. A client without contacts gets a card made from
+/// its own name, email and phone.
+pub fn render_cards(clients: &[(Client, Vec<ClientContact>)]) -> String {
+    let mut out = String::new();
+    for (client, contacts) in clients {
+        if contacts.is_empty() {
+            if !client.email.is_empty() {
+                render_card(client, &contacts::primary_from(client), &mut out);
+            }
+            continue;
+        }
+        for contact in contacts {
+            render_card(client, contact, &mut out);
+        }
+    }
+    out
+}
+
+fn render_card(client: &Client, contact: &ClientContact, out: &mut String) {
+    let organization = client.company.as_deref().unwrap_or(&client.name);
+    let mut lines = vec![
+        "BEGIN:VCARD".to_string(),
+        "VERSION:4.0".to_string(),
+        "KIND:individual".to_string(),
+        format!("FN:{}", ical::escape(&contact.name)),
+        format!("ORG:{}", ical::escape(organization)),
+    ];
+    if let Some(id) = contact.id {
+        lines.push(format!("UID:urn:orbix:contact:{}", id.to_hex()));
+    }
+    if let Some(title) = &contact.title {
+        lines.push(format!("TITLE:{}", ical::escape(title)));
+    }
+    let preference = if contact.is_primary { ";PREF=1" } else { "" };
+    lines.push(format!("EMAIL;TYPE=work{}:{}", preference, ical::escape(&contact.email)));
+    if let Some(phone) = &contact.phone {
+        lines.push(format!("TEL;TYPE=work:{}", ical::escape(phone)));
+    }
+    if let Some(address) = &client.billing_address {
+        let components = [
+            "",
+            address.line2.as_deref().unwrap_or_default(),
+            &address.line1,
+            &address.city,
+            address.region.as_deref().unwrap_or_default(),
+            address.postal_code.as_deref().unwrap_or_default(),
+            &address.country,
+        ];
+        let value: Vec<String> = components.iter().map(|component| ical::escape(component)).collect();
+        lines.push(format!("ADR;TYPE=work:{}", value.join(";")));
+    }
+    if !contact.roles.is_empty() {
+        let roles: Vec<String> = contact
+            .roles
+            .iter()
+            .filter_map(|role| bson::to_bson(role).ok()?.as_str().map(str::to_string))
+            .collect();
+        lines.push(format!("{}:{}", ROLES_PROPERTY, roles.join(",")));
+    }
+    lines.push(format!("REV:{}", contact.updated_at.format("%Y%m%dT%H%M%SZ")));
+    lines.push("END:VCARD".to_string());
+
+    for line in lines {
+        ical::fold(&line, out);
+    }
+}
+
+/// The clients given with their contacts, primary contact first.
+pub async fn with_contacts(
+    db: &Database,
+    clients: Vec<Client>,
+) -> Result<Vec<(Client, Vec<ClientContact>)>> {
+    let mut cards = Vec::new();
+    for client in clients {
+        let contacts = contacts::list(db, client.user_id, client.id.unwrap()).await?;
+        cards.push((client, contacts));
+    }
+    Ok(cards)
+}
+
+// ---------------------------------------------------------------------------
+// Import
+
+#[derive(Default)]
+struct Card {
+    name: Option<String>,
+    organization: Option<String>,
+    title: Option<String>,
+    email: Option<String>,
+    email_preferred: bool,
+    phone: Option<String>,
+    address: Option<Vec<String>>,
+    note: Option<String>,
+    roles: Vec<ContactRole>,
+}
+
+impl Card {
+    /// The client the card belongs to
+    fn client_name(&self) -> Option<&str> {
+        self.organization.as_deref().or(self.name.as_deref())
+    }
+}
+
+/// Splits a structured value at the semicolons that aren't escaped.
+fn components(value: &str) -> Vec<String> {
+    let mut parts = vec![String::new()];
+    let mut escaped = false;
+    for c in value.chars() {
+        match c {
+            ';' if !escaped => parts.push(String::new()),
+            _ => parts.last_mut().unwrap().push(c),
+        }
+        escaped = c == '\\' && !escaped;
+    }
+    parts.iter().map(|part| ical::unescape(part).trim().to_string()).collect()
+}
+
+fn parse_cards(text: &str) -> Result<Vec<Card>> {
+    let mut cards = Vec::new();
+    let mut current: Option<Card> = None;
+
+    for line in ical::unfold(text) {
+        let Some(property) = ical::parse_property(&line) else { continue };
+        // Apple and Google prefix properties with a group such as `item1.`
+        let name = property.name.rsplit('.').next().unwrap_or_default();
+
+        match (name, current.as_mut()) {
+            ("BEGIN", None) if property.value.eq_ignore_ascii_case("VCARD") => current = Some(Card::default()),
+            ("END", Some(_)) if property.value.eq_ignore_ascii_case("VCARD") => cards.extend(current.take()),
+            ("FN", Some(card)) => card.name = Some(ical::unescape(&property.value)),
+            ("ORG", Some(card)) => {
+                card.organization = components(&property.value).into_iter().find(|part| !part.is_empty())
+            }
+            ("TITLE", Some(card)) => card.title = Some(ical::unescape(&property.value)),
+            ("EMAIL", Some(card)) => {
+                let preferred = property.param("PREF") == Some("1")
+                    || property
+                        .param("TYPE")
+                        .is_some_and(|kinds| kinds.split(',').any(|kind| kind.eq_ignore_ascii_case("pref")));
+                if card.email.is_none() || (preferred && !card.email_preferred) {
+                    card.email = Some(ical::unescape(&property.value));
+                    card.email_preferred = preferred;
+                }
+            }
+            ("TEL", Some(card)) if card.phone.is_none() => {
+                let value = ical::unescape(&property.value);
+                card.phone = Some(value.strip_prefix("tel:").unwrap_or(&value).to_string());
+            }
+            ("ADR", Some(card)) if card.address.is_none() => card.address = Some(components(&property.value)),
+            ("NOTE", Some(card)) => card.note = Some(ical::unescape(&property.value)),
+            (ROLES_PROPERTY, Some(card)) => {
+                card.roles = property
+                    .value
+                    .split(',')
+                    .filter_map(|role| {
+                        serde_json::from_value(serde_json::Value::String(role.trim().to_lowercase())).ok()
+                    })
+                    .collect();
+            }
+            ("BEGIN", Some(_)) => {
+                return Err(AppError::BadRequest("Invalid vCard: nested BEGIN".to_string()))
+            }
+            _ => {}
+        }
+    }
+    if current.is_some() {
+        return Err(AppError::BadRequest("Invalid vCard: missing END:VCARD".to_string()));
+    }
+
+    Ok(cards)
+}
+
+/// ADR components are post office box, extended address, street, locality,
+/// region, postal code and country. The structured billing address needs a
+/// two-letter country code; anything else is kept as free text only.
+fn addresses(components: &[String]) -> (Option<String>, Option<PostalAddress>) {
+    let part = |index: usize| components.get(index).filter(|part| !part.is_empty()).cloned();
+    let text: Vec<String> = [2, 1, 0, 3, 4, 5, 6].iter().filter_map(|&index| part(index)).collect();
+    if text.is_empty() {
+        return (None, None);
+    }
+
+    let structured = match (part(2), part(3), part(6)) {
+        (Some(line1), Some(city), Some(country)) => tax::validate_address(PostalAddress {
+            line1,
+            line2: part(1),
+            city,
+            postal_code: part(5),
+            region: part(4),
+            country,
+        })
+        .ok(),
+        _ => None,
+    };
+
+    (Some(text.join(", ")), structured)
+}
+
+/// Imports every card as a contact. Cards without an email address are
+/// rejected; cards whose email a client or contact already uses are
+/// skipped as duplicates. With `dry_run` nothing is written.
+pub async fn import_cards(
+    db: &Database,
+    user_id: ObjectId,
+    request: ImportVcardRequest,
+) -> Result<ImportClientsResponse> {
+    let cards = parse_cards(&request.vcard)?;
+    let known = contacts::emails_in_use(db, user_id).await?;
+
+    // Exported cards carry the company as ORG when there is one
+    let mut clients: HashMap<String, (Option<ObjectId>, bool)> = HashMap::new();
+    let mut cursor = db.clients().find(doc! { "user_id": user_id }, None).await?;
+    while cursor.advance().await? {
+        let client: Client = cursor.deserialize_current()?;
+        let archived = client.archived_at.is_some();
+        clients.insert(client.name.to_lowercase(), (client.id, archived));
+        if let Some(company) = client.company.filter(|company| !company.trim().is_empty()) {
+            clients.entry(company.to_lowercase()).or_insert((client.id, archived));
+        }
+    }
+
+    let mut response = ImportClientsResponse {
+        dry_run: request.dry_run,
+        imported: 0,
+        duplicates: 0,
+        errors: 0,
+        rows: Vec::new(),
+    };
+    let mut seen = HashSet::new();
+
+    for (index, card) in cards.into_iter().enumerate() {
+        let line = index + 1;
+        let name = card.name.clone().or_else(|| card.client_name().map(str::to_string)).unwrap_or_default();
+        let mut row = ClientImportRow {
+            line,
+            status: ClientImportStatus::Error,
+            name: name.clone(),
+            email: card.email.clone(),
+            client_id: None,
+            message: None,
+        };
+
+        let (Some(email), Some(client_name)) = (card.email.clone(), card.client_name().map(str::to_string)) else {
+            response.errors += 1;
+            row.message = Some("Card needs a name and an email address".to_string());
+            response.rows.push(row);
+            continue;
+        };
+        if let Err(AppError::BadRequest(message)) = contacts::validate_email(&email) {
+            response.errors += 1;
+            row.message = Some(message);
+            response.rows.push(row);
+            continue;
+        }
+        if let Some(existing) = known.get(&email.to_lowercase()) {
+            response.duplicates += 1;
+            row.status = ClientImportStatus::Duplicate;
+            row.client_id = Some(*existing);
+            row.message = Some("A client or contact already uses this email".to_string());
+            response.rows.push(row);
+            continue;
+        }
+        if !seen.insert(email.to_lowercase()) {
+            response.duplicates += 1;
+            row.status = ClientImportStatus::Duplicate;
+            row.message = Some("Email appears on an earlier card".to_string());
+            response.rows.push(row);
+            continue;
+        }
+
+        let existing = clients.get(&client_name.to_lowercase()).copied();
+        if let Some((client_id, true)) = existing {
+            response.errors += 1;
+            row.client_id = client_id;
+            row.message = Some(format!("Client '{}' is archived", client_name));
+            response.rows.push(row);
+            continue;
+        }
+        let existing = existing.map(|(client_id, _)| client_id);
+        row.client_id = match (existing, request.dry_run) {
+            (Some(client_id), true) => client_id,
+            (None, true) => None,
+            (Some(Some(client_id)), false) => {
+                let contact = CreateContactRequest {
+                    name,
+                    email,
+                    phone: card.phone.clone(),
+                    title: card.title.clone(),
+                    roles: card.roles.clone(),
+                    is_primary: false,
+                };
+                contacts::create(db, user_id, client_id, contact).await?;
+                Some(client_id)
+            }
+            (_, false) => {
+                let (address, billing_address) = card.address.as_deref().map(addresses).unwrap_or_default();
+                let client = Client {
+                    id: None,
+                    user_id,
+                    name: client_name.clone(),
+                    email: email.clone(),
+                    phone: card.phone.clone(),
+                    company: card.organization.clone(),
+                    address,
+                    country: billing_address.as_ref().map(|address| address.country.clone()),
+                    billing_address,
+                    tax_id_type: None,
+                    tax_id: None,
+                    notes: card.note.clone(),
+                    retainer_balance: 0.0,
+                    time_rounding: None,
+                    default_hourly_rate: None,
+                    currency: None,
+                    archived_at: None,
+                    created_at: Utc::now(),
+                    updated_at: Utc::now(),
+                };
+                let result = db.clients().insert_one(&client, None).await?;
+                let client = Client { id: result.inserted_id.as_object_id(), ..client };
+                let contact = ClientContact {
+                    name,
+                    title: card.title.clone(),
+                    roles: card.roles.clone(),
+                    ..contacts::primary_from(&client)
+                };
+                db.client_contacts().insert_one(contact, None).await?;
+                client.id
+            }
+        };
+        if existing.is_none() {
+            clients.insert(client_name.to_lowercase(), (row.client_id, false));
+            row.message = Some(format!("New client '{}'", client_name));
+        }
+
+        response.imported += 1;
+        row.status = ClientImportStatus::Import;
+        response.rows.push(row);
+    }
+
+    Ok(response)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn components_split_at_unescaped_semicolons() {
+        let address = components(";;Main St. 1;Berlin;;10115;DE");
+        assert_eq!(address, ["", "", "Main St. 1", "Berlin", "", "10115", "DE"]);
+        assert_eq!(components(r"Smith\; Sons;Sales"), ["Smith; Sons", "Sales"]);
+        assert_eq!(components(r"Back\\;slash"), [r"Back\", "slash"]);
+        assert_eq!(components(" Acme "), ["Acme"]);
+    }
+
+    #[test]
+    fn parses_cards() {
+        let text = "BEGIN:VCARD\r\n\
+            VERSION:3.0\r\n\
+            FN:Jane Doe\r\n\
+            ORG:Acme\\, Inc.;Sales\r\n\
+            item1.EMAIL;TYPE=work:jane@old.example\r\n\
+            EMAIL;TYPE=work,pref:jane@acme.example\r\n\
+            TEL;VALUE=uri:tel:+49 30 1234\r\n\
+            ADR;TYPE=work:;;Main St. 1;Berlin;;10115;DE\r\n\
+            NOTE:Long-standing\r\n  customer\r\n\
+            X-ORBIX-ROLES:billing,unknown\r\n\
+            END:VCARD\r\n\
+            BEGIN:VCARD\r\n\
+            FN:Solo Trader\r\n\
+            END:VCARD\r\n";
+        let cards = parse_cards(text).unwrap();
+        assert_eq!(cards.len(), 2);
+
+        let card = &cards[0];
+        assert_eq!(card.client_name(), Some("Acme, Inc."));
+        assert_eq!(card.email.as_deref(), Some("jane@acme.example"));
+        assert_eq!(card.phone.as_deref(), Some("+49 30 1234"));
+        assert_eq!(card.note.as_deref(), Some("Long-standing customer"));
+        assert_eq!(card.roles, vec![ContactRole::Billing]);
+        let (address, billing_address) = addresses(card.address.as_deref().unwrap());
+        assert_eq!(address.as_deref(), Some("Main St. 1, Berlin, 10115, DE"));
+        assert_eq!(billing_address.map(|address| address.city), Some("Berlin".to_string()));
+
+        assert_eq!(cards[1].client_name(), Some("Solo Trader"));
+        assert_eq!(cards[1].email, None);
+    }
+
+    #[test]
+    fn rejects_malformed_cards() {
+        assert!(parse_cards("BEGIN:VCARD\r\nFN:Jane\r\n").is_err());
+        assert!(parse_cards("BEGIN:VCARD\r\nBEGIN:VCARD\r\nEND:VCARD\r\n").is_err());
+    }
+}